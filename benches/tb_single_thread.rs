@@ -17,6 +17,7 @@
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use daumtils::SliceRef;
+use relbox::codec::Codec;
 use relbox::index::{AttrType, IndexType};
 use relbox::{RelBox, RelationInfo};
 use std::rc::Rc;
@@ -43,6 +44,7 @@ fn test_db() -> Arc<RelBox> {
             unique_domain: true,
             index_type: IndexType::AdaptiveRadixTree,
             codomain_index_type: None,
+            ..Default::default()
         })
         .collect::<Vec<_>>();
 
@@ -50,7 +52,7 @@ fn test_db() -> Arc<RelBox> {
 }
 
 fn from_val(value: i64) -> SliceRef {
-    SliceRef::from_bytes(&value.to_le_bytes()[..])
+    value.encode()
 }
 
 fn load_history() -> Vec<History> {
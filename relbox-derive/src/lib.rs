@@ -0,0 +1,409 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! `#[derive(Relations)]`, for declaring the relations of a `relbox` database as an enum whose
+//! schema is checked at compile time, rather than parsed (and panicked over) at runtime:
+//!
+//! ```ignore
+//! #[derive(Relations)]
+//! pub enum WorldState {
+//!     #[relation(domain = Integer, codomain = String, index = BTree)]
+//!     ObjectName,
+//!     #[relation(domain = Integer, codomain = Integer, secondary_index = Hash)]
+//!     ObjectParent,
+//!     #[relation(domain = Integer, codomain = Bytes, unique_domain = false, validate_types)]
+//!     ObjectVerbs,
+//! }
+//! ```
+//!
+//! Each variant is one relation, whose id is its position in the enum. Its properties are those
+//! of `RelationInfo`:
+//!
+//! * `domain` and `codomain` (required): `AttrType`s.
+//! * `index` (default `Hash`): the `IndexType` of the domain index.
+//! * `secondary_index`: the `IndexType` of a codomain index, if there's to be one.
+//! * `unique_domain` (default `true`) and `validate_types` (default `false`).
+//! * `name` (default the variant's name).
+//!
+//! The derive implements `relbox::Relations` for the enum, and declares a `<Enum>Access` trait,
+//! implemented for `relbox::Transaction`, with an accessor per relation (named for it, in snake
+//! case) returning its `RelVar`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::meta::ParseNestedMeta;
+use syn::{
+    parse_macro_input, Data, DeriveInput, Error, Fields, Ident, LitBool, LitStr, Result, Token,
+    Variant,
+};
+
+// These mirror `relbox::AttrType` and `relbox::IndexType`, which this crate can't depend on.
+const ATTR_TYPES: &[&str] = &["Integer", "UnsignedInteger", "Float", "String", "Bytes"];
+const INDEX_TYPES: &[&str] = &["Hash", "AdaptiveRadixTree", "BTree"];
+/// The attribute types the adaptive radix tree can index: those of a fixed (8 byte) size.
+const FIXED_SIZE_TYPES: &[&str] = &["Integer", "UnsignedInteger", "Float"];
+
+#[proc_macro_derive(Relations, attributes(relation))]
+pub fn derive_relations(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// A relation, as declared on its variant.
+struct Relation {
+    variant: Ident,
+    name: String,
+    domain: Ident,
+    codomain: Ident,
+    index: Ident,
+    secondary_index: Option<Ident>,
+    unique_domain: bool,
+    validate_types: bool,
+}
+
+impl Relation {
+    fn parse(variant: &Variant) -> Result<Self> {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(Error::new_spanned(
+                &variant.fields,
+                "relations must be unit variants",
+            ));
+        }
+        if let Some((_, discriminant)) = &variant.discriminant {
+            return Err(Error::new_spanned(
+                discriminant,
+                "relation ids are the positions of their variants, and can't be given explicitly",
+            ));
+        }
+
+        let mut name = variant.ident.to_string();
+        let mut domain = None;
+        let mut codomain = None;
+        let mut index = None;
+        let mut secondary_index = None;
+        let mut unique_domain = true;
+        let mut validate_types = false;
+        let mut declared = false;
+        for attr in variant
+            .attrs
+            .iter()
+            .filter(|a| a.path().is_ident("relation"))
+        {
+            declared = true;
+            attr.parse_nested_meta(|meta| {
+                let Some(property) = meta.path.get_ident() else {
+                    return Err(meta.error("expected a relation property"));
+                };
+                match property.to_string().as_str() {
+                    "name" => name = meta.value()?.parse::<LitStr>()?.value(),
+                    "domain" => domain = Some(one_of(meta.value()?.parse()?, ATTR_TYPES)?),
+                    "codomain" => codomain = Some(one_of(meta.value()?.parse()?, ATTR_TYPES)?),
+                    "index" => index = Some(one_of(meta.value()?.parse()?, INDEX_TYPES)?),
+                    "secondary_index" => {
+                        secondary_index = Some(one_of(meta.value()?.parse()?, INDEX_TYPES)?)
+                    }
+                    "unique_domain" => unique_domain = flag(&meta)?,
+                    "validate_types" => validate_types = flag(&meta)?,
+                    _ => {
+                        return Err(meta.error(
+                            "unknown relation property; expected one of name, domain, codomain, \
+                             index, secondary_index, unique_domain or validate_types",
+                        ))
+                    }
+                }
+                Ok(())
+            })?;
+        }
+        if !declared {
+            return Err(Error::new_spanned(
+                &variant.ident,
+                "relations must be declared with #[relation(domain = ..., codomain = ...)]",
+            ));
+        }
+        let domain = domain
+            .ok_or_else(|| Error::new_spanned(&variant.ident, "relation has no domain type"))?;
+        let codomain = codomain
+            .ok_or_else(|| Error::new_spanned(&variant.ident, "relation has no codomain type"))?;
+        let index = index.unwrap_or_else(|| format_ident!("Hash"));
+        check_indexable(&index, &domain)?;
+        if let Some(secondary_index) = &secondary_index {
+            check_indexable(secondary_index, &codomain)?;
+        }
+
+        Ok(Self {
+            variant: variant.ident.clone(),
+            name,
+            domain,
+            codomain,
+            index,
+            secondary_index,
+            unique_domain,
+            validate_types,
+        })
+    }
+
+    /// The `RelationInfo` expression for the relation.
+    fn info(&self) -> TokenStream2 {
+        let Self {
+            name,
+            domain,
+            codomain,
+            index,
+            unique_domain,
+            validate_types,
+            ..
+        } = self;
+        let secondary_indexed = self.secondary_index.is_some();
+        let codomain_index_type = match &self.secondary_index {
+            Some(index) => quote!(::std::option::Option::Some(::relbox::IndexType::#index)),
+            None => quote!(::std::option::Option::None),
+        };
+        quote! {
+            ::relbox::RelationInfo {
+                name: ::std::string::ToString::to_string(#name),
+                domain_type: ::relbox::AttrType::#domain,
+                codomain_type: ::relbox::AttrType::#codomain,
+                secondary_indexed: #secondary_indexed,
+                unique_domain: #unique_domain,
+                index_type: ::relbox::IndexType::#index,
+                codomain_index_type: #codomain_index_type,
+                validate_types: #validate_types,
+                ..::std::default::Default::default()
+            }
+        }
+    }
+
+    /// The name of the relation's accessor.
+    fn accessor(&self) -> Ident {
+        let name = snake_case(&self.variant.to_string());
+        // Keywords (`Type` -> `type`) have to be raw.
+        if syn::parse_str::<Ident>(&name).is_ok() {
+            Ident::new(&name, self.variant.span())
+        } else {
+            Ident::new_raw(&name, self.variant.span())
+        }
+    }
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "Relations can only be derived for enums",
+        ));
+    };
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "Relations can't be derived for generic enums",
+        ));
+    }
+    let relations = data
+        .variants
+        .iter()
+        .map(Relation::parse)
+        .collect::<Result<Vec<_>>>()?;
+
+    let ident = &input.ident;
+    let vis = &input.vis;
+    let access = format_ident!("{}Access", ident);
+    let variants: Vec<_> = relations.iter().map(|r| &r.variant).collect();
+    let ids = 0..relations.len();
+    let infos = relations.iter().map(Relation::info);
+    let accessors: Vec<_> = relations.iter().map(Relation::accessor).collect();
+    let accessor_docs = relations
+        .iter()
+        .map(|r| format!("The `{}` relation.", r.name));
+    let access_doc = format!("Accessors for the relations of [`{ident}`].");
+
+    Ok(quote! {
+        impl ::relbox::Relations for #ident {
+            fn relations() -> ::std::vec::Vec<::relbox::RelationInfo> {
+                ::std::vec![#(#infos),*]
+            }
+
+            fn relation_id(&self) -> ::relbox::RelationId {
+                match *self {
+                    #(#ident::#variants => ::relbox::RelationId(#ids),)*
+                }
+            }
+        }
+
+        #[doc = #access_doc]
+        #vis trait #access {
+            #(
+                #[doc = #accessor_docs]
+                fn #accessors(&self) -> ::relbox::RelVar<'_>;
+            )*
+        }
+
+        impl #access for ::relbox::Transaction {
+            #(
+                fn #accessors(&self) -> ::relbox::RelVar<'_> {
+                    self.relation(::relbox::Relations::relation_id(&#ident::#variants))
+                }
+            )*
+        }
+    })
+}
+
+/// `ident`, if it's one of `valid`.
+fn one_of(ident: Ident, valid: &[&str]) -> Result<Ident> {
+    if valid.iter().any(|v| ident == v) {
+        return Ok(ident);
+    }
+    Err(Error::new_spanned(
+        &ident,
+        format!(
+            "unknown type `{ident}`; expected one of {}",
+            valid.join(", ")
+        ),
+    ))
+}
+
+/// A boolean property, which is true if given without a value.
+fn flag(meta: &ParseNestedMeta) -> Result<bool> {
+    if !meta.input.peek(Token![=]) {
+        return Ok(true);
+    }
+    Ok(meta.value()?.parse::<LitBool>()?.value)
+}
+
+/// Check that an `index` can index values of `attr_type`.
+fn check_indexable(index: &Ident, attr_type: &Ident) -> Result<()> {
+    if index == "AdaptiveRadixTree" && !FIXED_SIZE_TYPES.iter().any(|t| attr_type == t) {
+        return Err(Error::new_spanned(
+            index,
+            format!(
+                "an AdaptiveRadixTree index needs fixed-size keys ({}), not {attr_type}",
+                FIXED_SIZE_TYPES.join(", ")
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        if i > 0 && c.is_uppercase() {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            // A word starts after a lowercase letter or digit, or at the last capital of an
+            // acronym (`HTTPServer` -> `http_server`).
+            if prev.is_lowercase()
+                || prev.is_ascii_digit()
+                || (prev.is_uppercase() && next_is_lower)
+            {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse_quote;
+
+    use crate::{expand, snake_case};
+
+    fn error_of(input: syn::DeriveInput) -> String {
+        expand(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn accessor_names() {
+        assert_eq!(snake_case("ObjectName"), "object_name");
+        assert_eq!(snake_case("Object2Name"), "object2_name");
+        assert_eq!(snake_case("HTTPServer"), "http_server");
+        assert_eq!(snake_case("Type"), "type");
+    }
+
+    #[test]
+    fn expands_relations() {
+        let expanded = expand(parse_quote! {
+            enum WorldState {
+                #[relation(domain = Integer, codomain = String, index = AdaptiveRadixTree)]
+                ObjectName,
+                #[relation(name = "parents", domain = Integer, codomain = Integer)]
+                #[relation(secondary_index = BTree, unique_domain = false, validate_types)]
+                ObjectParent,
+                #[relation(domain = Bytes, codomain = Bytes)]
+                Type,
+            }
+        })
+        .unwrap()
+        .to_string();
+        assert!(expanded.contains("trait WorldStateAccess"));
+        assert!(expanded.contains("fn object_name"));
+        assert!(expanded.contains("fn r#type"));
+        assert!(expanded.contains("\"parents\""));
+        assert!(expanded.contains("RelationId (1usize)"));
+    }
+
+    #[test]
+    fn rejects_bad_declarations() {
+        assert!(error_of(parse_quote! {
+            enum E {
+                #[relation(domain = Int, codomain = String)]
+                A,
+            }
+        })
+        .starts_with("unknown type `Int`"));
+        assert!(error_of(parse_quote! {
+            enum E {
+                #[relation(domain = Integer, codomain = String, index = Btree)]
+                A,
+            }
+        })
+        .starts_with("unknown type `Btree`"));
+        assert!(error_of(parse_quote! {
+            enum E {
+                #[relation(domain = Integer, codomain = String, secondary_index = AdaptiveRadixTree)]
+                A,
+            }
+        })
+        .starts_with("an AdaptiveRadixTree index needs fixed-size keys"));
+        assert!(error_of(parse_quote! {
+            enum E {
+                #[relation(domain = Integer)]
+                A,
+            }
+        })
+        .contains("no codomain type"));
+        assert!(error_of(parse_quote! {
+            enum E {
+                #[relation(domain = Integer, codomain = String, unique = false)]
+                A,
+            }
+        })
+        .starts_with("unknown relation property"));
+        assert!(error_of(parse_quote! {
+            enum E {
+                A,
+            }
+        })
+        .starts_with("relations must be declared"));
+        assert!(error_of(parse_quote! {
+            struct E;
+        })
+        .contains("only be derived for enums"));
+    }
+}
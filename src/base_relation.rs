@@ -13,11 +13,12 @@
 //
 
 use std::collections::HashSet;
+use std::ops::Bound;
 use tracing::error;
 
 use daumtils::SliceRef;
 
-use crate::index::{pick_base_index, Index};
+use crate::index::{pick_attribute_indexes, pick_base_index, Index};
 use crate::tuples::{TupleId, TupleRef};
 use crate::{RelationError, RelationId, RelationInfo};
 
@@ -26,10 +27,9 @@ use crate::{RelationError, RelationId, RelationInfo};
 ///
 /// In this layer  we do not really differentiate the Domain & Codomain type; they are
 /// stored and managed as ref-counted byte-arrays and it is up to layers above & below to interpret the the values
-/// correctly.
+/// correctly. (Relations which ask for their values to be type-checked have them checked by the
+/// transaction, before they get here.)
 ///
-// TODO: Add some kind of 'type' flag to the relation & tuple values,
-//   so that we can do type-checking on the values, though for our purposes this may be overkill at this time.
 // TODO: Indexes should be paged.
 #[derive(Clone)]
 pub struct BaseRelation {
@@ -47,6 +47,8 @@ pub struct BaseRelation {
     domain_index: Box<dyn Index + Send + Sync>,
     /// Codomain -> TupleIds
     codomain_index: Option<Box<dyn Index + Send + Sync>>,
+    /// Attribute(s) -> TupleIds, one per declared attribute index.
+    attribute_indexes: Vec<Box<dyn Index + Send + Sync>>,
 }
 
 impl Clone for Box<dyn Index + Send + Sync> {
@@ -58,37 +60,65 @@ impl Clone for Box<dyn Index + Send + Sync> {
 impl BaseRelation {
     pub(crate) fn new(id: RelationId, relation_info: RelationInfo, timestamp: u64) -> Self {
         let (domain_index, codomain_index) = pick_base_index(&relation_info);
+        let attribute_indexes = pick_attribute_indexes(&relation_info);
         Self {
             id,
             ts: timestamp,
             tuples: Default::default(),
             domain_index,
             codomain_index,
+            attribute_indexes,
             info: relation_info,
         }
     }
 
+    /// Add the tuple to each of the attribute indexes.
+    fn index_attributes(&mut self, tuple: &TupleRef) -> Result<(), RelationError> {
+        for (i, index) in self.attribute_indexes.iter_mut().enumerate() {
+            let key = self.info.attribute_index_key(i, tuple)?;
+            index.index_tuple(&key, tuple.id())?;
+        }
+        Ok(())
+    }
+
+    /// Remove the tuple from each of the attribute indexes.
+    fn unindex_attributes(&mut self, tuple: &TupleRef) -> Result<(), RelationError> {
+        for (i, index) in self.attribute_indexes.iter_mut().enumerate() {
+            let key = self.info.attribute_index_key(i, tuple)?;
+            index.unindex_tuple(&key, tuple.id())?;
+        }
+        Ok(())
+    }
+
     /// Establish indexes & storage for a tuple initial-loaded from secondary storage. Basically a, "trust us,
-    /// this exists" move.
-    pub(crate) fn load_tuple(&mut self, mut tuple: TupleRef) {
+    /// this exists" move. A tuple which can't be indexed (e.g. because the relation's indexes have
+    /// been redeclared in a way that doesn't suit it) fails the load.
+    pub(crate) fn load_tuple(&mut self, mut tuple: TupleRef) -> Result<(), RelationError> {
         // Reset timestamp to 0, since this is a tuple initial-loaded from secondary storage.
         tuple.update_timestamp(0);
 
         let d_result = self.domain_index.index_tuple(&tuple.domain(), tuple.id());
         if let Err(e) = d_result {
             error!(tuple = ?tuple, relation = ?self.info.name, ?e, "Domain indexing failed on load");
-            return;
+            return Err(e);
         }
 
         if let Some(codomain_index) = &mut self.codomain_index {
             let c_result = codomain_index.index_tuple(&tuple.codomain(), tuple.id());
             if let Err(e) = c_result {
                 error!(tuple = ?tuple, relation = ?self.info.name, ?e, "Codomain indexing failed on load");
+                return Err(e);
             }
         }
 
+        if let Err(e) = self.index_attributes(&tuple) {
+            error!(tuple = ?tuple, relation = ?self.info.name, ?e, "Attribute indexing failed on load");
+            return Err(e);
+        }
+
         // Add the tuple to the relation.
         self.tuples.insert(tuple.id(), tuple);
+        Ok(())
     }
 
     /// Check for a specific tuple by its id.
@@ -113,6 +143,26 @@ impl BaseRelation {
             .collect())
     }
 
+    /// Seek for all tuples whose domain falls within the given bounds, in domain order (or reverse
+    /// domain order). Requires an ordered domain index.
+    pub fn seek_range_by_domain(
+        &self,
+        lower: Bound<&SliceRef>,
+        upper: Bound<&SliceRef>,
+        reverse: bool,
+    ) -> Result<Vec<TupleRef>, RelationError> {
+        Ok(self
+            .domain_index
+            .seek_range(lower, upper, reverse)?
+            .map(|id| {
+                self.tuples
+                    .get(&id)
+                    .expect("missing tuple for indexed id")
+                    .clone()
+            })
+            .collect())
+    }
+
     pub fn seek_by_codomain(&self, codomain: SliceRef) -> Result<HashSet<TupleRef>, RelationError> {
         Ok(self
             .codomain_index
@@ -123,10 +173,49 @@ impl BaseRelation {
             .collect())
     }
 
+    /// Seek for all tuples whose attributes in the given attribute index match `key` (their packed
+    /// values, if there's more than one).
+    pub fn seek_by_attributes(
+        &self,
+        index: usize,
+        key: SliceRef,
+    ) -> Result<HashSet<TupleRef>, RelationError> {
+        Ok(self.attribute_indexes[index]
+            .seek(&key)?
+            .map(|id| {
+                self.tuples
+                    .get(&id)
+                    .expect("missing tuple for indexed id")
+                    .clone()
+            })
+            .collect())
+    }
+
     pub fn predicate_scan<F: Fn(&TupleRef) -> bool>(&self, f: &F) -> HashSet<TupleRef> {
         self.tuples.values().filter(|t| f(t)).cloned().collect()
     }
 
+    /// An iterator over a snapshot of all tuples in the relation. The snapshot is a cheap clone of
+    /// the (persistent) tuple map, so it doesn't hold the canonical lock while being consumed.
+    pub fn scan_iter(&self) -> Box<dyn Iterator<Item = TupleRef>> {
+        Box::new(self.tuples.clone().into_iter().map(|(_, t)| t))
+    }
+
+    /// As `scan_iter`, but only over the tuples matching the given domain.
+    pub fn seek_iter(
+        &self,
+        domain: &SliceRef,
+    ) -> Result<Box<dyn Iterator<Item = TupleRef>>, RelationError> {
+        let ids: Vec<_> = self.domain_index.seek(domain)?.collect();
+        let tuples = self.tuples.clone();
+        Ok(Box::new(ids.into_iter().map(move |id| {
+            tuples
+                .get(&id)
+                .expect("missing tuple for indexed id")
+                .clone()
+        })))
+    }
+
     /// Remove a specific tuple from the relation, and update indexes accordingly.
     pub(crate) fn remove_tuple(&mut self, tuple: &TupleId) -> Result<(), RelationError> {
         let Some(tuple_ref) = self.tuples.remove(tuple) else {
@@ -138,6 +227,7 @@ impl BaseRelation {
         if let Some(codomain_index) = &mut self.codomain_index {
             codomain_index.unindex_tuple(&tuple_ref.codomain(), tuple_ref.id())?;
         }
+        self.unindex_attributes(&tuple_ref)?;
         Ok(())
     }
 
@@ -157,6 +247,7 @@ impl BaseRelation {
         if let Some(codomain_index) = &mut self.codomain_index {
             codomain_index.index_tuple(&tuple_ref.codomain(), tuple_ref.id())?;
         }
+        self.index_attributes(&tuple_ref)?;
         self.tuples.insert(tuple_ref.id(), tuple_ref.clone());
 
         Ok(())
@@ -177,12 +268,14 @@ impl BaseRelation {
         if let Some(codomain_index) = &mut self.codomain_index {
             codomain_index.unindex_tuple(&old_tref.codomain(), old_tref.id())?;
         }
+        self.unindex_attributes(&old_tref)?;
 
         // Add the new tuple to the domain index
         self.domain_index.index_tuple(&tuple.domain(), tuple.id())?;
         if let Some(codomain_index) = &mut self.codomain_index {
             codomain_index.index_tuple(&tuple.codomain(), tuple.id())?;
         }
+        self.index_attributes(&tuple)?;
         self.tuples.insert(tuple.id(), tuple.clone());
 
         Ok(())
@@ -0,0 +1,237 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Encoding of typed values into byte strings which compare (as bytes) in the same order as the
+//! values themselves, so that ordered indexes order them correctly:
+//!
+//! * Integers are big-endian, with the sign bit of signed ones flipped, so negatives sort first.
+//! * Floats are big-endian, with the sign bit flipped for positives and every bit flipped for
+//!   negatives, which gives IEEE 754's total order (`-NaN < -inf < ... < -0 < 0 < ... < inf < NaN`).
+//! * Strings and byte arrays are escaped and terminated, so that a value sorts before anything it
+//!   is a prefix of, even when packed together with further attributes.
+
+use daumtils::SliceRef;
+
+use crate::index::AttrType;
+use crate::tuples::attributes;
+use crate::RelationError;
+
+/// A type with an order-preserving encoding, for one of the `AttrType`s.
+pub trait Codec: Sized {
+    /// The attribute type values of this type are stored as.
+    const ATTR_TYPE: AttrType;
+
+    fn encode(&self) -> SliceRef;
+
+    fn decode(encoded: &SliceRef) -> Result<Self, RelationError>;
+}
+
+const SIGN_BIT: u64 = 1 << 63;
+
+fn fixed(encoded: &SliceRef) -> Result<u64, RelationError> {
+    let bytes = encoded
+        .as_slice()
+        .try_into()
+        .map_err(|_| RelationError::BadKey)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+impl Codec for i64 {
+    const ATTR_TYPE: AttrType = AttrType::Integer;
+
+    fn encode(&self) -> SliceRef {
+        SliceRef::from_bytes(&(*self as u64 ^ SIGN_BIT).to_be_bytes())
+    }
+
+    fn decode(encoded: &SliceRef) -> Result<Self, RelationError> {
+        Ok((fixed(encoded)? ^ SIGN_BIT) as i64)
+    }
+}
+
+impl Codec for u64 {
+    const ATTR_TYPE: AttrType = AttrType::UnsignedInteger;
+
+    fn encode(&self) -> SliceRef {
+        SliceRef::from_bytes(&self.to_be_bytes())
+    }
+
+    fn decode(encoded: &SliceRef) -> Result<Self, RelationError> {
+        fixed(encoded)
+    }
+}
+
+impl Codec for f64 {
+    const ATTR_TYPE: AttrType = AttrType::Float;
+
+    fn encode(&self) -> SliceRef {
+        let bits = self.to_bits();
+        let ordered = if bits & SIGN_BIT != 0 {
+            !bits
+        } else {
+            bits ^ SIGN_BIT
+        };
+        SliceRef::from_bytes(&ordered.to_be_bytes())
+    }
+
+    fn decode(encoded: &SliceRef) -> Result<Self, RelationError> {
+        let ordered = fixed(encoded)?;
+        let bits = if ordered & SIGN_BIT != 0 {
+            ordered ^ SIGN_BIT
+        } else {
+            !ordered
+        };
+        Ok(f64::from_bits(bits))
+    }
+}
+
+impl Codec for String {
+    const ATTR_TYPE: AttrType = AttrType::String;
+
+    fn encode(&self) -> SliceRef {
+        encode_str(self)
+    }
+
+    fn decode(encoded: &SliceRef) -> Result<Self, RelationError> {
+        let bytes = <Vec<u8>>::decode(encoded)?;
+        String::from_utf8(bytes).map_err(|_| RelationError::BadKey)
+    }
+}
+
+impl Codec for Vec<u8> {
+    const ATTR_TYPE: AttrType = AttrType::Bytes;
+
+    fn encode(&self) -> SliceRef {
+        encode_bytes(self)
+    }
+
+    fn decode(encoded: &SliceRef) -> Result<Self, RelationError> {
+        match &attributes::unpack_prefix(encoded)?[..] {
+            [value] => Ok(value.as_slice().to_vec()),
+            _ => Err(RelationError::BadKey),
+        }
+    }
+}
+
+/// Encode a string, without needing to own it.
+pub fn encode_str(value: &str) -> SliceRef {
+    encode_bytes(value.as_bytes())
+}
+
+/// Encode a byte array, without needing to own it.
+pub fn encode_bytes(value: &[u8]) -> SliceRef {
+    SliceRef::from_vec(attributes::pack_prefix(&[SliceRef::from_bytes(value)]))
+}
+
+/// Encode a list of values, as merged by `MergeOperator::Append` and `MergeOperator::Union`.
+pub fn encode_list(values: &[SliceRef]) -> SliceRef {
+    SliceRef::from_vec(attributes::pack_prefix(values))
+}
+
+/// Decode a list encoded by `encode_list`.
+pub fn decode_list(encoded: &SliceRef) -> Result<Vec<SliceRef>, RelationError> {
+    attributes::unpack_prefix(encoded)
+}
+
+/// Check that `value` is a valid encoding of an `attr_type`. Strings may be plain UTF-8 as well as
+/// encoded, for relations which don't need them ordered.
+pub(crate) fn validate(attr_type: AttrType, value: &SliceRef) -> Result<(), RelationError> {
+    let valid = match attr_type {
+        AttrType::Integer | AttrType::UnsignedInteger | AttrType::Float => {
+            value.as_slice().len() == 8
+        }
+        AttrType::String => {
+            std::str::from_utf8(value.as_slice()).is_ok() || String::decode(value).is_ok()
+        }
+        AttrType::Bytes => true,
+    };
+    if !valid {
+        return Err(RelationError::TypeMismatch(attr_type));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use daumtils::SliceRef;
+
+    use crate::codec::{encode_str, Codec};
+    use crate::RelationError;
+
+    /// Check that `values` (which are in ascending order) encode in ascending order, and decode
+    /// back to themselves.
+    fn check_ordered<T: Codec + PartialEq + Debug>(values: &[T]) {
+        for pair in values.windows(2) {
+            assert!(
+                pair[0].encode().as_slice() < pair[1].encode().as_slice(),
+                "{:?} should encode below {:?}",
+                pair[0],
+                pair[1]
+            );
+        }
+        for value in values {
+            assert_eq!(&T::decode(&value.encode()).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn integers_are_ordered() {
+        check_ordered(&[i64::MIN, -256, -1, 0, 1, 255, 256, i64::MAX]);
+        check_ordered(&[0u64, 1, 255, 256, u64::MAX]);
+        assert_eq!(
+            i64::decode(&SliceRef::from_bytes(b"short")),
+            Err(RelationError::BadKey)
+        );
+    }
+
+    #[test]
+    fn floats_are_ordered() {
+        check_ordered(&[
+            f64::NEG_INFINITY,
+            f64::MIN,
+            -1.5,
+            -f64::MIN_POSITIVE,
+            -0.0,
+            0.0,
+            f64::MIN_POSITIVE,
+            1.0,
+            1.5,
+            f64::MAX,
+            f64::INFINITY,
+        ]);
+        assert!(f64::decode(&f64::NAN.encode()).unwrap().is_nan());
+    }
+
+    #[test]
+    fn strings_are_ordered() {
+        check_ordered(&[
+            String::new(),
+            "\0".to_string(),
+            "a".to_string(),
+            "a\0".to_string(),
+            "ab".to_string(),
+            "b".to_string(),
+        ]);
+        check_ordered(&[
+            vec![],
+            vec![0u8],
+            vec![0, 0],
+            vec![0, 1],
+            vec![1],
+            vec![0xff],
+        ]);
+        assert_eq!(encode_str("abc"), "abc".to_string().encode());
+    }
+}
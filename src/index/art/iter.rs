@@ -12,6 +12,8 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+use std::cmp::Ordering;
+
 use super::{node::Node, KeyTrait, Partial};
 
 type IterEntry<'a, P, V> = (u8, &'a Node<P, V>);
@@ -27,6 +29,9 @@ struct IterInner<'a, K: KeyTrait<PartialType = P>, P: Partial + Clone + 'a, V: C
 
     // Pushed and popped with prefix portions as we descend the tree,
     cur_key: K,
+
+    // Whether we're going in descending key order.
+    reverse: bool,
 }
 
 impl<'a, K: KeyTrait<PartialType = P>, P: Partial + Clone + 'a, V: Clone> IterInner<'a, K, P, V> {
@@ -39,8 +44,101 @@ impl<'a, K: KeyTrait<PartialType = P>, P: Partial + Clone + 'a, V: Clone> IterIn
         Self {
             node_iter_stack,
             cur_key: K::new_from_partial(&node.prefix),
+            reverse: false,
+        }
+    }
+
+    /// Start at `bound` (the first key at or after it, or if `reverse` the last at or before it),
+    /// by descending straight to where it is or would be. Along the way, the nodes on the far
+    /// side of it are stacked up to be iterated once we're done with what's under it.
+    fn seek(root: &'a Node<P, V>, bound: Option<&K>, reverse: bool) -> Self {
+        let mut iter = Self {
+            node_iter_stack: vec![],
+            cur_key: K::new_from_slice(&[]),
+            reverse,
+        };
+        let Some(bound) = bound else {
+            iter.node_iter_stack
+                .push((0, Box::new(std::iter::once((0, root)))));
+            return iter;
+        };
+
+        // `node` is the one whose prefix starts at `depth`, which all before it matched the bound.
+        let before_bound = if reverse {
+            Ordering::Greater
+        } else {
+            Ordering::Less
+        };
+        let mut node = root;
+        let mut depth = 0;
+        loop {
+            match compare_prefix(&node.prefix, bound, depth) {
+                ordering if ordering == before_bound => return iter,
+                Ordering::Equal if node.is_inner() => {}
+                // Everything under it is within the bound.
+                _ => {
+                    iter.node_iter_stack
+                        .push((depth, Box::new(std::iter::once((0, node)))));
+                    return iter;
+                }
+            }
+            iter.cur_key = iter.cur_key.extend_from_partial(&node.prefix);
+            depth += node.prefix.len();
+
+            // The keys under here are longer than the bound, so follow it going forwards, and
+            // precede it going backwards.
+            if bound.length_at(depth) == 0 {
+                if !reverse {
+                    let children = iter.children(node);
+                    iter.node_iter_stack.push((depth, children));
+                }
+                return iter;
+            }
+
+            // The children beyond the bound's next byte come after whatever's under the child for
+            // it.
+            let next = bound.at(depth);
+            let beyond: Box<NodeIterator<'a, P, V>> = if reverse {
+                Box::new(iter.children(node).skip_while(move |(k, _)| *k >= next))
+            } else {
+                Box::new(iter.children(node).skip_while(move |(k, _)| *k <= next))
+            };
+            iter.node_iter_stack.push((depth, beyond));
+            match node.seek_child(next) {
+                Some(child) => node = child,
+                None => return iter,
+            }
         }
     }
+
+    /// The children of `node`, in the order we're going in.
+    fn children(&self, node: &'a Node<P, V>) -> Box<NodeIterator<'a, P, V>> {
+        if self.reverse {
+            Box::new(node.iter().collect::<Vec<_>>().into_iter().rev())
+        } else {
+            node.iter()
+        }
+    }
+}
+
+/// How `prefix` compares with the same stretch of `key`, from `depth`. (A prefix running past the
+/// end of the key is after it, as the longer keys under it are.)
+fn compare_prefix<K: KeyTrait<PartialType = P>, P: Partial>(
+    prefix: &P,
+    key: &K,
+    depth: usize,
+) -> Ordering {
+    let key_len = key.length_at(depth);
+    for i in 0..prefix.len() {
+        if i >= key_len {
+            return Ordering::Greater;
+        }
+        match prefix.at(i).cmp(&key.at(depth + i)) {
+            Ordering::Equal => continue,
+            ordering => return ordering,
+        }
+    }
+    Ordering::Equal
 }
 
 impl<'a, K: KeyTrait<PartialType = P> + 'a, P: Partial + Clone + 'a, V: Clone> Iter<'a, K, P, V> {
@@ -69,6 +167,18 @@ impl<'a, K: KeyTrait<PartialType = P> + 'a, P: Partial + Clone + 'a, V: Clone> I
             _marker: Default::default(),
         }
     }
+
+    /// Iterate from the first key at or after `bound`, or if `reverse`, backwards from the last
+    /// key at or before it. (Without a bound, from the first or last key.)
+    pub fn new_from(node: Option<&'a Node<P, V>>, bound: Option<&K>, reverse: bool) -> Self {
+        let Some(root_node) = node else {
+            return Self::new(None);
+        };
+        Self {
+            inner: Box::new(IterInner::<K, P, V>::seek(root_node, bound, reverse)),
+            _marker: Default::default(),
+        }
+    }
 }
 
 impl<'a, K: KeyTrait<PartialType = P>, P: Partial + Clone + 'a, V: Clone> Iterator
@@ -109,8 +219,9 @@ impl<'a, K: KeyTrait<PartialType = P>, P: Partial + Clone + 'a, V: Clone> Iterat
             // iterator into the stack. We also extend our working key with this node's prefix.
             let node_prefix = &node.prefix;
             if node.is_inner() {
+                let children = self.children(node);
                 self.node_iter_stack
-                    .push((tree_depth + node.prefix.len(), node.iter()));
+                    .push((tree_depth + node.prefix.len(), children));
                 self.cur_key = self.cur_key.extend_from_partial(node_prefix);
                 continue;
             }
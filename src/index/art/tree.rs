@@ -97,6 +97,17 @@ impl<KeyType: KeyTrait, ValueType: Clone> AdaptiveRadixTree<KeyType, ValueType>
         Iter::new(self.root.as_ref())
     }
 
+    /// Iterate in key order from the first key at or after `from`, or if `reverse`, in reverse
+    /// from the last key at or before it. Without `from`, from the first (or last) key. Getting to
+    /// `from` takes a descent of the tree, rather than a walk of the keys before it.
+    pub fn iter_from(
+        &self,
+        from: Option<&KeyType>,
+        reverse: bool,
+    ) -> Iter<KeyType, KeyType::PartialType, ValueType> {
+        Iter::new_from(self.root.as_ref(), from, reverse)
+    }
+
     pub fn is_empty(&self) -> bool {
         self.root.is_none()
     }
@@ -446,4 +457,41 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    /// Verify .iter_from() starts where it should, going either way, whether or not the bound is
+    /// in the tree: compare windows from all over a large tree with the same from a BTreeSet.
+    fn iter_from() {
+        let mut keys: BTreeSet<u64> = (0..10000).map(|i| i * 3).collect();
+        keys.extend((0..1000).map(|_| random_key_pair().0));
+        let mut tree = super::AdaptiveRadixTree::new();
+        for key in &keys {
+            tree.insert_k(&tk(key), *key);
+        }
+
+        let mut bounds = vec![0, 1, 15000, 15001, 15002, 29997, 29998, u64::MAX];
+        bounds.extend((0..100).map(|_| random_key_pair().0));
+        for bound in bounds {
+            let forwards: Vec<_> = tree
+                .iter_from(Some(&tk(&bound)), false)
+                .take(10)
+                .map(|(_, v)| *v)
+                .collect();
+            let expected: Vec<_> = keys.range(bound..).take(10).copied().collect();
+            assert_eq!(forwards, expected, "forwards from {bound}");
+
+            let backwards: Vec<_> = tree
+                .iter_from(Some(&tk(&bound)), true)
+                .take(10)
+                .map(|(_, v)| *v)
+                .collect();
+            let expected: Vec<_> = keys.range(..=bound).rev().take(10).copied().collect();
+            assert_eq!(backwards, expected, "backwards from {bound}");
+        }
+
+        let all: Vec<_> = tree.iter_from(None, true).map(|(_, v)| *v).collect();
+        assert_eq!(all, keys.iter().rev().copied().collect::<Vec<_>>());
+        let all: Vec<_> = tree.iter_from(None, false).map(|(_, v)| *v).collect();
+        assert_eq!(all, keys.iter().copied().collect::<Vec<_>>());
+    }
 }
@@ -12,11 +12,13 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-use crate::index::{AdaptiveRadixTree, ArrayKey, AttrType, Index};
+use crate::index::art::KeyTrait;
+use crate::index::{is_empty_range, AdaptiveRadixTree, ArrayKey, AttrType, Index};
 use crate::tuples::TupleId;
 use crate::{IndexType, RelationError};
 use daumtils::SliceRef;
 use std::collections::HashSet;
+use std::ops::Bound;
 use tracing::error;
 
 /// Adaptive Radix Tree index for when the keys are fixed-length values.
@@ -49,29 +51,53 @@ impl<'a> Iterator for Iter<'a> {
     }
 }
 
+/// Keys are expected in their `codec` encoding, which for the fixed-size types is already the
+/// (big-endian, sign-flipped) ordered form the tree wants. (Keys used to be little-endian
+/// integers, which this reads as the wrong values; databases written that way are refused at open,
+/// by the key format recorded in their catalog.)
 fn to_key(attr_type: AttrType, sr: &SliceRef) -> Result<ArrayKey<16>, RelationError> {
     match attr_type {
-        AttrType::Integer => {
-            let sr = sr.as_slice();
-            let k = i64::from_le_bytes(sr.try_into().map_err(|_| RelationError::BadKey)?);
-            Ok(k.into())
-        }
-        AttrType::UnsignedInteger => {
-            let k = u64::from_le_bytes(
-                sr.as_slice()
-                    .try_into()
-                    .map_err(|_| RelationError::BadKey)?,
-            );
-            Ok(k.into())
+        AttrType::Integer | AttrType::UnsignedInteger | AttrType::Float => {
+            if sr.as_slice().len() != 8 {
+                return Err(RelationError::BadKey);
+            }
+            Ok(ArrayKey::new_from_slice(sr.as_slice()))
         }
         _ => Err(RelationError::BadKey),
     }
 }
 
+/// Wrapper to give the (big-endian, sign-flipped) array keys a total order, for range comparisons.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct OrderedKey(ArrayKey<16>);
+
+impl PartialOrd for OrderedKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OrderedKey {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.as_slice().cmp(other.0.as_slice())
+    }
+}
+
 impl ArtArrayIndex {
     fn to_attr_key(&self, attr: &SliceRef) -> Result<ArrayKey<16>, RelationError> {
         to_key(self.attr_type, attr)
     }
+
+    fn to_attr_key_bound(
+        &self,
+        bound: Bound<&SliceRef>,
+    ) -> Result<Bound<OrderedKey>, RelationError> {
+        Ok(match bound {
+            Bound::Included(k) => Bound::Included(OrderedKey(self.to_attr_key(k)?)),
+            Bound::Excluded(k) => Bound::Excluded(OrderedKey(self.to_attr_key(k)?)),
+            Bound::Unbounded => Bound::Unbounded,
+        })
+    }
 }
 
 impl Index for ArtArrayIndex {
@@ -118,6 +144,49 @@ impl Index for ArtArrayIndex {
         }))
     }
 
+    fn seek_range(
+        &self,
+        lower: Bound<&SliceRef>,
+        upper: Bound<&SliceRef>,
+        reverse: bool,
+    ) -> Result<Box<dyn Iterator<Item = TupleId> + '_>, RelationError> {
+        let lower = self.to_attr_key_bound(lower)?;
+        let upper = self.to_attr_key_bound(upper)?;
+        if is_empty_range(&lower, &upper) {
+            return Ok(Box::new(Iter {
+                iter: Box::new(std::iter::empty()),
+            }));
+        }
+
+        // The tree goes straight to the bound we start from (the upper, going backwards), then
+        // we stop once we're past the other.
+        let (start, end) = if reverse {
+            (upper, lower)
+        } else {
+            (lower, upper)
+        };
+        let from = match &start {
+            Bound::Included(k) | Bound::Excluded(k) => Some(k.0),
+            Bound::Unbounded => None,
+        };
+        // Whether `a` comes after `b`, in the order we're going in.
+        let after = move |a: &OrderedKey, b: &OrderedKey| if reverse { a < b } else { a > b };
+        let iter = self
+            .index
+            .iter_from(from.as_ref(), reverse)
+            .map(|(k, set)| (OrderedKey(k), set))
+            .skip_while(move |(k, _)| matches!(&start, Bound::Excluded(s) if k == s))
+            .take_while(move |(k, _)| match &end {
+                Bound::Included(e) => !after(k, e),
+                Bound::Excluded(e) => after(e, k),
+                Bound::Unbounded => true,
+            })
+            .flat_map(|(_, set)| set.iter().cloned());
+        Ok(Box::new(Iter {
+            iter: Box::new(iter),
+        }))
+    }
+
     fn index_tuple(&mut self, key: &SliceRef, tuple_id: TupleId) -> Result<(), RelationError> {
         let attr_key = self.to_attr_key(key)?;
 
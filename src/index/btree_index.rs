@@ -12,11 +12,12 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-use crate::index::{AttrType, Index};
+use crate::index::{is_empty_range, AttrType, Index};
 use crate::tuples::TupleId;
 use crate::{IndexType, RelationError};
 use daumtils::SliceRef;
 use std::collections::{BTreeMap, HashSet};
+use std::ops::Bound;
 
 #[derive(Clone)]
 pub struct BtreeIndex {
@@ -32,6 +33,7 @@ enum Key {
     UnsignedInteger(SliceRef),
     Float(SliceRef),
     String(SliceRef),
+    Bytes(SliceRef),
 }
 
 impl PartialEq for Key {
@@ -41,6 +43,7 @@ impl PartialEq for Key {
             (Key::UnsignedInteger(a), Key::UnsignedInteger(b)) => a == b,
             (Key::Float(a), Key::Float(b)) => a == b,
             (Key::String(a), Key::String(b)) => a == b,
+            (Key::Bytes(a), Key::Bytes(b)) => a == b,
             _ => false,
         }
     }
@@ -60,6 +63,7 @@ impl Ord for Key {
             (Key::UnsignedInteger(a), Key::UnsignedInteger(b)) => a.cmp(b),
             (Key::Float(a), Key::Float(b)) => a.cmp(b),
             (Key::String(a), Key::String(b)) => a.cmp(b),
+            (Key::Bytes(a), Key::Bytes(b)) => a.cmp(b),
             _ => std::cmp::Ordering::Equal,
         }
     }
@@ -71,10 +75,18 @@ fn to_key(slice_ref: &SliceRef, attr_type: AttrType) -> Result<Key, RelationErro
         AttrType::UnsignedInteger => Ok(Key::UnsignedInteger(slice_ref.clone())),
         AttrType::Float => Ok(Key::Float(slice_ref.clone())),
         AttrType::String => Ok(Key::String(slice_ref.clone())),
-        _ => Err(RelationError::BadKey),
+        AttrType::Bytes => Ok(Key::Bytes(slice_ref.clone())),
     }
 }
 
+fn to_key_bound(bound: Bound<&SliceRef>, attr_type: AttrType) -> Result<Bound<Key>, RelationError> {
+    Ok(match bound {
+        Bound::Included(k) => Bound::Included(to_key(k, attr_type)?),
+        Bound::Excluded(k) => Bound::Excluded(to_key(k, attr_type)?),
+        Bound::Unbounded => Bound::Unbounded,
+    })
+}
+
 pub struct Iter<'a> {
     iter: Box<dyn Iterator<Item = TupleId> + 'a>,
 }
@@ -138,6 +150,29 @@ impl Index for BtreeIndex {
         }))
     }
 
+    fn seek_range(
+        &self,
+        lower: Bound<&SliceRef>,
+        upper: Bound<&SliceRef>,
+        reverse: bool,
+    ) -> Result<Box<dyn Iterator<Item = TupleId> + '_>, RelationError> {
+        let lower = to_key_bound(lower, self.attr_type)?;
+        let upper = to_key_bound(upper, self.attr_type)?;
+        if is_empty_range(&lower, &upper) {
+            return Ok(Box::new(Iter {
+                iter: Box::new(std::iter::empty()),
+            }));
+        }
+
+        let range = self.index.range((lower, upper));
+        let iter: Box<dyn Iterator<Item = TupleId> + '_> = if reverse {
+            Box::new(range.rev().flat_map(|(_, set)| set.iter().cloned()))
+        } else {
+            Box::new(range.flat_map(|(_, set)| set.iter().cloned()))
+        };
+        Ok(Box::new(Iter { iter }))
+    }
+
     fn index_tuple(&mut self, key: &SliceRef, tuple_id: TupleId) -> Result<(), RelationError> {
         let key = to_key(key, self.attr_type)?;
         let entry = self.index.entry(key).or_default();
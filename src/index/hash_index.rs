@@ -17,6 +17,7 @@ use crate::tuples::TupleId;
 use crate::{IndexType, RelationError};
 use daumtils::SliceRef;
 use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
 
 #[derive(Clone)]
 pub struct HashIndex {
@@ -83,6 +84,15 @@ impl Index for HashIndex {
         }))
     }
 
+    fn seek_range(
+        &self,
+        _lower: Bound<&SliceRef>,
+        _upper: Bound<&SliceRef>,
+        _reverse: bool,
+    ) -> Result<Box<dyn Iterator<Item = TupleId> + '_>, RelationError> {
+        Err(RelationError::UnorderedIndex)
+    }
+
     fn index_tuple(&mut self, key: &SliceRef, tuple_id: TupleId) -> Result<(), RelationError> {
         let entry = self.index.entry(key.clone()).or_default();
         if self.unique && !entry.is_empty() {
@@ -32,17 +32,22 @@ use crate::index::btree_index::BtreeIndex;
 use crate::index::im_btree_index::ImBtreeIndex;
 use crate::RelationError;
 pub use art_index::ArtArrayIndex;
+use daumtils::SliceRef;
 pub use hash_index::HashIndex;
 pub use im_hash_index::ImHashIndex;
-use daumtils::SliceRef;
-use strum::EnumString;
+use std::ops::Bound;
+use strum::{EnumString, FromRepr};
 
 /// Types that domains or codomains can be for the purpose of indexing.
 ///
 /// Note that this is not the same as the `moor` Var type, but instead used for declaring the types of the purpose of
 /// indexing and querying. The actual user data can be stored in a variety of ways, but the TupleType is used by the
 /// indexing code to manage e.g. encoding, ordering, hashing, etc.
-#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumString)]
+///
+/// Ordered indexes compare keys as bytes, so values should be stored in their `codec` encoding to
+/// be ordered correctly.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumString, FromRepr)]
 pub enum AttrType {
     /// The tuple attribute in question is a signed 64-bit integer.
     Integer,
@@ -56,15 +61,16 @@ pub enum AttrType {
     Bytes,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumString)]
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumString, FromRepr)]
 pub enum IndexType {
     /// Unordered arbitrary keys. Lookup speed is O(1).
     Hash,
-    /// Viable for integer keys. Keys are ordered and must fit in a fixed size.
+    /// Viable for integer (and float) keys. Keys are ordered and must fit in a fixed size.
     /// Lookup speed is O(log N), but real world performance lies between Hash and BTree.
     /// Linear scan is (theoretically) faster than both.
     AdaptiveRadixTree,
-    /// For ordered keys, valid for everything but Bytes.
+    /// For ordered keys, valid for every attribute type (Bytes are ordered lexicographically).
     /// Lookup speed is O(log n).
     BTree,
 }
@@ -80,6 +86,18 @@ impl IndexType {
             IndexType::BTree => true,
         }
     }
+
+    /// Return true if keys of the given type can be indexed this way. (Adaptive radix trees only
+    /// take fixed-size keys.)
+    pub fn supports(&self, attr_type: AttrType) -> bool {
+        match self {
+            IndexType::AdaptiveRadixTree => matches!(
+                attr_type,
+                AttrType::Integer | AttrType::UnsignedInteger | AttrType::Float
+            ),
+            IndexType::Hash | IndexType::BTree => true,
+        }
+    }
 }
 
 pub trait Index: Send {
@@ -94,6 +112,14 @@ pub trait Index: Send {
         &self,
         domain: &SliceRef,
     ) -> Result<Box<dyn Iterator<Item = TupleId> + '_>, RelationError>;
+    /// Seek tuples whose keys fall within the given bounds, in key order (or descending key order
+    /// if `reverse` is set). Only supported by ordered indexes.
+    fn seek_range(
+        &self,
+        lower: Bound<&SliceRef>,
+        upper: Bound<&SliceRef>,
+        reverse: bool,
+    ) -> Result<Box<dyn Iterator<Item = TupleId> + '_>, RelationError>;
     /// Index the given tuple.
     fn index_tuple(&mut self, key: &SliceRef, tuple_id: TupleId) -> Result<(), RelationError>;
     /// Remove the given tuple from the index.
@@ -105,10 +131,25 @@ pub trait Index: Send {
     fn clear(&mut self);
 }
 
+/// Returns true if the given bounds describe a range which can contain no keys. (`BTreeMap::range`
+/// panics on these, rather than returning nothing.)
+pub(crate) fn is_empty_range<K: Ord>(lower: &Bound<K>, upper: &Bound<K>) -> bool {
+    match (lower, upper) {
+        (Bound::Included(l), Bound::Included(u)) => l > u,
+        (Bound::Included(l), Bound::Excluded(u))
+        | (Bound::Excluded(l), Bound::Included(u))
+        | (Bound::Excluded(l), Bound::Excluded(u)) => l >= u,
+        _ => false,
+    }
+}
+
 pub fn pick_tx_index(
     relation_info: &crate::RelationInfo,
-) -> (Box<dyn Index>, Option<Box<dyn Index>>) {
-    let domain_index: Box<dyn Index> = match relation_info.index_type {
+) -> (
+    Box<dyn Index + Send + Sync>,
+    Option<Box<dyn Index + Send + Sync>>,
+) {
+    let domain_index: Box<dyn Index + Send + Sync> = match relation_info.index_type {
         IndexType::AdaptiveRadixTree => Box::new(ArtArrayIndex::new(
             relation_info.domain_type,
             relation_info.unique_domain,
@@ -119,18 +160,19 @@ pub fn pick_tx_index(
             relation_info.unique_domain,
         )),
     };
-    let codomain_index: Option<Box<dyn Index>> = match relation_info.codomain_index_type {
-        Some(IndexType::AdaptiveRadixTree) => Some(Box::new(ArtArrayIndex::new(
-            relation_info.codomain_type,
-            false,
-        ))),
-        Some(IndexType::Hash) => Some(Box::new(HashIndex::new(false))),
-        None => None,
-        Some(IndexType::BTree) => Some(Box::new(BtreeIndex::new(
-            relation_info.codomain_type,
-            false,
-        ))),
-    };
+    let codomain_index: Option<Box<dyn Index + Send + Sync>> =
+        match relation_info.codomain_index_type {
+            Some(IndexType::AdaptiveRadixTree) => Some(Box::new(ArtArrayIndex::new(
+                relation_info.codomain_type,
+                false,
+            ))),
+            Some(IndexType::Hash) => Some(Box::new(HashIndex::new(false))),
+            None => None,
+            Some(IndexType::BTree) => Some(Box::new(BtreeIndex::new(
+                relation_info.codomain_type,
+                false,
+            ))),
+        };
 
     (domain_index, codomain_index)
 }
@@ -167,3 +209,41 @@ pub fn pick_base_index(
         };
     (domain_index, codomain_index)
 }
+
+/// The transaction-local indexes for a relation's attribute indexes. Transactions only seek them by
+/// key, so they're all hashed.
+pub fn pick_tx_attribute_indexes(
+    relation_info: &crate::RelationInfo,
+) -> Vec<Box<dyn Index + Send + Sync>> {
+    relation_info
+        .attribute_indexes
+        .iter()
+        .map(|_| {
+            let index: Box<dyn Index + Send + Sync> = Box::new(HashIndex::new(false));
+            index
+        })
+        .collect()
+}
+
+/// The (base relation) indexes for a relation's attribute indexes, in declaration order. Indexes on
+/// several attributes are keyed by their packed values, so are `Bytes`.
+pub fn pick_attribute_indexes(
+    relation_info: &crate::RelationInfo,
+) -> Vec<Box<dyn Index + Send + Sync>> {
+    relation_info
+        .attribute_indexes
+        .iter()
+        .map(|index| {
+            let attr_type = match index.attributes[..] {
+                [position] => relation_info.attribute_type(position),
+                _ => AttrType::Bytes,
+            };
+            let index: Box<dyn Index + Send + Sync> = match index.index_type {
+                IndexType::AdaptiveRadixTree => Box::new(ArtArrayIndex::new(attr_type, false)),
+                IndexType::Hash => Box::new(ImHashIndex::new(false)),
+                IndexType::BTree => Box::new(ImBtreeIndex::new(attr_type, false)),
+            };
+            index
+        })
+        .collect()
+}
@@ -15,7 +15,9 @@
 //! In-memory database that provides transactional consistency through copy-on-write maps
 //! Base relations are `im` hashmaps -- persistent / functional / copy-on-writish hashmaps, which
 //! transactions obtain a fork of from `canonical`. At commit timestamps are checked and reconciled
-//! if possible, and the whole set of relations is swapped out for the set of modified tuples.
+//! if possible, and the relations the transaction touched are swapped out for their modified
+//! versions. Commits only lock the relations they touch, so those to disjoint relations proceed
+//! concurrently.
 //!
 //! The tuples themselves are written out at commit time to a backing store, and then re-read at
 //! system initialization.
@@ -24,14 +26,23 @@
 
 pub use index::AttrType;
 pub use index::IndexType;
-pub use relbox::{RelBox, RelationInfo};
+pub use relbox::{
+    Attribute, AttributeIndex, ConflictCounts, Migration, RelBox, RelationInfo, Relations,
+    SchemaError, SequenceKind,
+};
+pub use relbox_derive::Relations;
 use std::fmt::Display;
 use std::str::FromStr;
 use strum::EnumProperty;
 use thiserror::Error;
-pub use tx::{CommitError, Transaction};
+pub use tx::{
+    Atom, Backoff, Change, CommitChanges, CommitError, CommitPolicy, CommitReceipt, Conflict,
+    DurabilityToken, Fact, JoinOn, JoinStrategy, MergeOperator, Predicate, ReadTransaction, RelVar,
+    RelationChanges, Rule, Savepoint, Term, Transaction, Watch,
+};
 
 mod base_relation;
+pub mod codec;
 mod paging;
 mod pool;
 mod relbox;
@@ -53,6 +64,14 @@ impl RelationId {
     pub fn is_transient_relation(&self) -> bool {
         !self.is_base_relation()
     }
+    /// The id of the `index`th transient relation.
+    pub(crate) fn transient(index: usize) -> Self {
+        RelationId(index | (1 << 63))
+    }
+    /// The position of a transient relation amongst its transaction's transient relations.
+    pub(crate) fn transient_index(&self) -> usize {
+        self.0 & !(1 << 63)
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Error)]
@@ -65,9 +84,39 @@ pub enum RelationError {
     AmbiguousTuple,
     #[error("Invalid key type")]
     BadKey,
+    #[error("Operation requires an ordered index")]
+    UnorderedIndex,
+    #[error("Relation has no secondary (codomain) index")]
+    NoSecondaryIndex,
+    #[error("Rule head variable {0} is not bound by the rule body")]
+    UnboundVariable(String),
+    #[error("Relation not found")]
+    RelationNotFound,
+    #[error("Expected {0} attributes, got {1}")]
+    AttributeCount(usize, usize),
+    #[error("Value is not a valid {0:?}")]
+    TypeMismatch(AttrType),
+    #[error("Relation has no merge operator")]
+    NoMergeOperator,
+    #[error("Operation requires a unique domain")]
+    NonUniqueDomain,
+    #[error("Sequence {0} already exists")]
+    SequenceExists(String),
+    #[error("Sequence {0} not found")]
+    SequenceNotFound(usize),
+    #[error("Sequence names can't be empty")]
+    EmptySequenceName,
+    #[error("Savepoint was already released or rolled past")]
+    SavepointNotFound,
+    #[error("No more than {0} relations can be created")]
+    TooManyRelations(usize),
+    #[error("Invalid relation declaration: {0}")]
+    InvalidRelation(String),
 }
 
 /// Convert an enum schema description into RelationInfo (see WorldStateRelation for example)
+/// Prefer `#[derive(Relations)]`, which checks the description at compile time rather than
+/// panicking over it at runtime.
 pub fn relation_info_for<E: EnumProperty + Display>(relation: E) -> RelationInfo {
     let domain_type = relation
         .get_str("DomainType")
@@ -92,6 +141,14 @@ pub fn relation_info_for<E: EnumProperty + Display>(relation: E) -> RelationInfo
         .get_str("SecondaryIndexed")
         .map(|it| it == "true")
         .unwrap_or(false);
+    let unique_domain = relation
+        .get_str("UniqueDomain")
+        .map(|it| it == "true")
+        .unwrap_or(true);
+    let validate_types = relation
+        .get_str("ValidateTypes")
+        .map(|it| it == "true")
+        .unwrap_or(false);
 
     let index_type = relation
         .get_str("IndexType")
@@ -128,8 +185,10 @@ pub fn relation_info_for<E: EnumProperty + Display>(relation: E) -> RelationInfo
         domain_type,
         codomain_type,
         secondary_indexed,
-        unique_domain: true,
+        unique_domain,
         index_type,
         codomain_index_type,
+        validate_types,
+        ..Default::default()
     }
 }
@@ -19,6 +19,8 @@
 use crossbeam_channel::Sender;
 use std::thread::yield_now;
 
+use crate::paging::sequences::StoredSequence;
+use crate::paging::CatalogEntry;
 use crate::tx::WorkingSet;
 
 pub struct BackingStoreClient {
@@ -27,7 +29,16 @@ pub struct BackingStoreClient {
 }
 
 pub enum WriterMessage {
-    Commit(u64, WorkingSet, Vec<i64>),
+    /// A commit to write out, and who to tell (if anyone) once it's durable.
+    Commit(
+        u64,
+        WorkingSet,
+        Vec<StoredSequence>,
+        Option<Vec<CatalogEntry>>,
+        Option<Sender<()>>,
+    ),
+    /// Sequences to write out on their own, and who to tell once they're durable.
+    SyncSequences(Vec<StoredSequence>, Sender<()>),
     Shutdown,
 }
 
@@ -41,13 +52,28 @@ impl BackingStoreClient {
 
     /// Sync out the working set from a committed transaction for the given transaction timestamp.
     /// Used to support persistent storage of committed transactions, effectively as a write-ahead
-    /// log.
-    pub fn sync(&self, ts: u64, ws: WorkingSet, sequences: Vec<i64>) {
+    /// log. If the transaction changed the schema, `catalog` is the new description of it.
+    /// `durable` is signalled once the write-ahead log entry has been written and synced.
+    pub fn sync(
+        &self,
+        ts: u64,
+        ws: WorkingSet,
+        sequences: Vec<StoredSequence>,
+        catalog: Option<Vec<CatalogEntry>>,
+        durable: Option<Sender<()>>,
+    ) {
         self.sender
-            .send(WriterMessage::Commit(ts, ws, sequences))
+            .send(WriterMessage::Commit(ts, ws, sequences, catalog, durable))
             .expect("Unable to send write-ahead sync message");
     }
 
+    /// Write out the sequences outside of any commit, signalling `durable` once they're synced.
+    pub fn sync_sequences(&self, sequences: Vec<StoredSequence>, durable: Sender<()>) {
+        self.sender
+            .send(WriterMessage::SyncSequences(sequences, durable))
+            .expect("Unable to send sequence sync message");
+    }
+
     /// Shutdown the backing store writer thread.
     pub fn shutdown(&self) {
         self.sender
@@ -0,0 +1,510 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! The catalog is the persisted description of the schema: every base relation, in relation id
+//! order, including those which have since been dropped (ids are never reused).
+
+use std::collections::HashSet;
+
+use binary_layout::{binary_layout, Field};
+
+use crate::index::{AttrType, IndexType};
+use crate::relbox::{Attribute, AttributeIndex, Migration, RelationInfo, SchemaError};
+use crate::RelationId;
+
+binary_layout!(catalog_page, LittleEndian, {
+    // The number of relations described in this page.
+    num_relations: u32,
+    // The KEY_FORMAT the relations' values were written in. (This used to be the high half of a
+    // 64-bit relation count, so pages written before it was recorded read as format 0.)
+    key_format: u32,
+    // The relation entries, one after another.
+    relations: [u8],
+});
+
+binary_layout!(catalog_entry, LittleEndian, {
+    // Non-zero if the relation has been dropped.
+    dropped: u8,
+    domain_type: u8,
+    codomain_type: u8,
+    secondary_indexed: u8,
+    unique_domain: u8,
+    index_type: u8,
+    // The codomain index type, or NO_INDEX if there isn't one.
+    codomain_index_type: u8,
+    // Non-zero if values are checked against their declared types.
+    validate_types: u8,
+    key_attributes: u16,
+    num_attributes: u16,
+    num_attribute_indexes: u16,
+    // The length of the name, in bytes.
+    name_len: u32,
+    // The (UTF-8) name, followed by the attributes, then the attribute indexes, then the next
+    // entry.
+    name: [u8],
+});
+
+binary_layout!(catalog_attribute, LittleEndian, {
+    attr_type: u8,
+    name_len: u32,
+    name: [u8],
+});
+
+binary_layout!(catalog_attribute_index, LittleEndian, {
+    index_type: u8,
+    num_attributes: u16,
+    // The (u16) attribute positions.
+    attributes: [u8],
+});
+
+const NO_INDEX: u8 = 0xff;
+
+/// The version of the encoding radix-tree indexed values are stored in. Format 0 took them as
+/// little-endian integers; format 1 takes them in their (order-preserving) `codec` encoding.
+pub(crate) const KEY_FORMAT: u32 = 1;
+
+/// A relation, as recorded in the catalog.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct CatalogEntry {
+    pub(crate) info: RelationInfo,
+    pub(crate) dropped: bool,
+}
+
+/// Relations are only ever added or dropped (and never un-dropped), so of two catalogs, the one
+/// which has seen more changes is the newer.
+pub(crate) fn generation(catalog: &[CatalogEntry]) -> usize {
+    catalog.len() + catalog.iter().filter(|e| e.dropped).count()
+}
+
+fn entry_size(info: &RelationInfo) -> usize {
+    catalog_entry::name::OFFSET
+        + info.name.len()
+        + info
+            .attributes
+            .iter()
+            .map(|a| catalog_attribute::name::OFFSET + a.name.len())
+            .sum::<usize>()
+        + info
+            .attribute_indexes
+            .iter()
+            .map(|i| catalog_attribute_index::attributes::OFFSET + 2 * i.attributes.len())
+            .sum::<usize>()
+}
+
+pub(crate) fn encoded_size(catalog: &[CatalogEntry]) -> usize {
+    catalog_page::relations::OFFSET + catalog.iter().map(|e| entry_size(&e.info)).sum::<usize>()
+}
+
+/// Encode the catalog into `buf`, which must be `encoded_size` bytes.
+pub(crate) fn encode(catalog: &[CatalogEntry], buf: &mut [u8]) {
+    let mut page = catalog_page::View::new(buf);
+    page.num_relations_mut().write(catalog.len() as u32);
+    page.key_format_mut().write(KEY_FORMAT);
+    let entries = page.relations_mut();
+    let mut offset = 0;
+    for e in catalog {
+        let name = e.info.name.as_bytes();
+        let mut entry = catalog_entry::View::new(&mut entries[offset..]);
+        entry.dropped_mut().write(e.dropped as u8);
+        entry.domain_type_mut().write(e.info.domain_type as u8);
+        entry.codomain_type_mut().write(e.info.codomain_type as u8);
+        entry
+            .secondary_indexed_mut()
+            .write(e.info.secondary_indexed as u8);
+        entry.unique_domain_mut().write(e.info.unique_domain as u8);
+        entry.index_type_mut().write(e.info.index_type as u8);
+        entry
+            .codomain_index_type_mut()
+            .write(e.info.codomain_index_type.map_or(NO_INDEX, |t| t as u8));
+        entry
+            .validate_types_mut()
+            .write(e.info.validate_types as u8);
+        entry
+            .key_attributes_mut()
+            .write(e.info.key_attributes as u16);
+        entry
+            .num_attributes_mut()
+            .write(e.info.attributes.len() as u16);
+        entry
+            .num_attribute_indexes_mut()
+            .write(e.info.attribute_indexes.len() as u16);
+        entry.name_len_mut().write(name.len() as u32);
+        entry.name_mut()[..name.len()].copy_from_slice(name);
+        offset += catalog_entry::name::OFFSET + name.len();
+
+        for a in &e.info.attributes {
+            let name = a.name.as_bytes();
+            let mut attribute = catalog_attribute::View::new(&mut entries[offset..]);
+            attribute.attr_type_mut().write(a.attr_type as u8);
+            attribute.name_len_mut().write(name.len() as u32);
+            attribute.name_mut()[..name.len()].copy_from_slice(name);
+            offset += catalog_attribute::name::OFFSET + name.len();
+        }
+        for i in &e.info.attribute_indexes {
+            let mut index = catalog_attribute_index::View::new(&mut entries[offset..]);
+            index.index_type_mut().write(i.index_type as u8);
+            index.num_attributes_mut().write(i.attributes.len() as u16);
+            for (n, position) in i.attributes.iter().enumerate() {
+                index.attributes_mut()[n * 2..n * 2 + 2]
+                    .copy_from_slice(&(*position as u16).to_le_bytes());
+            }
+            offset += catalog_attribute_index::attributes::OFFSET + 2 * i.attributes.len();
+        }
+    }
+}
+
+/// Decode the catalog, and the key format its relations' values were written in.
+pub(crate) fn decode(buf: &[u8]) -> Result<(Vec<CatalogEntry>, u32), SchemaError> {
+    if buf.len() < catalog_page::relations::OFFSET {
+        return Err(corrupt("truncated header"));
+    }
+    let page = catalog_page::View::new(buf);
+    let key_format = page.key_format().read();
+    if key_format > KEY_FORMAT {
+        return Err(corrupt("unknown key format"));
+    }
+    let num_relations = page.num_relations().read();
+    let entries = page.relations();
+    let mut offset = 0;
+    let mut catalog = vec![];
+    for _ in 0..num_relations {
+        if entries.len() < offset + catalog_entry::name::OFFSET {
+            return Err(corrupt("truncated relation entry"));
+        }
+        let entry = catalog_entry::View::new(&entries[offset..]);
+        let name = read_name(entry.name_len().read(), entry.name())?;
+        offset += catalog_entry::name::OFFSET + name.len();
+        let codomain_index_type = match entry.codomain_index_type().read() {
+            NO_INDEX => None,
+            t => Some(index_type(t)?),
+        };
+
+        let mut attributes = vec![];
+        for _ in 0..entry.num_attributes().read() {
+            if entries.len() < offset + catalog_attribute::name::OFFSET {
+                return Err(corrupt("truncated attribute"));
+            }
+            let attribute = catalog_attribute::View::new(&entries[offset..]);
+            let name = read_name(attribute.name_len().read(), attribute.name())?;
+            offset += catalog_attribute::name::OFFSET + name.len();
+            attributes.push(Attribute {
+                name,
+                attr_type: attr_type(attribute.attr_type().read())?,
+            });
+        }
+        let mut attribute_indexes = vec![];
+        for _ in 0..entry.num_attribute_indexes().read() {
+            if entries.len() < offset + catalog_attribute_index::attributes::OFFSET {
+                return Err(corrupt("truncated attribute index"));
+            }
+            let index = catalog_attribute_index::View::new(&entries[offset..]);
+            let num_attributes = index.num_attributes().read() as usize;
+            let positions = index.attributes();
+            if positions.len() < 2 * num_attributes {
+                return Err(corrupt("truncated attribute index"));
+            }
+            attribute_indexes.push(AttributeIndex {
+                attributes: positions[..2 * num_attributes]
+                    .chunks(2)
+                    .map(|p| u16::from_le_bytes([p[0], p[1]]) as usize)
+                    .collect(),
+                index_type: index_type(index.index_type().read())?,
+            });
+            offset += catalog_attribute_index::attributes::OFFSET + 2 * num_attributes;
+        }
+
+        catalog.push(CatalogEntry {
+            info: RelationInfo {
+                name,
+                domain_type: attr_type(entry.domain_type().read())?,
+                codomain_type: attr_type(entry.codomain_type().read())?,
+                secondary_indexed: entry.secondary_indexed().read() != 0,
+                unique_domain: entry.unique_domain().read() != 0,
+                index_type: index_type(entry.index_type().read())?,
+                codomain_index_type,
+                attributes,
+                key_attributes: entry.key_attributes().read() as usize,
+                attribute_indexes,
+                validate_types: entry.validate_types().read() != 0,
+            },
+            dropped: entry.dropped().read() != 0,
+        });
+    }
+    Ok((catalog, key_format))
+}
+
+/// Check the stored catalog against the schema declared at open, producing the catalog to carry
+/// on with. Relations declared beyond the end of the stored ones are new, and stored relations
+/// beyond the end of the declared ones were created at runtime; both are kept, as are drops. A
+/// relation declared differently from how it was stored is an error unless there's a migration for
+/// it.
+pub(crate) fn reconcile(
+    stored: &[CatalogEntry],
+    declared: &[RelationInfo],
+    migrations: &[Migration],
+) -> Result<Vec<CatalogEntry>, SchemaError> {
+    let mut catalog = stored.to_vec();
+    for (i, info) in declared.iter().enumerate() {
+        let Some(entry) = catalog.get_mut(i) else {
+            catalog.push(CatalogEntry {
+                info: info.clone(),
+                dropped: false,
+            });
+            continue;
+        };
+        if entry.info == *info {
+            continue;
+        }
+        let relation_id = RelationId(i);
+        if !migrations.contains(&Migration::Reindex(relation_id)) {
+            return Err(SchemaError::RelationMismatch {
+                relation_id,
+                declared: info.clone(),
+                stored: entry.info.clone(),
+            });
+        }
+        entry.info = info.clone();
+    }
+    Ok(catalog)
+}
+
+/// Check that no relation with stored tuples would index values written in an older key format
+/// with a radix tree, which would read them as the wrong keys. There's no migrating them in place
+/// (the values themselves need re-encoding), so it's an error to open them at all.
+pub(crate) fn check_key_format(
+    catalog: &[CatalogEntry],
+    key_format: u32,
+    stored_relations: &HashSet<RelationId>,
+) -> Result<(), SchemaError> {
+    if key_format == KEY_FORMAT {
+        return Ok(());
+    }
+    for (i, entry) in catalog.iter().enumerate() {
+        let relation_id = RelationId(i);
+        if !entry.dropped && stored_relations.contains(&relation_id) && radix_indexed(&entry.info) {
+            return Err(SchemaError::KeyFormatChanged {
+                relation_id,
+                stored: key_format,
+                current: KEY_FORMAT,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn radix_indexed(info: &RelationInfo) -> bool {
+    info.index_type == IndexType::AdaptiveRadixTree
+        || info.codomain_index_type == Some(IndexType::AdaptiveRadixTree)
+        || info
+            .attribute_indexes
+            .iter()
+            .any(|i| i.index_type == IndexType::AdaptiveRadixTree)
+}
+
+fn corrupt(reason: &str) -> SchemaError {
+    SchemaError::CorruptCatalog(reason.to_string())
+}
+
+fn read_name(len: u32, buf: &[u8]) -> Result<String, SchemaError> {
+    let len = len as usize;
+    if buf.len() < len {
+        return Err(corrupt("truncated name"));
+    }
+    String::from_utf8(buf[..len].to_vec()).map_err(|_| corrupt("name is not UTF-8"))
+}
+
+fn attr_type(v: u8) -> Result<AttrType, SchemaError> {
+    AttrType::from_repr(v).ok_or_else(|| corrupt("invalid attribute type"))
+}
+
+fn index_type(v: u8) -> Result<IndexType, SchemaError> {
+    IndexType::from_repr(v).ok_or_else(|| corrupt("invalid index type"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::index::{AttrType, IndexType};
+    use crate::paging::catalog::{
+        check_key_format, decode, encode, encoded_size, reconcile, CatalogEntry, KEY_FORMAT,
+    };
+    use crate::relbox::{Attribute, AttributeIndex, Migration, RelationInfo, SchemaError};
+    use crate::RelationId;
+
+    fn relation_info(name: &str, index_type: IndexType) -> RelationInfo {
+        RelationInfo {
+            name: name.to_string(),
+            domain_type: AttrType::Integer,
+            codomain_type: AttrType::Integer,
+            secondary_indexed: false,
+            unique_domain: true,
+            index_type,
+            codomain_index_type: None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn catalog_round_trip() {
+        let catalog = vec![
+            CatalogEntry {
+                info: RelationInfo {
+                    name: "first".to_string(),
+                    domain_type: AttrType::Integer,
+                    codomain_type: AttrType::Bytes,
+                    secondary_indexed: true,
+                    unique_domain: false,
+                    index_type: IndexType::AdaptiveRadixTree,
+                    codomain_index_type: Some(IndexType::Hash),
+                    ..Default::default()
+                },
+                dropped: true,
+            },
+            CatalogEntry {
+                info: RelationInfo {
+                    name: "second".to_string(),
+                    domain_type: AttrType::String,
+                    codomain_type: AttrType::Float,
+                    secondary_indexed: false,
+                    unique_domain: true,
+                    index_type: IndexType::BTree,
+                    codomain_index_type: None,
+                    attributes: vec![
+                        Attribute {
+                            name: "a".to_string(),
+                            attr_type: AttrType::String,
+                        },
+                        Attribute {
+                            name: "b".to_string(),
+                            attr_type: AttrType::Integer,
+                        },
+                        Attribute {
+                            name: "c".to_string(),
+                            attr_type: AttrType::Float,
+                        },
+                    ],
+                    key_attributes: 2,
+                    attribute_indexes: vec![AttributeIndex {
+                        attributes: vec![2, 0],
+                        index_type: IndexType::Hash,
+                    }],
+                    validate_types: true,
+                },
+                dropped: false,
+            },
+        ];
+        let mut buf = vec![0; encoded_size(&catalog)];
+        encode(&catalog, &mut buf);
+        assert_eq!(decode(&buf).unwrap(), (catalog.clone(), KEY_FORMAT));
+
+        // Anything cut short is reported, rather than read past.
+        for len in [0, 9, buf.len() - 1] {
+            assert!(matches!(
+                decode(&buf[..len]),
+                Err(SchemaError::CorruptCatalog(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn reconcile_with_declared() {
+        let a = relation_info("a", IndexType::Hash);
+        let b = relation_info("b", IndexType::Hash);
+        let c = relation_info("c", IndexType::Hash);
+        let stored = vec![
+            CatalogEntry {
+                info: a.clone(),
+                dropped: false,
+            },
+            CatalogEntry {
+                info: b.clone(),
+                dropped: true,
+            },
+        ];
+
+        // Matching declarations (plus a new one) are fine, and keep what was stored.
+        let catalog = reconcile(&stored, &[a.clone(), b.clone(), c.clone()], &[]).unwrap();
+        assert_eq!(&catalog[..2], &stored[..]);
+        assert_eq!(catalog[2].info, c);
+
+        // As are declarations of only the first few.
+        assert_eq!(reconcile(&stored, &[a.clone()], &[]).unwrap(), stored);
+
+        // But reordering them isn't.
+        assert_eq!(
+            reconcile(&stored, &[b.clone(), a.clone()], &[]).unwrap_err(),
+            SchemaError::RelationMismatch {
+                relation_id: RelationId(0),
+                declared: b.clone(),
+                stored: a.clone(),
+            }
+        );
+
+        // Unless there's a migration for the change.
+        let a_btree = relation_info("a", IndexType::BTree);
+        assert!(reconcile(&stored, &[a_btree.clone()], &[]).is_err());
+        let catalog = reconcile(
+            &stored,
+            &[a_btree.clone()],
+            &[Migration::Reindex(RelationId(0))],
+        )
+        .unwrap();
+        assert_eq!(catalog[0].info, a_btree);
+        assert!(catalog[1].dropped);
+    }
+
+    #[test]
+    fn key_format() {
+        let catalog = vec![
+            CatalogEntry {
+                info: relation_info("art", IndexType::AdaptiveRadixTree),
+                dropped: false,
+            },
+            CatalogEntry {
+                info: relation_info("hash", IndexType::Hash),
+                dropped: false,
+            },
+            CatalogEntry {
+                info: relation_info("dropped", IndexType::AdaptiveRadixTree),
+                dropped: true,
+            },
+        ];
+        let mut buf = vec![0; encoded_size(&catalog)];
+        encode(&catalog, &mut buf);
+
+        // Pages written before the key format was recorded had a 64-bit relation count there.
+        let mut old = buf.clone();
+        old[4..8].copy_from_slice(&[0; 4]);
+        let (decoded, key_format) = decode(&old).unwrap();
+        assert_eq!((&decoded, key_format), (&catalog, 0));
+
+        // Which is only a problem for the relations with tuples to read into a radix tree.
+        let stored = |ids: &[usize]| ids.iter().map(|i| RelationId(*i)).collect::<HashSet<_>>();
+        assert_eq!(
+            check_key_format(&catalog, 0, &stored(&[0, 1])).unwrap_err(),
+            SchemaError::KeyFormatChanged {
+                relation_id: RelationId(0),
+                stored: 0,
+                current: KEY_FORMAT,
+            }
+        );
+        assert!(check_key_format(&catalog, 0, &stored(&[1, 2])).is_ok());
+        assert!(check_key_format(&catalog, KEY_FORMAT, &stored(&[0, 1, 2])).is_ok());
+
+        // A format from the future can't be read at all.
+        buf[4..8].copy_from_slice(&(KEY_FORMAT + 1).to_le_bytes());
+        assert!(matches!(decode(&buf), Err(SchemaError::CorruptCatalog(_))));
+    }
+}
@@ -19,18 +19,21 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::thread::JoinHandle;
 
-use binary_layout::{binary_layout, Field};
-use crossbeam_channel::{unbounded, Receiver};
+use crossbeam_channel::{unbounded, Receiver, Sender};
 use human_bytes::human_bytes;
 use okaywal::WriteAheadLog;
 use tracing::{debug, error, info};
 
 use crate::base_relation::BaseRelation;
-use crate::paging::page_storage::PageStore;
+use crate::paging::catalog::{self, CatalogEntry};
+use crate::paging::page_storage::{PageStore, PageStoreMutation};
+use crate::paging::sequences::{self, StoredSequence};
 use crate::paging::wal::{make_wal_entry, WalEntryType, WalManager};
 use crate::paging::PageId;
 use crate::paging::TupleBox;
+use crate::relbox::{Migration, RelationInfo, SchemaError};
 use crate::tx::{TxTupleOp, WorkingSet};
+use crate::RelationId;
 
 use super::backing::{BackingStoreClient, WriterMessage};
 
@@ -39,29 +42,18 @@ use super::backing::{BackingStoreClient, WriterMessage};
 /// Uses WAL + custom page store as the persistent backing store & write-ahead-log for the relbox.
 pub struct ColdStorage {}
 
-binary_layout!(sequence_page, LittleEndian, {
-    // The number of sequences stored in this page.
-    num_sequences: u64,
-    // The sequences are here..
-    sequences: [u8],
-});
-
-binary_layout!(sequence, LittleEndian, {
-    // The sequence id.
-    id: u64,
-    // The current value of the sequence.
-    value: i64,
-});
-
 const SEQUENCE_PAGE_ID: PageId = 0xfafe_babf;
+const CATALOG_PAGE_ID: PageId = 0xfafe_bac0;
 
 impl ColdStorage {
     pub fn start(
         path: PathBuf,
-        relations: &mut [BaseRelation],
-        sequences: &mut [i64],
+        relations: &mut Vec<BaseRelation>,
+        schema: &mut Vec<Option<RelationInfo>>,
+        migrations: &[Migration],
+        sequences: &mut Vec<StoredSequence>,
         tuple_box: Arc<TupleBox>,
-    ) -> BackingStoreClient {
+    ) -> Result<BackingStoreClient, SchemaError> {
         let page_storage = PageStore::new(path.join("pages"));
         let wal_manager = WalManager::new(page_storage.clone());
 
@@ -78,28 +70,76 @@ impl ColdStorage {
         // Grab page storage and wait for all the writes to complete.
         page_storage.wait_complete();
 
-        // Get the sequence page, and load the sequences from it, if any.
+        // Get the sequence page, and load the sequences from it, if any. There may be more of them
+        // than were declared (those created at runtime), or fewer (if more have been declared
+        // since the page was written), as long as those declared since don't take the ids of ones
+        // created at runtime.
         if let Ok(Some(sequence_page)) = page_storage.read_sequence_page() {
-            let sequence_page = sequence_page::View::new(&sequence_page[..]);
-            let num_sequences = sequence_page.num_sequences().read();
-            assert_eq!(num_sequences, sequences.len() as u64,
-                "Number of sequences in the sequence page does not match the number of sequences in the rdb");
-            let sequences_bytes = sequence_page.sequences().to_vec();
-            let sequence_size = sequence::SIZE.unwrap() as u64;
-            for i in 0..num_sequences {
-                let sequence =
-                    sequence::View::new(&sequences_bytes[(i * sequence_size) as usize..]);
-                let id = sequence.id().read();
-                let value = sequence.value().read();
-                sequences[id as usize] = value;
+            for (id, stored) in sequences::decode(&sequence_page)?.into_iter().enumerate() {
+                match sequences.get_mut(id) {
+                    Some(sequence) => {
+                        if let Some(name) = &stored.name {
+                            return Err(SchemaError::SequenceCollision {
+                                sequence: id,
+                                name: name.clone(),
+                            });
+                        }
+                        *sequence = stored;
+                    }
+                    None => sequences.push(stored),
+                }
             }
         }
 
-        // Recover all the pages from cold storage and re-index all the tuples in them.
+        // Check the declared schema against the one the pages were written with, before loading
+        // any of them into the wrong relation. Relations created or dropped at runtime are
+        // recorded there as well; bring the schema up to date with them. (Pages written before
+        // there was a catalog predate the current key format, too.)
         let ids = page_storage.list_pages();
+        let (stored_catalog, key_format) = match page_storage
+            .read_catalog_page()
+            .expect("Unable to read catalog page")
+        {
+            Some(catalog_page) => catalog::decode(&catalog_page)?,
+            None if ids.is_empty() => (vec![], catalog::KEY_FORMAT),
+            None => (vec![], 0),
+        };
+        let declared: Vec<_> = relations.iter().map(|r| r.info.clone()).collect();
+        let catalog = catalog::reconcile(&stored_catalog, &declared, migrations)?;
+        let stored_relations: HashSet<_> =
+            ids.iter().map(|(_, _, relation_id)| *relation_id).collect();
+        catalog::check_key_format(&catalog, key_format, &stored_relations)?;
+        for (i, entry) in catalog.iter().enumerate() {
+            if i >= relations.len() {
+                relations.push(BaseRelation::new(RelationId(i), entry.info.clone(), 0));
+                schema.push(Some(entry.info.clone()));
+            }
+            if entry.dropped {
+                schema[i] = None;
+            }
+        }
+        if catalog != stored_catalog || key_format != catalog::KEY_FORMAT {
+            let mut catalog_page = vec![0; catalog::encoded_size(&catalog)];
+            catalog::encode(&catalog, &mut catalog_page);
+            page_storage
+                .enqueue_page_mutations(vec![PageStoreMutation::WriteCatalogPage(
+                    catalog_page.into_boxed_slice(),
+                )])
+                .expect("Unable to write catalog page");
+            page_storage.wait_complete();
+        }
+
+        // Recover all the pages from cold storage and re-index all the tuples in them.
         let mut restored_slots = HashMap::new();
         let mut restored_bytes = 0;
         for (page_size, page_num, relation_id) in ids {
+            // Pages left behind by dropped relations are garbage.
+            if schema[relation_id.0].is_none() {
+                page_storage
+                    .delete_page(page_num, relation_id)
+                    .expect("Unable to delete page of dropped relation");
+                continue;
+            }
             let tuple_ids = tuple_box
                 .clone()
                 .load_page(relation_id, page_num, page_size, |buf| {
@@ -122,7 +162,9 @@ impl ColdStorage {
             for page_tuple_ids in relation_tuple_ids {
                 for tuple_id in page_tuple_ids {
                     let relation = &mut relations[relation_id.0];
-                    relation.load_tuple(tuple_id);
+                    relation
+                        .load_tuple(tuple_id)
+                        .map_err(|error| SchemaError::UnloadableTuple { relation_id, error })?;
                     restored_count += 1;
                 }
             }
@@ -141,10 +183,11 @@ impl ColdStorage {
             wal.clone(),
             tuple_box.clone(),
             page_storage.clone(),
+            sequences.clone(),
         );
 
         // And return the client to it.
-        BackingStoreClient::new(writer_send, cs_join)
+        Ok(BackingStoreClient::new(writer_send, cs_join))
     }
 
     fn start_listen_loop(
@@ -152,10 +195,11 @@ impl ColdStorage {
         wal: WriteAheadLog,
         tuple_box: Arc<TupleBox>,
         ps: Arc<PageStore>,
+        sequences: Vec<StoredSequence>,
     ) -> JoinHandle<()> {
         std::thread::Builder::new()
             .name("moor-coldstorage-listen".to_string())
-            .spawn(move || Self::listen_loop(writer_receive, wal, tuple_box, ps))
+            .spawn(move || Self::listen_loop(writer_receive, wal, tuple_box, ps, sequences))
             .expect("Unable to spawn coldstorage listen thread")
     }
 
@@ -164,12 +208,41 @@ impl ColdStorage {
         wal: WriteAheadLog,
         tuple_box: Arc<TupleBox>,
         ps: Arc<PageStore>,
+        mut written_sequences: Vec<StoredSequence>,
     ) {
         ps.clone().start();
+        let mut catalog_generation = 0;
         loop {
             match writer_receive.recv() {
-                Ok(WriterMessage::Commit(ts, ws, sequences)) => {
-                    Self::perform_writes(wal.clone(), tuple_box.clone(), ts, ws, sequences);
+                Ok(WriterMessage::Commit(ts, ws, sequences, catalog, durable)) => {
+                    // Likewise, the sequences may have been read before those of a sync already
+                    // written.
+                    sequences::advance(&mut written_sequences, sequences);
+                    // Commits can arrive out of order, so only write out a catalog if it's newer
+                    // than the last one we did.
+                    let catalog = match catalog {
+                        Some(catalog) if catalog::generation(&catalog) > catalog_generation => {
+                            catalog_generation = catalog::generation(&catalog);
+                            Some(catalog)
+                        }
+                        _ => None,
+                    };
+                    Self::perform_writes(
+                        wal.clone(),
+                        tuple_box.clone(),
+                        ts,
+                        ws,
+                        &written_sequences,
+                        catalog,
+                    );
+                    // Whoever was waiting for it may have since stopped caring.
+                    if let Some(durable) = durable {
+                        let _ = durable.send(());
+                    }
+                }
+                Ok(WriterMessage::SyncSequences(sequences, durable)) => {
+                    sequences::advance(&mut written_sequences, sequences);
+                    Self::write_sequences(wal.clone(), &written_sequences, durable);
                 }
                 Ok(WriterMessage::Shutdown) => {
                     // Flush the WAL
@@ -189,6 +262,32 @@ impl ColdStorage {
         ps.stop();
     }
 
+    /// Write the sequences (alone) out to the write-ahead-log, signalling `durable` once they're
+    /// synced.
+    fn write_sequences(wal: WriteAheadLog, sequences: &[StoredSequence], durable: Sender<()>) {
+        let mut sync_wal = wal.begin_entry().expect("Failed to begin WAL entry");
+        sync_wal
+            .write_chunk(&Self::sequence_wal_entry(0, sequences))
+            .expect("Failed to write to WAL");
+        sync_wal.commit().expect("Failed to commit WAL entry");
+        let _ = durable.send(());
+    }
+
+    /// A WAL entry to rewrite the sequence page with the current values of all the sequences.
+    fn sequence_wal_entry(ts: u64, sequences: &[StoredSequence]) -> Vec<u8> {
+        make_wal_entry(
+            WalEntryType::SequenceSync,
+            SEQUENCE_PAGE_ID as PageId,
+            None,
+            0,
+            ts,
+            0,
+            sequences::encoded_size(sequences),
+            |buf| sequences::encode(sequences, buf),
+        )
+        .expect("Failed to encode sequence WAL entry")
+    }
+
     /// Receive an (already committed) working set and write the modified pages out to the write-ahead-log to make
     /// the changes durable.
     fn perform_writes(
@@ -196,7 +295,8 @@ impl ColdStorage {
         tuple_box: Arc<TupleBox>,
         ts: u64,
         ws: WorkingSet,
-        sequences: Vec<i64>,
+        sequences: &[StoredSequence],
+        catalog: Option<Vec<CatalogEntry>>,
     ) {
         debug!("Committing write-ahead for ts {}", ts);
 
@@ -207,34 +307,27 @@ impl ColdStorage {
         //   transaction, so we need some kind of signal from above that they have
         //   changed.
 
-        // Build the sequence page first, by copying the current values of all the
-        // sequences into it.
-        let seq_size = sequence::SIZE.unwrap();
-        let seq_page_size = sequence_page::sequences::OFFSET;
-        let seq_wal_entry = make_wal_entry(
-            WalEntryType::SequenceSync,
-            SEQUENCE_PAGE_ID as PageId,
-            None,
-            0,
-            ts,
-            0,
-            seq_page_size + (seq_size * sequences.len()),
-            |buf| {
-                let mut sequence_page = sequence_page::View::new(buf);
-                sequence_page
-                    .num_sequences_mut()
-                    .write(sequences.len() as u64);
-                for (i, sequence_value) in sequences.iter().enumerate() {
-                    let mut sequence = sequence::View::new(
-                        &mut sequence_page.sequences_mut()[i * seq_size..(i + 1) * seq_size],
-                    );
-                    sequence.id_mut().write(i as u64);
-                    sequence.value_mut().write(*sequence_value);
-                }
-            },
-        )
-        .expect("Failed to encode sequence WAL entry");
-        write_batch.push((SEQUENCE_PAGE_ID, Some(seq_wal_entry)));
+        // Build the sequence page first, from the current values of all the sequences.
+        write_batch.push((
+            SEQUENCE_PAGE_ID,
+            Some(Self::sequence_wal_entry(ts, sequences)),
+        ));
+
+        // If the schema changed, the catalog is rewritten as well.
+        if let Some(catalog) = catalog {
+            let catalog_wal_entry = make_wal_entry(
+                WalEntryType::CatalogSync,
+                CATALOG_PAGE_ID,
+                None,
+                0,
+                ts,
+                0,
+                catalog::encoded_size(&catalog),
+                |buf| catalog::encode(&catalog, buf),
+            )
+            .expect("Failed to encode catalog WAL entry");
+            write_batch.push((CATALOG_PAGE_ID, Some(catalog_wal_entry)));
+        }
 
         // Now iterate over all the tuples referred to in the working set and produce WAL entries for them.
         let mut dirty_pages = HashSet::new();
@@ -14,15 +14,19 @@
 
 use thiserror::Error;
 
+pub(crate) use catalog::CatalogEntry;
 pub use pager::Pager;
+pub(crate) use sequences::StoredSequence;
 pub use slotted_page::SlotId;
 pub use tuple_box::{PageId, TupleBox};
 pub use tuple_ptr::TuplePtr;
 
 mod backing;
+mod catalog;
 mod cold_storage;
 mod page_storage;
 mod pager;
+mod sequences;
 mod slotted_page;
 mod tuple_box;
 mod tuple_ptr;
@@ -56,6 +56,7 @@ pub enum PageStoreMutation {
         data: Box<[u8]>,
     },
     WriteSequencePage(Box<[u8]>),
+    WriteCatalogPage(Box<[u8]>),
     DeleteTuple(PageId, RelationId),
 }
 
@@ -237,7 +238,7 @@ impl PageStore {
         for entry in std::fs::read_dir(&self.dir).unwrap() {
             let entry = entry.unwrap();
             let filename = entry.file_name();
-            if filename == "sequences.page" {
+            if filename == "sequences.page" || filename == "catalog.page" {
                 continue;
             }
             let filename = filename.to_str().unwrap();
@@ -253,7 +254,25 @@ impl PageStore {
 
     /// Read the special sequences page into a buffer.
     pub(crate) fn read_sequence_page(&self) -> std::io::Result<Option<Vec<u8>>> {
-        let path = self.dir.join("sequences.page");
+        self.read_named_page("sequences.page")
+    }
+
+    /// Read the special catalog page into a buffer.
+    pub(crate) fn read_catalog_page(&self) -> std::io::Result<Option<Vec<u8>>> {
+        self.read_named_page("catalog.page")
+    }
+
+    /// Remove a page's file (e.g. because the relation it belonged to has been dropped).
+    pub(crate) fn delete_page(
+        &self,
+        page_id: PageId,
+        relation_id: RelationId,
+    ) -> std::io::Result<()> {
+        std::fs::remove_file(self.dir.join(format!("{}_{}.page", page_id, relation_id.0)))
+    }
+
+    fn read_named_page(&self, name: &str) -> std::io::Result<Option<Vec<u8>>> {
+        let path = self.dir.join(name);
         let mut file = match File::open(path) {
             Ok(f) => f,
             Err(e) => {
@@ -401,39 +420,10 @@ impl PageStore {
                     }
                 }
                 PageStoreMutation::WriteSequencePage(data) => {
-                    let path = self.dir.join("sequences.page");
-
-                    let len = data.len();
-                    let mut options = OpenOptions::new();
-                    let file = options.write(true).append(false).create(true).open(path)?;
-                    let raw_fd = file.as_raw_fd();
-
-                    let mut inner = self.inner.lock().unwrap();
-
-                    inner.buffers.insert(request_id, (Arc::new(file), data));
-                    let data_ptr = inner.buffers.get(&request_id).unwrap().1.as_ptr();
-
-                    let write_e = opcode::Write::new(Fd(raw_fd), data_ptr as _, len as _)
-                        .build()
-                        .user_data(request_id)
-                        .flags(Flags::IO_HARDLINK);
-
-                    unsafe {
-                        inner
-                            .uring
-                            .submission()
-                            .push(&write_e)
-                            .expect("Unable to push write to submission queue");
-                    }
-
-                    let fsync_e = opcode::Fsync::new(Fd(raw_fd)).build().user_data(request_id);
-                    unsafe {
-                        inner
-                            .uring
-                            .submission()
-                            .push(&fsync_e)
-                            .expect("Unable to push fsync to submission queue");
-                    }
+                    self.write_named_page(request_id, "sequences.page", data)?;
+                }
+                PageStoreMutation::WriteCatalogPage(data) => {
+                    self.write_named_page(request_id, "catalog.page", data)?;
                 }
                 PageStoreMutation::DeleteTuple(_, _) => {
                     // We could zero-out this data, but it's not really necessary, the header will
@@ -446,4 +436,52 @@ impl PageStore {
 
         Ok(())
     }
+
+    /// Replace the entire contents of one of the special (non-tuple) pages.
+    fn write_named_page(
+        &self,
+        request_id: u64,
+        name: &str,
+        data: Box<[u8]>,
+    ) -> std::io::Result<()> {
+        let path = self.dir.join(name);
+
+        let len = data.len();
+        let mut options = OpenOptions::new();
+        let file = options
+            .write(true)
+            .append(false)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let raw_fd = file.as_raw_fd();
+
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.buffers.insert(request_id, (Arc::new(file), data));
+        let data_ptr = inner.buffers.get(&request_id).unwrap().1.as_ptr();
+
+        let write_e = opcode::Write::new(Fd(raw_fd), data_ptr as _, len as _)
+            .build()
+            .user_data(request_id)
+            .flags(Flags::IO_HARDLINK);
+
+        unsafe {
+            inner
+                .uring
+                .submission()
+                .push(&write_e)
+                .expect("Unable to push write to submission queue");
+        }
+
+        let fsync_e = opcode::Fsync::new(Fd(raw_fd)).build().user_data(request_id);
+        unsafe {
+            inner
+                .uring
+                .submission()
+                .push(&fsync_e)
+                .expect("Unable to push fsync to submission queue");
+        }
+        Ok(())
+    }
 }
@@ -20,8 +20,10 @@ use crate::pool::BufferPool;
 use crate::{
     base_relation::BaseRelation,
     pool::{Bid, BufferPoolError, MmapBufferPool},
+    relbox::{Migration, RelationInfo, SchemaError},
     tx::WorkingSet,
 };
+use crossbeam_channel::Sender;
 use dashmap::DashMap;
 use std::{
     path::PathBuf,
@@ -32,7 +34,10 @@ use std::{
     },
 };
 
-use super::{backing::BackingStoreClient, cold_storage::ColdStorage, PageId, TupleBox};
+use super::{
+    backing::BackingStoreClient, cold_storage::ColdStorage, CatalogEntry, PageId, StoredSequence,
+    TupleBox,
+};
 
 pub struct Pager {
     inner: Inner,
@@ -61,21 +66,27 @@ impl Pager {
     }
 
     /// Restore pages and the tuples they contain, and the indexes to those tuples, and set up
-    /// the pager to use the provided directory for cold storage.
+    /// the pager to use the provided directory for cold storage. The stored schema is checked
+    /// against (or migrated to) the declared one, and relations created or dropped since it was
+    /// declared are restored into `relations` and `schema`.
     pub fn open(
         &self,
         path: PathBuf,
-        relations: &mut [BaseRelation],
-        sequences: &mut [i64],
+        relations: &mut Vec<BaseRelation>,
+        schema: &mut Vec<Option<RelationInfo>>,
+        migrations: &[Migration],
+        sequences: &mut Vec<StoredSequence>,
         tuple_box: Arc<TupleBox>,
-    ) -> Result<(), BufferPoolError> {
+    ) -> Result<(), SchemaError> {
         let mut cs = self.cold_storage.lock().unwrap();
         (*cs) = Some(ColdStorage::start(
             path,
             relations,
+            schema,
+            migrations,
             sequences,
             tuple_box.clone(),
-        ));
+        )?);
 
         Ok(())
     }
@@ -144,10 +155,34 @@ impl Pager {
     }
 
     /// Sync the working set to cold storage (if any)
-    pub fn sync(&self, ts: u64, ws: WorkingSet, sequences: Vec<i64>) {
+    pub fn sync(
+        &self,
+        ts: u64,
+        ws: WorkingSet,
+        sequences: Vec<StoredSequence>,
+        catalog: Option<Vec<CatalogEntry>>,
+        durable: Option<Sender<()>>,
+    ) {
         let cs = self.cold_storage.lock().unwrap();
-        if let Some(cold_storage) = cs.as_ref() {
-            cold_storage.sync(ts, ws, sequences);
+        match cs.as_ref() {
+            Some(cold_storage) => cold_storage.sync(ts, ws, sequences, catalog, durable),
+            // Without cold storage, there's nothing more to do to make it durable.
+            None => {
+                if let Some(durable) = durable {
+                    let _ = durable.send(());
+                }
+            }
+        }
+    }
+
+    /// Sync the sequences alone to cold storage (if any), signalling `durable` once they're synced.
+    pub fn sync_sequences(&self, sequences: Vec<StoredSequence>, durable: Sender<()>) {
+        let cs = self.cold_storage.lock().unwrap();
+        match cs.as_ref() {
+            Some(cold_storage) => cold_storage.sync_sequences(sequences, durable),
+            None => {
+                let _ = durable.send(());
+            }
         }
     }
 
@@ -0,0 +1,252 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! The sequence page holds the current value of every sequence, in sequence id order, followed by
+//! the name and kind of each. (Pages written before sequences had names stop after the values;
+//! their sequences are all unnamed, and immediate.)
+
+use binary_layout::{binary_layout, Field};
+
+use crate::relbox::{SchemaError, SequenceKind};
+
+binary_layout!(sequence_page, LittleEndian, {
+    // The number of sequences stored in this page.
+    num_sequences: u64,
+    // The sequences are here, followed by their names.
+    sequences: [u8],
+});
+
+binary_layout!(sequence, LittleEndian, {
+    // The sequence id.
+    id: u64,
+    // The current value of the sequence.
+    value: i64,
+});
+
+binary_layout!(sequence_name, LittleEndian, {
+    // Non-zero if the sequence is transactional.
+    transactional: u8,
+    // The length of the name, in bytes. Unnamed sequences have an empty one.
+    name_len: u32,
+    // The (UTF-8) name, then the next sequence's.
+    name: [u8],
+});
+
+/// A sequence, as recorded in the sequence page.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct StoredSequence {
+    pub(crate) name: Option<String>,
+    pub(crate) kind: SequenceKind,
+    pub(crate) value: i64,
+}
+
+impl StoredSequence {
+    /// One of the sequences declared at open, which have no name.
+    pub(crate) fn unnamed() -> Self {
+        Self {
+            name: None,
+            kind: SequenceKind::Immediate,
+            value: -1,
+        }
+    }
+}
+
+/// Sequences only ever move forward, and are only ever added, so bring `written` up to date with
+/// `sequences`, which may have been taken before or after it.
+pub(crate) fn advance(written: &mut Vec<StoredSequence>, sequences: Vec<StoredSequence>) {
+    for (id, sequence) in sequences.into_iter().enumerate() {
+        match written.get_mut(id) {
+            Some(written) => written.value = written.value.max(sequence.value),
+            None => written.push(sequence),
+        }
+    }
+}
+
+pub(crate) fn encoded_size(sequences: &[StoredSequence]) -> usize {
+    sequence_page::sequences::OFFSET
+        + sequences
+            .iter()
+            .map(|s| {
+                sequence::SIZE.unwrap()
+                    + sequence_name::name::OFFSET
+                    + s.name.as_ref().map_or(0, |n| n.len())
+            })
+            .sum::<usize>()
+}
+
+/// Encode the sequences into `buf`, which must be `encoded_size` bytes.
+pub(crate) fn encode(sequences: &[StoredSequence], buf: &mut [u8]) {
+    let seq_size = sequence::SIZE.unwrap();
+    let mut page = sequence_page::View::new(buf);
+    page.num_sequences_mut().write(sequences.len() as u64);
+    let entries = page.sequences_mut();
+    for (i, s) in sequences.iter().enumerate() {
+        let mut sequence = sequence::View::new(&mut entries[i * seq_size..(i + 1) * seq_size]);
+        sequence.id_mut().write(i as u64);
+        sequence.value_mut().write(s.value);
+    }
+    let mut offset = sequences.len() * seq_size;
+    for s in sequences {
+        let name = s.name.as_deref().unwrap_or_default().as_bytes();
+        let mut entry = sequence_name::View::new(&mut entries[offset..]);
+        entry
+            .transactional_mut()
+            .write((s.kind == SequenceKind::Transactional) as u8);
+        entry.name_len_mut().write(name.len() as u32);
+        entry.name_mut()[..name.len()].copy_from_slice(name);
+        offset += sequence_name::name::OFFSET + name.len();
+    }
+}
+
+/// Decode the sequences from a sequence page, indexed by id.
+pub(crate) fn decode(buf: &[u8]) -> Result<Vec<StoredSequence>, SchemaError> {
+    if buf.len() < sequence_page::sequences::OFFSET {
+        return Err(corrupt("truncated header"));
+    }
+    let seq_size = sequence::SIZE.unwrap();
+    let page = sequence_page::View::new(buf);
+    let entries = page.sequences();
+    let num_sequences = usize::try_from(page.num_sequences().read())
+        .ok()
+        .filter(|n| {
+            n.checked_mul(seq_size)
+                .is_some_and(|len| len <= entries.len())
+        })
+        .ok_or_else(|| corrupt("truncated sequence values"))?;
+    let mut sequences = vec![StoredSequence::unnamed(); num_sequences];
+    for i in 0..num_sequences {
+        let sequence = sequence::View::new(&entries[i * seq_size..]);
+        let id = sequence.id().read() as usize;
+        let Some(stored) = sequences.get_mut(id) else {
+            return Err(corrupt("sequence id out of range"));
+        };
+        stored.value = sequence.value().read();
+    }
+
+    // Older pages end here.
+    let mut offset = num_sequences * seq_size;
+    if entries.len() == offset {
+        return Ok(sequences);
+    }
+    for sequence in sequences.iter_mut() {
+        if entries.len() < offset + sequence_name::name::OFFSET {
+            return Err(corrupt("truncated sequence name"));
+        }
+        let entry = sequence_name::View::new(&entries[offset..]);
+        let len = entry.name_len().read() as usize;
+        if entry.name().len() < len {
+            return Err(corrupt("truncated sequence name"));
+        }
+        let name = String::from_utf8(entry.name()[..len].to_vec())
+            .map_err(|_| corrupt("sequence name is not UTF-8"))?;
+        sequence.name = (!name.is_empty()).then_some(name);
+        if entry.transactional().read() != 0 {
+            sequence.kind = SequenceKind::Transactional;
+        }
+        offset += sequence_name::name::OFFSET + len;
+    }
+    Ok(sequences)
+}
+
+fn corrupt(reason: &str) -> SchemaError {
+    SchemaError::CorruptSequences(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::paging::sequences::{advance, decode, encode, encoded_size, StoredSequence};
+    use crate::relbox::{SchemaError, SequenceKind};
+
+    fn sequences() -> Vec<StoredSequence> {
+        vec![
+            StoredSequence {
+                value: 10,
+                ..StoredSequence::unnamed()
+            },
+            StoredSequence {
+                name: Some("ids".to_string()),
+                kind: SequenceKind::Transactional,
+                value: 42,
+            },
+            StoredSequence {
+                name: Some("tickets".to_string()),
+                kind: SequenceKind::Immediate,
+                value: -1,
+            },
+        ]
+    }
+
+    #[test]
+    fn sequences_round_trip() {
+        let sequences = sequences();
+        let mut buf = vec![0; encoded_size(&sequences)];
+        encode(&sequences, &mut buf);
+        assert_eq!(decode(&buf).unwrap(), sequences);
+    }
+
+    /// Damaged pages are reported, rather than read past the end of.
+    #[test]
+    fn corrupt_sequence_pages() {
+        let sequences = sequences();
+        let mut buf = vec![0; encoded_size(&sequences)];
+        encode(&sequences, &mut buf);
+        for len in [4, 20, buf.len() - 1] {
+            assert!(matches!(
+                decode(&buf[..len]),
+                Err(SchemaError::CorruptSequences(_))
+            ));
+        }
+        let mut bad_id = buf.clone();
+        bad_id[8] = 7;
+        assert!(decode(&bad_id).is_err());
+        let mut huge = buf;
+        huge[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(decode(&huge).is_err());
+    }
+
+    /// Pages from before sequences were named hold just the values.
+    #[test]
+    fn unnamed_sequence_pages() {
+        let mut buf = 2u64.to_le_bytes().to_vec();
+        for (id, value) in [(0u64, 5i64), (1, 7)] {
+            buf.extend(id.to_le_bytes());
+            buf.extend(value.to_le_bytes());
+        }
+        let decoded = decode(&buf).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert!(decoded.iter().all(|s| s.name.is_none()));
+        assert_eq!(decoded[1].value, 7);
+    }
+
+    /// Syncs of the sequences can be written out of order; none of them takes a sequence back.
+    #[test]
+    fn sequences_only_advance() {
+        let mut written = sequences();
+        let mut stale = sequences();
+        stale.truncate(2);
+        stale[1].value = 40;
+        advance(&mut written, stale);
+        assert_eq!(written, sequences());
+
+        let mut newer = sequences();
+        newer[0].value = 11;
+        newer.push(StoredSequence {
+            name: Some("more".to_string()),
+            kind: SequenceKind::Immediate,
+            value: 0,
+        });
+        advance(&mut written, newer.clone());
+        assert_eq!(written, newer);
+    }
+}
@@ -206,6 +206,9 @@ struct Inner {
     pager: Arc<Pager>,
     /// The set of used pages, indexed by relation, in sorted order of the free space available in them.
     available_page_space: Box<BitArray<PageSpace, 64, Bitset64<1>>>,
+    /// The pages used by transient (transaction-local) relations, which are kept apart from the
+    /// base relations' pages so they never end up in a page written to the backing store.
+    transient_page_space: Option<PageSpace>,
     /// The "swizzelable" references to tuples, indexed by tuple id.
     /// There has to be a stable-memory address for each of these, as they are referenced by
     /// pointers in the TupleRefs themselves.
@@ -216,6 +219,7 @@ impl Inner {
     fn new(pager: Arc<Pager>) -> Self {
         Self {
             available_page_space: Box::new(BitArray::new()),
+            transient_page_space: None,
             pager,
             tuple_ptrs: HashMap::new(),
         }
@@ -300,10 +304,25 @@ impl Inner {
         Ok(SlottedPage::for_page_mut(addr.load(SeqCst), page_size))
     }
 
+    /// The page space for the given relation, if any pages have been allocated to it yet.
+    fn page_space_mut(&mut self, relation_id: RelationId) -> Option<&mut PageSpace> {
+        if relation_id.is_transient_relation() {
+            return self.transient_page_space.as_mut();
+        }
+        self.available_page_space.get_mut(relation_id.0)
+    }
+
+    fn set_page_space(&mut self, relation_id: RelationId, page_space: PageSpace) {
+        if relation_id.is_transient_relation() {
+            self.transient_page_space = Some(page_space);
+        } else {
+            self.available_page_space.set(relation_id.0, page_space);
+        }
+    }
+
     fn do_mark_page_used(&mut self, relation_id: RelationId, free_space: usize, pid: PageId) {
-        let Some(available_page_space) = self.available_page_space.get_mut(relation_id.0) else {
-            self.available_page_space
-                .set(relation_id.0, PageSpace::new(free_space, pid));
+        let Some(available_page_space) = self.page_space_mut(relation_id) else {
+            self.set_page_space(relation_id, PageSpace::new(free_space, pid));
             return;
         };
 
@@ -384,14 +403,14 @@ impl Inner {
                 panic!("Unexpected buffer pool error: {:?}", e);
             }
         };
-        match self.available_page_space.get_mut(relation_id.0) {
+        match self.page_space_mut(relation_id) {
             Some(available_page_space) => {
                 available_page_space.insert(slot_page_empty_size(actual_size), pid);
                 Ok((pid, available_page_space.len() - 1))
             }
             None => {
-                self.available_page_space.set(
-                    relation_id.0,
+                self.set_page_space(
+                    relation_id,
                     PageSpace::new(slot_page_empty_size(actual_size), pid),
                 );
                 Ok((pid, 0))
@@ -411,7 +430,7 @@ impl Inner {
         // Do we have a used pages set for this relation? If not, we can start one, and allocate a
         // new full page to it, and return. When we actually do the allocation, we'll be able to
         // find the page in the used pages set.
-        let Some(available_page_space) = self.available_page_space.get_mut(relation_id.0) else {
+        let Some(available_page_space) = self.page_space_mut(relation_id) else {
             // Ask the buffer pool for a new buffer.
             return self.alloc(relation_id, page_size);
         };
@@ -432,12 +451,13 @@ impl Inner {
         offset: usize,
         page_remaining_bytes: usize,
     ) {
-        let available_page_space = self.available_page_space.get_mut(relation_id.0).unwrap();
+        let available_page_space = self.page_space_mut(relation_id).unwrap();
         available_page_space.finish(offset, page_remaining_bytes);
     }
 
     fn report_free(&mut self, pid: PageId, new_size: usize, is_empty: bool) {
-        for (_, available_page_space) in self.available_page_space.iter_mut() {
+        let base_page_spaces = self.available_page_space.iter_mut().map(|(_, ps)| ps);
+        for available_page_space in base_page_spaces.chain(self.transient_page_space.as_mut()) {
             if available_page_space.update_page(pid, new_size, is_empty) {
                 if is_empty {
                     self.pager.free(pid).expect("Could not free page");
@@ -109,6 +109,9 @@ pub enum WalEntryType {
     // Write current state of sequences to the sequence page. Ignores page id, slot id. Data is
     // the contents of the sequence page.
     SequenceSync = 4,
+    // Write the current catalog (schema description) to the catalog page. Ignores page id, slot id.
+    // Data is the contents of the catalog page.
+    CatalogSync = 5,
 }
 
 #[derive(Error, Debug)]
@@ -289,6 +292,9 @@ impl WalManager {
                 // Data is the contents of the sequence page.
                 write_mutations.push(PageStoreMutation::WriteSequencePage(data));
             }
+            WalEntryType::CatalogSync => {
+                write_mutations.push(PageStoreMutation::WriteCatalogPage(data));
+            }
             WalEntryType::Delete => {
                 // Delete
                 let relation_id = RelationId(wal_entry.header().relation_id().read() as usize);
@@ -12,31 +12,42 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-// TODO: 'join' and transitive closure on db relations
-//   -> datalog-style variable unification
-//   can be used for some of the inheritance graph / verb & property resolution activity done manually now
-
 use crate::base_relation::BaseRelation;
+use crate::codec;
 use crate::index::{AttrType, IndexType};
-use crate::paging::TupleBox;
+use crate::paging::{CatalogEntry, StoredSequence, TupleBox};
+use crate::tuples::attributes;
+use crate::tuples::TupleRef;
 use crate::tx::WorkingSet;
-use crate::tx::{CommitError, CommitSet, Transaction};
-use crate::RelationId;
+use crate::tx::{
+    CommitChanges, CommitError, CommitPolicy, CommitSet, LockWait, MergeOperator, Observers,
+    ReadTransaction, Transaction, Watch,
+};
+use crate::{RelationError, RelationId};
+use crossbeam_channel::{bounded, Sender};
+use daumtils::SliceRef;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
+use std::ops::{Deref, Range};
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicI64, AtomicU64};
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Instant;
+
+use thiserror::Error;
 
 use super::paging::Pager;
 
 /// Meta-data about a relation
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct RelationInfo {
     /// Human readable name of the relation.
     pub name: String,
     /// The domain type ID, which is user defined in the client's type system, and not enforced
+    /// unless `validate_types` is set
     pub domain_type: AttrType,
     /// The codomain type ID, which is user defined in the client's type system, and not enforced
+    /// unless `validate_types` is set
     pub codomain_type: AttrType,
     /// Whether or not this relation has a secondary index on its codomain.
     pub secondary_indexed: bool,
@@ -46,37 +57,320 @@ pub struct RelationInfo {
     pub index_type: IndexType,
     /// Type of the codomain index (only used if `secondary_indexed` is true)
     pub codomain_index_type: Option<IndexType>,
+    /// The attributes of an N-ary relation, if it's declared as one. The first `key_attributes`
+    /// of them make up the domain (and with more than one, the domain is a composite of them
+    /// which should be declared as `Bytes`); the rest make up the codomain. Empty for a plain
+    /// binary relation.
+    pub attributes: Vec<Attribute>,
+    /// How many of the leading `attributes` are the key.
+    pub key_attributes: usize,
+    /// Further (non-unique) indexes, each on one or more attributes.
+    pub attribute_indexes: Vec<AttributeIndex>,
+    /// Whether values written to the relation are checked against their declared types (and
+    /// rejected with `TypeMismatch` if they don't fit), rather than trusted.
+    pub validate_types: bool,
+}
+
+/// A named attribute of an N-ary relation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attribute {
+    pub name: String,
+    pub attr_type: AttrType,
+}
+
+/// An index on some attributes of an N-ary relation, by their positions. Indexes on a single
+/// attribute are keyed by its value (and so can be of any index type the attribute's type suits);
+/// on several, by their packed values, which are `Bytes`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AttributeIndex {
+    pub attributes: Vec<usize>,
+    pub index_type: IndexType,
+}
+
+/// An unnamed binary relation of `Bytes` to `Bytes`, hash indexed, with nothing else declared; for
+/// filling in the fields a declaration doesn't care about.
+impl Default for RelationInfo {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            domain_type: AttrType::Bytes,
+            codomain_type: AttrType::Bytes,
+            secondary_indexed: false,
+            unique_domain: false,
+            index_type: IndexType::Hash,
+            codomain_index_type: None,
+            attributes: vec![],
+            key_attributes: 0,
+            attribute_indexes: vec![],
+            validate_types: false,
+        }
+    }
+}
+
+impl RelationInfo {
+    /// Check that what the declaration says to index can be indexed the way it says, which would
+    /// otherwise only come to light as tuples are written to (or loaded into) the relation.
+    pub fn validate(&self) -> Result<(), RelationError> {
+        let invalid = |reason: String| {
+            Err(RelationError::InvalidRelation(format!(
+                "{}: {reason}",
+                self.name
+            )))
+        };
+        if !self.attributes.is_empty() {
+            if self.key_attributes == 0 || self.key_attributes > self.attributes.len() {
+                return invalid(format!(
+                    "key of {} attributes, out of {}",
+                    self.key_attributes,
+                    self.attributes.len()
+                ));
+            }
+            // Several attributes are packed together, into bytes.
+            let rest = self.arity() - self.key_arity();
+            if self.key_arity() > 1 && self.domain_type != AttrType::Bytes {
+                return invalid("composite key, but the domain isn't Bytes".to_string());
+            }
+            if rest != 1 && self.codomain_type != AttrType::Bytes {
+                return invalid(format!(
+                    "{rest} codomain attributes, but the codomain isn't Bytes"
+                ));
+            }
+        }
+        if !self.index_type.supports(self.domain_type) {
+            return invalid(format!(
+                "{:?} domain can't be indexed by {:?}",
+                self.domain_type, self.index_type
+            ));
+        }
+        if let Some(index_type) = self.codomain_index_type {
+            if !index_type.supports(self.codomain_type) {
+                return invalid(format!(
+                    "{:?} codomain can't be indexed by {index_type:?}",
+                    self.codomain_type
+                ));
+            }
+        }
+        for (i, index) in self.attribute_indexes.iter().enumerate() {
+            if index.attributes.is_empty() {
+                return invalid(format!("attribute index {i} is on no attributes"));
+            }
+            if let Some(position) = index.attributes.iter().find(|p| **p >= self.arity()) {
+                return invalid(format!(
+                    "attribute index {i} is on attribute {position}, of {}",
+                    self.arity()
+                ));
+            }
+            let key_type = match index.attributes[..] {
+                [position] => self.attribute_type(position),
+                _ => AttrType::Bytes,
+            };
+            if !index.index_type.supports(key_type) {
+                return invalid(format!(
+                    "attribute index {i} has {key_type:?} keys, which can't be indexed by {:?}",
+                    index.index_type
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of attributes in the relation's tuples.
+    pub fn arity(&self) -> usize {
+        if self.attributes.is_empty() {
+            return 2;
+        }
+        self.attributes.len()
+    }
+
+    /// The number of attributes which make up the relation's key (domain).
+    pub fn key_arity(&self) -> usize {
+        if self.attributes.is_empty() {
+            return 1;
+        }
+        self.key_attributes
+    }
+
+    /// The type of the attribute at `position`.
+    pub fn attribute_type(&self, position: usize) -> AttrType {
+        if self.attributes.is_empty() {
+            return if position == 0 {
+                self.domain_type
+            } else {
+                self.codomain_type
+            };
+        }
+        self.attributes[position].attr_type
+    }
+
+    /// Encode a (complete) key into a domain.
+    pub fn encode_key(&self, key: &[SliceRef]) -> Result<SliceRef, RelationError> {
+        if key.len() != self.key_arity() {
+            return Err(RelationError::AttributeCount(self.key_arity(), key.len()));
+        }
+        Ok(attributes::pack(key))
+    }
+
+    /// Encode all of a tuple's attributes into its domain and codomain.
+    pub fn encode_tuple(&self, values: &[SliceRef]) -> Result<(SliceRef, SliceRef), RelationError> {
+        if values.len() != self.arity() {
+            return Err(RelationError::AttributeCount(self.arity(), values.len()));
+        }
+        let (key, rest) = values.split_at(self.key_arity());
+        Ok((attributes::pack(key), attributes::pack(rest)))
+    }
+
+    /// Decode a tuple of this relation into its attributes.
+    pub fn decode_tuple(&self, tuple: &TupleRef) -> Result<Vec<SliceRef>, RelationError> {
+        let mut values = attributes::unpack(&tuple.domain(), self.key_arity())?;
+        let rest = self.arity() - self.key_arity();
+        values.extend(attributes::unpack(&tuple.codomain(), rest)?);
+        Ok(values)
+    }
+
+    /// Check that `domain` and `codomain` hold valid values for their declared types, if the
+    /// relation validates them.
+    pub(crate) fn check_types(
+        &self,
+        domain: &SliceRef,
+        codomain: &SliceRef,
+    ) -> Result<(), RelationError> {
+        if !self.validate_types {
+            return Ok(());
+        }
+        let key_arity = self.key_arity();
+        let sides = [
+            (domain, 0, key_arity),
+            (codomain, key_arity, self.arity() - key_arity),
+        ];
+        for (packed, first, count) in sides {
+            let values = attributes::unpack(packed, count)
+                .map_err(|_| RelationError::TypeMismatch(self.attribute_type(first)))?;
+            for (i, value) in values.iter().enumerate() {
+                codec::validate(self.attribute_type(first + i), value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The key of `tuple` in the given attribute index.
+    pub(crate) fn attribute_index_key(
+        &self,
+        index: usize,
+        tuple: &TupleRef,
+    ) -> Result<SliceRef, RelationError> {
+        let values = self.decode_tuple(tuple)?;
+        let key: Vec<_> = self.attribute_indexes[index]
+            .attributes
+            .iter()
+            .map(|position| values.get(*position).cloned().ok_or(RelationError::BadKey))
+            .collect::<Result<_, _>>()?;
+        Ok(attributes::pack(&key))
+    }
+}
+
+/// A set of relations, declared as an enum whose variants are the relations, in relation id order.
+/// Usually derived, with `#[derive(Relations)]` (see `relbox_derive`).
+pub trait Relations {
+    /// The declarations of all the relations, for opening a `RelBox` with.
+    fn relations() -> Vec<RelationInfo>;
+
+    /// The id of this relation.
+    fn relation_id(&self) -> RelationId;
+}
+
+/// A change to how a relation is stored, which the database is allowed to make when opened with a
+/// declaration of the relation that differs from the one it was stored with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Migration {
+    /// Adopt the new declaration of the relation wholesale (e.g. a new name, index type, or
+    /// secondary index), rebuilding its indexes to match as its tuples are loaded.
+    Reindex(RelationId),
+}
+
+/// Errors which can occur when opening a database whose stored schema doesn't agree with the one
+/// declared for it.
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+pub enum SchemaError {
+    #[error("Relation {relation_id:?} is declared as {declared:?}, but was stored as {stored:?}; declare a migration for it if the change is intended")]
+    RelationMismatch {
+        relation_id: RelationId,
+        declared: RelationInfo,
+        stored: RelationInfo,
+    },
+    #[error("Stored schema catalog is corrupt: {0}")]
+    CorruptCatalog(String),
+    #[error("Stored sequences are corrupt: {0}")]
+    CorruptSequences(String),
+    #[error("Sequence {sequence} is declared, but was created as {name:?} since; declare no more sequences than when it was created")]
+    SequenceCollision { sequence: usize, name: String },
+    #[error("Relation {relation_id:?} is declared invalidly: {error}")]
+    InvalidRelation {
+        relation_id: RelationId,
+        error: RelationError,
+    },
+    #[error("Relation {relation_id:?} has values stored in key format {stored}, but radix-tree indexes now read format {current}; its values need re-encoding (see `codec`) before it can be opened")]
+    KeyFormatChanged {
+        relation_id: RelationId,
+        stored: u32,
+        current: u32,
+    },
+    #[error("A stored tuple of relation {relation_id:?} could not be loaded: {error}")]
+    UnloadableTuple {
+        relation_id: RelationId,
+        error: RelationError,
+    },
 }
 
 /// The "RelBox" is the set of relations, referenced by their unique (usize) relation ID.
 /// It exposes interfaces for starting & managing transactions on those relations.
 /// It is, essentially, a micro database.
 pub struct RelBox {
-    /// The description of the set of base relations in our schema
-    relation_info: Vec<RelationInfo>,
+    /// The description of the set of base relations in our schema, indexed by relation id.
+    /// Relations which have been dropped are `None`; their ids are not reused.
+    relation_info: RwLock<Vec<Option<RelationInfo>>>,
 
     /// The monotonically increasing transaction ID "timestamp" counter.
     maximum_transaction: AtomicU64,
 
-    /// Monotonically incrementing sequence counters.
-    sequences: Vec<AtomicI64>,
+    /// Monotonically incrementing sequence counters, indexed by id: the (unnamed) ones declared at
+    /// open, then those created by name since. Ids are never reused.
+    sequences: RwLock<Vec<Arc<Sequence>>>,
+
+    /// The copy-on-write set of current canonical base relations, indexed by relation id.
+    /// Each is swapped out for its modified version independently of the others, so commits to
+    /// disjoint sets of relations don't wait on each other. (The lock on the set itself is only
+    /// taken for writing when relations are created.)
+    pub(crate) canonical: RwLock<Vec<Arc<CanonicalRelation>>>,
 
-    /// The copy-on-write set of current canonical base relations.
-    /// Held in ArcSwap so that we can swap them out atomically for their modified versions, without holding
-    /// a lock for reads.
-    canonical: RwLock<Vec<BaseRelation>>,
+    /// Held (shared) by every commit for as long as it's in progress, and exclusively by anything
+    /// which needs there to be none, to be sure of seeing every commit from then on.
+    commit_gate: RwLock<()>,
+
+    /// Tracks commits swapping their relations into canonical, so snapshots can be taken between
+    /// them without holding commits up.
+    swaps: Swaps,
+
+    /// How hard commits try when the relations they touch are contended, unless their transaction
+    /// says otherwise.
+    commit_policy: RwLock<CommitPolicy>,
+
+    /// How merges into each relation which has had one registered are done.
+    merge_operators: RwLock<HashMap<RelationId, MergeOperator>>,
 
     /// The pager (which contains the buffer pool)
     pager: Arc<Pager>,
 
     /// Management of tuples happens through the tuple box (which uses said pager)
     tuple_box: Arc<TupleBox>,
+
+    /// Those following the changes made by committed transactions.
+    pub(crate) observers: Observers,
 }
 
 impl Debug for RelBox {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("RelBox")
-            .field("num_relations", &self.relation_info.len())
+            .field("num_relations", &self.relation_info.read().unwrap().len())
             .field("maximum_transaction", &self.maximum_transaction)
             .field("sequences", &self.sequences)
             .finish()
@@ -90,40 +384,97 @@ impl RelBox {
         relations: &[RelationInfo],
         num_sequences: usize,
     ) -> Arc<Self> {
+        Self::open(memory_size, path, relations, num_sequences, &[])
+            .unwrap_or_else(|e| panic!("Unable to open database at path: {}", e))
+    }
+
+    /// As `new`, but if there's an existing database at `path` whose stored schema doesn't match
+    /// `relations`, fail with a description of the difference rather than panicking -- unless the
+    /// difference is covered by one of `migrations`, in which case it's applied. Migrations which
+    /// have already been applied (or aren't needed) are ignored, so they can be left declared.
+    pub fn open(
+        memory_size: usize,
+        path: Option<PathBuf>,
+        relations: &[RelationInfo],
+        num_sequences: usize,
+        migrations: &[Migration],
+    ) -> Result<Arc<Self>, SchemaError> {
         let pager = Arc::new(Pager::new(memory_size).expect(
             "Unable to create pager. You may need to set /proc/sys/vm/overcommit_memory to '1'",
         ));
         let tuple_box = Arc::new(TupleBox::new(pager.clone()));
         let mut base_relations = Vec::with_capacity(relations.len());
         for (rid, r) in relations.iter().enumerate() {
+            r.validate().map_err(|error| SchemaError::InvalidRelation {
+                relation_id: RelationId(rid),
+                error,
+            })?;
             base_relations.push(BaseRelation::new(RelationId(rid), r.clone(), 0));
         }
-        let mut sequences = vec![-1; num_sequences];
+        let mut schema: Vec<_> = relations.iter().cloned().map(Some).collect();
+        let mut sequences = vec![StoredSequence::unnamed(); num_sequences];
 
         // Open the pager to the provided path, and restore the relations and sequences from it.
         // (If there's no path, this is a no-op and the database will be transient and empty).
         if let Some(path) = path {
-            pager
-                .open(path, &mut base_relations, &mut sequences, tuple_box.clone())
-                .expect("Unable to open database at path");
+            pager.open(
+                path,
+                &mut base_relations,
+                &mut schema,
+                migrations,
+                &mut sequences,
+                tuple_box.clone(),
+            )?;
         }
         let sequences = sequences
             .into_iter()
-            .map(AtomicI64::new)
+            .map(|s| {
+                Arc::new(Sequence {
+                    name: s.name,
+                    kind: s.kind,
+                    value: Mutex::new(s.value),
+                })
+            })
             .collect::<Vec<_>>();
 
-        Arc::new(Self {
-            relation_info: relations.to_vec(),
+        let canonical = base_relations
+            .into_iter()
+            .map(CanonicalRelation::new)
+            .collect();
+
+        Ok(Arc::new(Self {
+            relation_info: RwLock::new(schema),
             maximum_transaction: AtomicU64::new(0),
-            canonical: RwLock::new(base_relations),
-            sequences,
+            canonical: RwLock::new(canonical),
+            commit_gate: RwLock::new(()),
+            swaps: Swaps::default(),
+            commit_policy: RwLock::new(CommitPolicy::default()),
+            merge_operators: RwLock::new(HashMap::new()),
+            sequences: RwLock::new(sequences),
             tuple_box,
             pager,
-        })
+            observers: Observers::default(),
+        }))
     }
 
+    /// The description of every relation, indexed by relation id. Relations which have been
+    /// dropped keep their last one; see `schema` for which still exist.
     pub fn relation_info(&self) -> Vec<RelationInfo> {
-        self.relation_info.clone()
+        let schema = self.relation_info.read().unwrap();
+        let canonical = self.canonical.read().unwrap();
+        schema
+            .iter()
+            .zip(canonical.iter())
+            .map(|(info, slot)| match info {
+                Some(info) => info.clone(),
+                None => slot.relation.read().unwrap().info.clone(),
+            })
+            .collect()
+    }
+
+    /// The current schema, indexed by relation id. Relations which have been dropped are `None`.
+    pub fn schema(&self) -> Vec<Option<RelationInfo>> {
+        self.relation_info.read().unwrap().clone()
     }
 
     /// Begin a transaction against the current canonical relations.
@@ -134,68 +485,376 @@ impl RelBox {
         Transaction::new(next_ts, self.tuple_box.clone(), self.clone())
     }
 
+    /// Begin a transaction with serializable isolation. Its reads are validated at commit, which
+    /// fails with `CommitError::SerializationConflict` if any of them were invalidated by a
+    /// concurrent commit.
+    pub fn start_serializable_tx(self: Arc<Self>) -> Transaction {
+        let next_ts = self
+            .maximum_transaction
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Transaction::new_serializable(next_ts, self.tuple_box.clone(), self.clone())
+    }
+
+    /// Begin a read-only transaction, pinned to a snapshot of the current canonical relations.
+    pub fn start_read_tx(&self) -> ReadTransaction {
+        ReadTransaction::new(self.snapshot())
+    }
+
+    /// The canonical relations, as of between commits. (They're shared with canonical, rather
+    /// than copied: commits swap in new versions of relations, rather than changing them.)
+    fn snapshot(&self) -> Vec<Arc<BaseRelation>> {
+        let copy = || -> Vec<Arc<BaseRelation>> {
+            self.canonical
+                .read()
+                .unwrap()
+                .iter()
+                .map(|c| c.relation.read().unwrap().clone())
+                .collect()
+        };
+
+        // Copy optimistically, and keep the copy if no commit was swapping its relations in at any
+        // point while we made it.
+        for _ in 0..SNAPSHOT_ATTEMPTS {
+            let finished = self.swaps.finished.load(Ordering::SeqCst);
+            if self.swaps.started.load(Ordering::SeqCst) != finished {
+                std::thread::yield_now();
+                continue;
+            }
+            let snapshot = copy();
+            if self.swaps.started.load(Ordering::SeqCst) == finished {
+                return snapshot;
+            }
+        }
+
+        // Commits keep getting in the way, so hold off their swaps (only) while we copy.
+        let _gate = self.swaps.gate.write().unwrap();
+        copy()
+    }
+
+    /// Follow the changes made by committed transactions: each one which commits after this
+    /// returns, and changes any tuples, is sent (in commit order) to the returned receiver.
+    /// Subscribers which stop receiving should drop the receiver, as changes are queued for them
+    /// until they do.
+    pub fn subscribe(&self) -> crossbeam_channel::Receiver<CommitChanges> {
+        // Wait out any commit in progress, which might have already decided there was nobody to
+        // capture its changes for.
+        let _gate = self.commit_gate.write().unwrap();
+        self.observers.subscribers.subscribe()
+    }
+
+    /// Watch the tuples with `domain` in a relation: the returned watch is signalled each time a
+    /// transaction which inserts, updates or removes any of them commits (after this returns).
+    pub fn watch(&self, relation_id: RelationId, domain: SliceRef) -> Watch {
+        // As with `subscribe`, commits in progress mightn't be capturing changes.
+        let _gate = self.commit_gate.write().unwrap();
+        self.observers.watches.watch(relation_id, domain)
+    }
+
+    /// How many commits have failed for conflicting with others, for each relation (by relation
+    /// id), since the database was opened.
+    pub fn conflict_counts(&self) -> Vec<ConflictCounts> {
+        self.canonical
+            .read()
+            .unwrap()
+            .iter()
+            .map(|c| c.conflicts.counts())
+            .collect()
+    }
+
+    /// Register how merges (`RelVar::merge_by_domain`) into a relation are done. Relations need a
+    /// unique domain to be merged into. Merge operators aren't persisted, so need registering
+    /// each time the database is opened.
+    pub fn set_merge_operator(
+        &self,
+        relation_id: RelationId,
+        merge_operator: MergeOperator,
+    ) -> Result<(), RelationError> {
+        let relation_info = self.relation_info.read().unwrap();
+        let Some(Some(info)) = relation_info.get(relation_id.0) else {
+            return Err(RelationError::RelationNotFound);
+        };
+        if !info.unique_domain {
+            return Err(RelationError::NonUniqueDomain);
+        }
+        self.merge_operators
+            .write()
+            .unwrap()
+            .insert(relation_id, merge_operator);
+        Ok(())
+    }
+
+    /// How merges into the relation are done, if it's been given a merge operator.
+    pub(crate) fn merge_operator(&self, relation_id: RelationId) -> Option<MergeOperator> {
+        self.merge_operators
+            .read()
+            .unwrap()
+            .get(&relation_id)
+            .cloned()
+    }
+
+    /// The policy commits follow when the relations they touch are contended.
+    pub fn commit_policy(&self) -> CommitPolicy {
+        self.commit_policy.read().unwrap().clone()
+    }
+
+    /// Set the policy commits follow when the relations they touch are contended. (Transactions
+    /// can override it with `Transaction::set_commit_policy`.)
+    pub fn set_commit_policy(&self, policy: CommitPolicy) {
+        *self.commit_policy.write().unwrap() = policy;
+    }
+
     pub fn next_ts(self: Arc<Self>) -> u64 {
         self.maximum_transaction
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
     }
 
-    /// Increment this sequence and return its previous value.
-    pub fn increment_sequence(self: Arc<Self>, sequence_number: usize) -> i64 {
-        let sequence = &self.sequences[sequence_number];
-        sequence.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+    /// Create a sequence called `name`, returning its id (which `sequence_id` also finds it by).
+    /// It takes the next id after the sequences declared at open, so declaring more of them later
+    /// would collide with it (which opening refuses). Like them, it starts at -1. Its creation is
+    /// durable by the time this returns.
+    pub fn create_sequence(&self, name: &str, kind: SequenceKind) -> Result<usize, RelationError> {
+        if name.is_empty() {
+            return Err(RelationError::EmptySequenceName);
+        }
+        let sequence_number = {
+            let mut sequences = self.sequences.write().unwrap();
+            if sequences.iter().any(|s| s.name.as_deref() == Some(name)) {
+                return Err(RelationError::SequenceExists(name.to_string()));
+            }
+            sequences.push(Arc::new(Sequence {
+                name: Some(name.to_string()),
+                kind,
+                value: Mutex::new(-1),
+            }));
+            sequences.len() - 1
+        };
+        self.sync_sequences();
+        Ok(sequence_number)
+    }
+
+    /// The id of the sequence called `name`, if there is one.
+    pub fn sequence_id(&self, name: &str) -> Option<usize> {
+        self.sequences
+            .read()
+            .unwrap()
+            .iter()
+            .position(|s| s.name.as_deref() == Some(name))
+    }
+
+    /// Increment this sequence and return its previous value. The increment is durable by the
+    /// time this returns.
+    pub fn increment_sequence(
+        self: Arc<Self>,
+        sequence_number: usize,
+    ) -> Result<i64, RelationError> {
+        Ok(self.reserve_sequence(sequence_number, 1)?.start)
+    }
+
+    /// Take the next `count` values of this sequence, as if it were incremented that many times,
+    /// and return them. Like an increment, this is durable by the time it returns, so taking values
+    /// in blocks saves waiting for that over each one.
+    pub fn reserve_sequence(
+        self: Arc<Self>,
+        sequence_number: usize,
+        count: u32,
+    ) -> Result<Range<i64>, RelationError> {
+        let reserved = {
+            let sequence = self.sequence(sequence_number)?;
+            let mut value = sequence.value.lock().unwrap();
+            let start = *value;
+            *value += i64::from(count);
+            start..*value
+        };
+        self.sync_sequences();
+        Ok(reserved)
     }
 
     /// Get the current value for the given sequence.
-    pub fn sequence_current(self: Arc<Self>, sequence_number: usize) -> i64 {
-        self.sequences[sequence_number].load(std::sync::atomic::Ordering::SeqCst)
-    }
-
-    /// Update the given sequence to `value` iff `value` is greater than the current value.
-    pub fn update_sequence_max(self: Arc<Self>, sequence_number: usize, value: i64) {
-        let sequence = &self.sequences[sequence_number];
-        loop {
-            let current = sequence.load(std::sync::atomic::Ordering::SeqCst);
-            if sequence
-                .compare_exchange(
-                    current,
-                    std::cmp::max(current, value),
-                    std::sync::atomic::Ordering::SeqCst,
-                    std::sync::atomic::Ordering::SeqCst,
-                )
-                .is_ok()
-            {
-                return;
+    pub fn sequence_current(self: Arc<Self>, sequence_number: usize) -> Result<i64, RelationError> {
+        Ok(*self.sequence(sequence_number)?.value.lock().unwrap())
+    }
+
+    /// Update the given sequence to `value` iff `value` is greater than the current value. The
+    /// update is durable by the time this returns.
+    pub fn update_sequence_max(
+        self: Arc<Self>,
+        sequence_number: usize,
+        value: i64,
+    ) -> Result<(), RelationError> {
+        {
+            let sequence = self.sequence(sequence_number)?;
+            let mut current = sequence.value.lock().unwrap();
+            if *current >= value {
+                return Ok(());
             }
+            *current = value;
         }
+        self.sync_sequences();
+        Ok(())
+    }
+
+    pub(crate) fn sequence(&self, sequence_number: usize) -> Result<Arc<Sequence>, RelationError> {
+        self.sequences
+            .read()
+            .unwrap()
+            .get(sequence_number)
+            .cloned()
+            .ok_or(RelationError::SequenceNotFound(sequence_number))
+    }
+
+    /// The sequences as they are now, for persistence.
+    fn stored_sequences(&self) -> Vec<StoredSequence> {
+        self.sequences
+            .read()
+            .unwrap()
+            .iter()
+            .map(|s| StoredSequence {
+                name: s.name.clone(),
+                kind: s.kind,
+                value: *s.value.lock().unwrap(),
+            })
+            .collect()
+    }
+
+    /// Write the sequences out now, rather than with the next commit, and wait until they're
+    /// durable.
+    fn sync_sequences(&self) {
+        let (sender, durable) = bounded(1);
+        self.pager.sync_sequences(self.stored_sequences(), sender);
+        durable
+            .recv()
+            .expect("Write-ahead log writer stopped before syncing sequences");
+    }
+
+    /// The timestamp of the latest commit to the relation, if it exists. (Commits to a relation
+    /// land in timestamp order, so this identifies which of them have.)
+    pub(crate) fn relation_version(&self, relation_id: RelationId) -> Option<u64> {
+        let slot = self.canonical.read().unwrap().get(relation_id.0)?.clone();
+        let ts = slot.relation.read().unwrap().ts;
+        Some(ts)
     }
 
     pub fn with_relation<R, F: Fn(&BaseRelation) -> R>(&self, relation_id: RelationId, f: F) -> R {
-        let rl = self.canonical.read().unwrap();
-        f(rl.get(relation_id.0).unwrap())
+        let canonical = self.canonical.read().unwrap()[relation_id.0].clone();
+        let relation = canonical.relation.read().unwrap();
+        f(&relation)
     }
 
-    /// Prepare a commit set for the given transaction. This will scan through the transaction's
-    /// working set, and for each tuple, check to see if it's safe to commit. If it is, then we'll
-    /// add it to the commit set.
-    pub(crate) fn prepare_commit_set(
+    /// Commit the given transaction's working set, returning the commit timestamp. Only the
+    /// relations the transaction touched are locked, so commits to other relations can go on at
+    /// the same time; if one of them is already locked by another commit, this waits its turn as
+    /// `wait` says, or gives up (with `RelationContentionConflict`) before changing anything, to
+    /// be retried as the commit policy says. The working set is scanned, and each tuple checked
+    /// to see if it's safe to commit; if they all are, the new versions of the relations are
+    /// swapped in.
+    pub(crate) fn commit_working_set(
         &self,
-        commit_ts: u64,
         tx_working_set: &mut WorkingSet,
-    ) -> Result<CommitSet, CommitError> {
-        // The lock belongs to the transaction now now.
-        let canonical_lock = self.canonical.write().unwrap();
-        let mut commitset = CommitSet::new(commit_ts, canonical_lock);
-        commitset.prepare(tx_working_set)?;
-        Ok(commitset)
-    }
+        wait: LockWait,
+    ) -> Result<u64, CommitError> {
+        let _gate = self.commit_gate.read().unwrap();
 
-    pub fn sync(&self, ts: u64, working_set: WorkingSet) {
-        let seqs = self
+        // Schema changes have to be made one at a time, but other commits only need it to stay
+        // put.
+        let schema = if tx_working_set.schema_changes.is_empty() {
+            SchemaGuard::Shared(self.relation_info.read().unwrap())
+        } else {
+            SchemaGuard::Exclusive(self.relation_info.write().unwrap())
+        };
+
+        // Lock the relations, in id order. (Relations the transaction created don't exist yet, and
+        // need no lock: the exclusive schema lock keeps anybody else from creating them too.)
+        let canonical: BTreeMap<_, _> = {
+            let all = self.canonical.read().unwrap();
+            tx_working_set
+                .touched_relations()
+                .into_iter()
+                .filter_map(|id| Some((id, all.get(id.0)?.clone())))
+                .collect()
+        };
+        let mut locks = Vec::with_capacity(canonical.len());
+        for slot in canonical.values() {
+            let Some(lock) = slot.commit_lock.lock(wait) else {
+                return Err(CommitError::RelationContentionConflict { attempts: 1 });
+            };
+            locks.push(lock);
+        }
+
+        // The transactional sequences the transaction moved have to be where it found them, and
+        // stay there until it's done.
+        let sequences: Vec<_> = tx_working_set
             .sequences
             .iter()
-            .map(|s| s.load(std::sync::atomic::Ordering::SeqCst))
+            .map(|(id, moved)| {
+                // (Only sequences that exist could have been moved, and none are ever removed.)
+                let sequence = self.sequence(*id).expect("moved sequence not found");
+                (*id, sequence, *moved)
+            })
             .collect();
-        self.pager.sync(ts, working_set, seqs);
+        let mut sequence_locks = Vec::with_capacity(sequences.len());
+        for (id, sequence, moved) in &sequences {
+            let value = sequence.value.lock().unwrap();
+            if *value != moved.read {
+                return Err(CommitError::SequenceConflict { sequence: *id });
+            }
+            sequence_locks.push((value, moved.value));
+        }
+
+        // The timestamp's taken under the locks, so that commits to each relation get increasing
+        // timestamps in the order they're made, and has its place kept in the order commits are
+        // published in.
+        let commit_ts = self
+            .observers
+            .begin_commit(|| self.maximum_transaction.fetch_add(1, Ordering::SeqCst));
+        let mut commit_set = CommitSet::new(
+            commit_ts,
+            &canonical,
+            &self.canonical,
+            schema,
+            &self.observers,
+        );
+        if let Err(e) = commit_set.prepare(tx_working_set) {
+            // Later commits mustn't wait on this one to be published.
+            self.observers.publish(commit_ts, None);
+            return Err(e);
+        }
+        self.swaps.swap(|| commit_set.try_commit())?;
+        for (mut value, moved) in sequence_locks {
+            *value = moved;
+        }
+        Ok(commit_ts)
+    }
+
+    pub fn sync(&self, ts: u64, working_set: WorkingSet) {
+        self.sync_durable(ts, working_set, None)
+    }
+
+    /// As `sync`, signalling `durable` once the sync is durable.
+    pub(crate) fn sync_durable(
+        &self,
+        ts: u64,
+        working_set: WorkingSet,
+        durable: Option<Sender<()>>,
+    ) {
+        let seqs = self.stored_sequences();
+        let catalog = (!working_set.schema_changes.is_empty()).then(|| self.catalog());
+        self.pager.sync(ts, working_set, seqs, catalog, durable);
+    }
+
+    /// Describe the current schema for persistence, including the relations which were dropped.
+    fn catalog(&self) -> Vec<CatalogEntry> {
+        // Relations are only created or dropped under the schema lock, so holding it keeps the two
+        // in step.
+        let relation_info = self.relation_info.read().unwrap();
+        let canonical = self.canonical.read().unwrap();
+        canonical
+            .iter()
+            .zip(relation_info.iter())
+            .map(|(canonical, info)| CatalogEntry {
+                info: canonical.relation.read().unwrap().info.clone(),
+                dropped: info.is_none(),
+            })
+            .collect()
     }
 
     pub fn db_usage_bytes(&self) -> usize {
@@ -209,6 +868,172 @@ impl RelBox {
     /// Get a copy of the set of the database's canonical relations (generally used for
     /// testing purposes only.)
     pub fn copy_canonical(&self) -> Vec<BaseRelation> {
-        self.canonical.read().unwrap().clone()
+        self.snapshot()
+            .into_iter()
+            .map(|relation| BaseRelation::clone(&relation))
+            .collect()
+    }
+}
+
+/// How increments made to a sequence through a transaction take effect.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SequenceKind {
+    /// Straight away (and durably), as they are outside of any transaction. Those made by a
+    /// transaction which then rolls back are lost, leaving gaps.
+    Immediate,
+    /// When the transaction commits: until then only the transaction itself sees them, and if it
+    /// rolls back they're undone, so the values handed out have no gaps. Of two concurrent
+    /// transactions which both move the sequence, the second to commit fails with
+    /// `CommitError::SequenceConflict`.
+    Transactional,
+}
+
+/// A sequence counter.
+#[derive(Debug)]
+pub(crate) struct Sequence {
+    name: Option<String>,
+    pub(crate) kind: SequenceKind,
+    /// Locked rather than atomic, so that a commit can check and move several sequences at once.
+    pub(crate) value: Mutex<i64>,
+}
+
+/// How many times a snapshot is tried without holding up commits, before it gives up and does.
+const SNAPSHOT_ATTEMPTS: usize = 16;
+
+/// Counts of the commits which have started and finished swapping their relations into canonical.
+/// While the two are equal, no commit is half way through.
+#[derive(Default)]
+struct Swaps {
+    started: AtomicU64,
+    finished: AtomicU64,
+    /// Held (shared) through every swap, and exclusively by snapshots which can't otherwise find
+    /// a moment between them.
+    gate: RwLock<()>,
+}
+
+impl Swaps {
+    fn swap<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        let _gate = self.gate.read().unwrap();
+        self.started.fetch_add(1, Ordering::SeqCst);
+        let result = f();
+        self.finished.fetch_add(1, Ordering::SeqCst);
+        result
+    }
+}
+
+/// A lock which can be waited on for a while, or not at all, as well as until it's free.
+#[derive(Default)]
+pub(crate) struct CommitLock {
+    locked: Mutex<bool>,
+    released: Condvar,
+}
+
+/// Holds a `CommitLock` until dropped.
+pub(crate) struct CommitLockGuard<'a>(&'a CommitLock);
+
+impl CommitLock {
+    /// Take the lock, waiting for it as `wait` says, or return `None` if it couldn't be had in
+    /// that time.
+    pub(crate) fn lock(&self, wait: LockWait) -> Option<CommitLockGuard<'_>> {
+        let mut locked = self.locked.lock().unwrap();
+        while *locked {
+            locked = match wait {
+                LockWait::Try => return None,
+                LockWait::Until(deadline) => {
+                    let remaining = deadline.checked_duration_since(Instant::now())?;
+                    self.released.wait_timeout(locked, remaining).unwrap().0
+                }
+                LockWait::Forever => self.released.wait(locked).unwrap(),
+            };
+        }
+        *locked = true;
+        Some(CommitLockGuard(self))
+    }
+}
+
+impl Drop for CommitLockGuard<'_> {
+    fn drop(&mut self) {
+        *self.0.locked.lock().unwrap() = false;
+        self.0.released.notify_one();
+    }
+}
+
+/// A canonical base relation, in its slot in the set of them.
+pub(crate) struct CanonicalRelation {
+    /// Held by a commit to the relation from when it's prepared until its new version is swapped
+    /// in, so that commits to the same relation take turns.
+    pub(crate) commit_lock: CommitLock,
+    /// The current version of the relation. Only written (swapped) by the holder of `commit_lock`,
+    /// and never changed in place, so that snapshots can share it.
+    pub(crate) relation: RwLock<Arc<BaseRelation>>,
+    /// Counts of the commits which have conflicted over the relation.
+    conflicts: ConflictCounters,
+}
+
+impl CanonicalRelation {
+    pub(crate) fn new(relation: BaseRelation) -> Arc<Self> {
+        Arc::new(Self {
+            commit_lock: CommitLock::default(),
+            relation: RwLock::new(Arc::new(relation)),
+            conflicts: ConflictCounters::default(),
+        })
+    }
+
+    /// Count a commit's conflict over this relation, and pass the error on.
+    pub(crate) fn conflict(&self, error: CommitError) -> CommitError {
+        let counter = match &error {
+            CommitError::TupleVersionConflict(_) => &self.conflicts.version_conflicts,
+            CommitError::UniqueConstraintViolation(_) => &self.conflicts.unique_violations,
+            CommitError::SerializationConflict => &self.conflicts.serialization_conflicts,
+            _ => return error,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        error
+    }
+}
+
+/// How many commits have failed for conflicting with others over a relation, by the kind of
+/// conflict.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ConflictCounts {
+    /// `CommitError::TupleVersionConflict`s.
+    pub version_conflicts: u64,
+    /// `CommitError::UniqueConstraintViolation`s.
+    pub unique_violations: u64,
+    /// `CommitError::SerializationConflict`s.
+    pub serialization_conflicts: u64,
+}
+
+#[derive(Default)]
+struct ConflictCounters {
+    version_conflicts: AtomicU64,
+    unique_violations: AtomicU64,
+    serialization_conflicts: AtomicU64,
+}
+
+impl ConflictCounters {
+    fn counts(&self) -> ConflictCounts {
+        ConflictCounts {
+            version_conflicts: self.version_conflicts.load(Ordering::Relaxed),
+            unique_violations: self.unique_violations.load(Ordering::Relaxed),
+            serialization_conflicts: self.serialization_conflicts.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The lock a commit holds on the schema: exclusive if it changes it, shared otherwise.
+pub(crate) enum SchemaGuard<'a> {
+    Shared(RwLockReadGuard<'a, Vec<Option<RelationInfo>>>),
+    Exclusive(RwLockWriteGuard<'a, Vec<Option<RelationInfo>>>),
+}
+
+impl Deref for SchemaGuard<'_> {
+    type Target = Vec<Option<RelationInfo>>;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            SchemaGuard::Shared(schema) => schema,
+            SchemaGuard::Exclusive(schema) => schema,
+        }
     }
 }
@@ -0,0 +1,149 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Packing of several attributes into one side (domain or codomain) of a tuple.
+//!
+//! A lone attribute is stored as-is, so a binary relation is just a relation of two attributes.
+//! Several are each escaped (`0x00` becomes `0x00 0xff`) and terminated with `0x00 0x01`, which
+//! keeps the packed form in the same (lexicographic) order as the attributes it was made from, and
+//! makes the packing of the first few attributes a byte prefix of the packing of all of them.
+
+use daumtils::SliceRef;
+
+use crate::RelationError;
+
+const ESCAPE: u8 = 0x00;
+const ESCAPED: u8 = 0xff;
+const TERMINATOR: u8 = 0x01;
+
+/// Pack `values` into a single slice.
+pub(crate) fn pack(values: &[SliceRef]) -> SliceRef {
+    if let [value] = values {
+        return value.clone();
+    }
+    SliceRef::from_vec(pack_prefix(values))
+}
+
+/// Pack `values` as the leading attributes of several, i.e. always framed, even if there's only
+/// one.
+pub(crate) fn pack_prefix(values: &[SliceRef]) -> Vec<u8> {
+    let mut buf = vec![];
+    for value in values {
+        for b in value.as_slice() {
+            buf.push(*b);
+            if *b == ESCAPE {
+                buf.push(ESCAPED);
+            }
+        }
+        buf.extend_from_slice(&[ESCAPE, TERMINATOR]);
+    }
+    buf
+}
+
+/// The (exclusive) upper bound of everything packed with `prefix` as its leading attributes.
+pub(crate) fn prefix_upper_bound(prefix: &[u8]) -> Vec<u8> {
+    // Packed prefixes end with a terminator, and the byte after it in anything longer is always
+    // smaller than this.
+    let mut upper = prefix.to_vec();
+    if let Some(last) = upper.last_mut() {
+        *last = TERMINATOR + 1;
+    }
+    upper
+}
+
+/// Unpack a slice made by `pack` from `count` values.
+pub(crate) fn unpack(packed: &SliceRef, count: usize) -> Result<Vec<SliceRef>, RelationError> {
+    if count == 1 {
+        return Ok(vec![packed.clone()]);
+    }
+    let values = unpack_prefix(packed)?;
+    if values.len() != count {
+        return Err(RelationError::BadKey);
+    }
+    Ok(values)
+}
+
+/// Unpack a slice made by `pack_prefix`, however many values it holds.
+pub(crate) fn unpack_prefix(packed: &SliceRef) -> Result<Vec<SliceRef>, RelationError> {
+    let mut values = vec![];
+    let mut value = vec![];
+    let mut bytes = packed.as_slice().iter();
+    while let Some(b) = bytes.next() {
+        if *b != ESCAPE {
+            value.push(*b);
+            continue;
+        }
+        match bytes.next() {
+            Some(&ESCAPED) => value.push(ESCAPE),
+            Some(&TERMINATOR) => values.push(SliceRef::from_vec(std::mem::take(&mut value))),
+            _ => return Err(RelationError::BadKey),
+        }
+    }
+    if !value.is_empty() {
+        return Err(RelationError::BadKey);
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use daumtils::SliceRef;
+
+    use crate::tuples::attributes::{pack, pack_prefix, prefix_upper_bound, unpack};
+    use crate::RelationError;
+
+    fn attrs(values: &[&[u8]]) -> Vec<SliceRef> {
+        values.iter().map(|v| SliceRef::from_bytes(v)).collect()
+    }
+
+    #[test]
+    fn pack_round_trip() {
+        let single = attrs(&[b"a\x00b"]);
+        assert_eq!(pack(&single), single[0]);
+        assert_eq!(unpack(&pack(&single), 1).unwrap(), single);
+
+        let values = attrs(&[b"a\x00b", b"", b"\x00\x01\xff", b"c"]);
+        let packed = pack(&values);
+        assert_eq!(unpack(&packed, 4).unwrap(), values);
+        assert_eq!(unpack(&packed, 3), Err(RelationError::BadKey));
+        assert_eq!(
+            unpack(&SliceRef::from_bytes(b"a\x00"), 2),
+            Err(RelationError::BadKey)
+        );
+    }
+
+    #[test]
+    fn packing_preserves_order() {
+        let ordered = [
+            attrs(&[b"a", b"z"]),
+            attrs(&[b"a\x00", b"a"]),
+            attrs(&[b"ab", b""]),
+            attrs(&[b"ab", b"a"]),
+            attrs(&[b"b", b""]),
+        ];
+        for pair in ordered.windows(2) {
+            assert!(pack(&pair[0]).as_slice() < pack(&pair[1]).as_slice());
+        }
+
+        // Everything starting with a given prefix falls between it and its upper bound; nothing
+        // else does.
+        let prefix = pack_prefix(&attrs(&[b"ab"]));
+        let upper = prefix_upper_bound(&prefix);
+        for values in &ordered {
+            let packed = pack(values);
+            let in_range = packed.as_slice() >= &prefix[..] && packed.as_slice() < &upper[..];
+            assert_eq!(in_range, values[0].as_slice() == b"ab");
+        }
+    }
+}
@@ -16,6 +16,7 @@ pub use tuple_ref::TupleRef;
 
 use crate::paging::{PageId, SlotId};
 
+pub(crate) mod attributes;
 mod tuple_ref;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
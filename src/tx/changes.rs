@@ -0,0 +1,246 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Change data capture: the changes committed transactions made, for subscribers to follow, and
+//! watchers of particular keys to be woken by.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use daumtils::SliceRef;
+
+use crate::RelationId;
+
+/// A change a committed transaction made to a tuple.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    Insert {
+        domain: SliceRef,
+        codomain: SliceRef,
+    },
+    Update {
+        domain: SliceRef,
+        old_codomain: SliceRef,
+        new_codomain: SliceRef,
+    },
+    Delete {
+        domain: SliceRef,
+        old_codomain: SliceRef,
+    },
+}
+
+impl Change {
+    /// The domain of the tuple changed.
+    pub fn domain(&self) -> &SliceRef {
+        match self {
+            Change::Insert { domain, .. }
+            | Change::Update { domain, .. }
+            | Change::Delete { domain, .. } => domain,
+        }
+    }
+}
+
+/// The changes a committed transaction made to one relation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelationChanges {
+    pub relation_id: RelationId,
+    pub changes: Vec<Change>,
+}
+
+/// Everything a transaction changed, as of its commit timestamp.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommitChanges {
+    pub ts: u64,
+    pub relations: Vec<RelationChanges>,
+}
+
+/// Those following committed changes: subscribers to all of them, and watchers of particular keys.
+#[derive(Default)]
+pub(crate) struct Observers {
+    pub(crate) subscribers: Subscribers,
+    pub(crate) watches: Arc<Watches>,
+    order: Mutex<PublishOrder>,
+}
+
+/// Commits finish in whatever order their locks allow, but are published in timestamp order: each
+/// one's changes wait here until every commit with an earlier timestamp has been published (or
+/// abandoned).
+#[derive(Default)]
+struct PublishOrder {
+    /// The timestamps of the commits still in progress.
+    in_progress: BTreeSet<u64>,
+    /// The changes of finished commits waiting on earlier ones, by timestamp.
+    waiting: BTreeMap<u64, CommitChanges>,
+}
+
+impl Observers {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.subscribers.is_empty() && self.watches.is_empty()
+    }
+
+    /// Take a commit timestamp from `next_ts`, holding its place in the publication order until
+    /// the commit is published.
+    pub(crate) fn begin_commit<F: FnOnce() -> u64>(&self, next_ts: F) -> u64 {
+        let mut order = self.order.lock().unwrap();
+        let ts = next_ts();
+        order.in_progress.insert(ts);
+        ts
+    }
+
+    /// Publish the changes of the commit at `ts` (`None` if it made none, or didn't go through),
+    /// along with those of any later commits which were waiting on it.
+    pub(crate) fn publish(&self, ts: u64, changes: Option<CommitChanges>) {
+        let mut order = self.order.lock().unwrap();
+        order.in_progress.remove(&ts);
+        if let Some(changes) = changes {
+            order.waiting.insert(ts, changes);
+        }
+        let earliest_in_progress = order.in_progress.first().copied().unwrap_or(u64::MAX);
+        while let Some(waiting) = order.waiting.first_entry() {
+            if *waiting.key() > earliest_in_progress {
+                break;
+            }
+            let changes = waiting.remove();
+            self.watches.notify(&changes);
+            self.subscribers.publish(changes);
+        }
+    }
+}
+
+/// The subscribers to committed changes. Subscribers which have gone away (dropped their receiver)
+/// are forgotten the next time there's something to send them.
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    senders: Mutex<Vec<Sender<CommitChanges>>>,
+}
+
+impl Subscribers {
+    pub(crate) fn subscribe(&self) -> Receiver<CommitChanges> {
+        let (sender, receiver) = unbounded();
+        self.senders.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.senders.lock().unwrap().is_empty()
+    }
+
+    pub(crate) fn publish(&self, changes: CommitChanges) {
+        self.senders
+            .lock()
+            .unwrap()
+            .retain(|sender| sender.send(changes.clone()).is_ok());
+    }
+}
+
+/// A watch on a key (a domain of a relation), which is signalled with the timestamp of each
+/// committed transaction that inserts, updates or removes tuples with that domain. Dropping it ends
+/// the watch.
+pub struct Watch {
+    key: (RelationId, SliceRef),
+    id: u64,
+    receiver: Receiver<u64>,
+    watches: Arc<Watches>,
+}
+
+impl Watch {
+    /// Block until the key changes, and return the timestamp of the commit which changed it. If it
+    /// changed since the last wait, this returns straight away.
+    pub fn wait(&self) -> u64 {
+        self.receiver
+            .recv()
+            .expect("Watch is registered for as long as it exists")
+    }
+
+    /// As `wait`, but give up (returning `None`) if the key hasn't changed within `timeout`.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<u64> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// The timestamp of a commit which changed the key since the last wait, if there's been one.
+    pub fn try_wait(&self) -> Option<u64> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.watches.unwatch(&self.key, self.id);
+    }
+}
+
+/// The watches on keys, by key.
+#[derive(Default)]
+pub(crate) struct Watches {
+    next_id: AtomicU64,
+    watchers: Mutex<HashMap<(RelationId, SliceRef), Vec<(u64, Sender<u64>)>>>,
+}
+
+impl Watches {
+    pub(crate) fn watch(self: &Arc<Self>, relation_id: RelationId, domain: SliceRef) -> Watch {
+        let (sender, receiver) = unbounded();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let key = (relation_id, domain);
+        self.watchers
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .push((id, sender));
+        Watch {
+            key,
+            id,
+            receiver,
+            watches: self.clone(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.watchers.lock().unwrap().is_empty()
+    }
+
+    fn unwatch(&self, key: &(RelationId, SliceRef), id: u64) {
+        let mut watchers = self.watchers.lock().unwrap();
+        if let Some(senders) = watchers.get_mut(key) {
+            senders.retain(|(watch_id, _)| *watch_id != id);
+            if senders.is_empty() {
+                watchers.remove(key);
+            }
+        }
+    }
+
+    /// Signal the watchers of the keys `changes` touched, once each.
+    fn notify(&self, changes: &CommitChanges) {
+        let watchers = self.watchers.lock().unwrap();
+        if watchers.is_empty() {
+            return;
+        }
+        let mut touched = HashSet::new();
+        for relation in &changes.relations {
+            for change in &relation.changes {
+                let key = (relation.relation_id, change.domain().clone());
+                if !touched.insert(key.clone()) {
+                    continue;
+                }
+                for (_, sender) in watchers.get(&key).into_iter().flatten() {
+                    // The watch can only have gone if it's being dropped, and is about to unwatch.
+                    let _ = sender.send(changes.ts);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Transitive closure over self-referential relations (e.g. object -> parent), walked
+//! breadth-first through the transaction so results reflect its snapshot and working set.
+
+use std::collections::{HashSet, VecDeque};
+
+use daumtils::SliceRef;
+
+use crate::tuples::TupleRef;
+use crate::tx::transaction::Transaction;
+use crate::{RelationError, RelationId};
+
+impl Transaction {
+    /// Follow domain -> codomain edges from `start`, returning every reachable tuple in BFS order.
+    /// Each domain is expanded at most once, so cycles terminate. With `max_depth`, only tuples
+    /// at most that many edges away from `start` are returned.
+    pub fn transitive_closure(
+        &self,
+        relation_id: RelationId,
+        start: SliceRef,
+        max_depth: Option<usize>,
+    ) -> Result<Vec<TupleRef>, RelationError> {
+        self.closure(start, max_depth, |key| {
+            Ok(self
+                .seek_by_domain(relation_id, key)?
+                .into_iter()
+                .map(|t| {
+                    let next = t.codomain();
+                    (t, next)
+                })
+                .collect())
+        })
+    }
+
+    /// Follow edges backwards (codomain -> domain) from `start` through the codomain index,
+    /// returning every tuple which transitively points at `start`, in BFS order.
+    pub fn transitive_closure_reverse(
+        &self,
+        relation_id: RelationId,
+        start: SliceRef,
+        max_depth: Option<usize>,
+    ) -> Result<Vec<TupleRef>, RelationError> {
+        if !self.relation_info(relation_id)?.secondary_indexed {
+            return Err(RelationError::NoSecondaryIndex);
+        }
+        self.closure(start, max_depth, |key| {
+            Ok(self
+                .seek_by_codomain(relation_id, key)?
+                .into_iter()
+                .map(|t| {
+                    let next = t.domain();
+                    (t, next)
+                })
+                .collect())
+        })
+    }
+
+    /// Breadth-first walk from `start`, where `edges` produces the tuples leading out of a key,
+    /// each paired with the key it leads to.
+    fn closure<F>(
+        &self,
+        start: SliceRef,
+        max_depth: Option<usize>,
+        edges: F,
+    ) -> Result<Vec<TupleRef>, RelationError>
+    where
+        F: Fn(SliceRef) -> Result<Vec<(TupleRef, SliceRef)>, RelationError>,
+    {
+        let mut results = vec![];
+        let mut visited = HashSet::new();
+        let mut frontier = VecDeque::new();
+        visited.insert(start.clone());
+        frontier.push_back((start, 0));
+        while let Some((key, depth)) = frontier.pop_front() {
+            if max_depth.is_some_and(|max| depth >= max) {
+                continue;
+            }
+            for (tuple, next) in edges(key)? {
+                results.push(tuple);
+                if visited.insert(next.clone()) {
+                    frontier.push_back((next, depth + 1));
+                }
+            }
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use daumtils::SliceRef;
+
+    use crate::index::{AttrType, IndexType};
+    use crate::relbox::{RelBox, RelationInfo};
+    use crate::tuples::TupleRef;
+    use crate::{RelationError, RelationId};
+
+    fn attr(slice: &[u8]) -> SliceRef {
+        SliceRef::from_bytes(slice)
+    }
+
+    fn test_db() -> Arc<RelBox> {
+        RelBox::new(
+            1 << 24,
+            None,
+            &[
+                RelationInfo {
+                    name: "parent".to_string(),
+                    domain_type: AttrType::String,
+                    codomain_type: AttrType::String,
+                    secondary_indexed: true,
+                    unique_domain: true,
+                    index_type: IndexType::Hash,
+                    codomain_index_type: Some(IndexType::Hash),
+                    ..Default::default()
+                },
+                RelationInfo {
+                    name: "unindexed".to_string(),
+                    domain_type: AttrType::String,
+                    codomain_type: AttrType::String,
+                    secondary_indexed: false,
+                    unique_domain: true,
+                    index_type: IndexType::Hash,
+                    codomain_index_type: None,
+                    ..Default::default()
+                },
+            ],
+            0,
+        )
+    }
+
+    fn domains(tuples: &[TupleRef]) -> Vec<Vec<u8>> {
+        tuples
+            .iter()
+            .map(|t| t.domain().as_slice().to_vec())
+            .collect()
+    }
+
+    /// An inheritance chain c -> b -> a -> root, with d also inheriting from b.
+    #[test]
+    fn closure_forward_and_reverse() {
+        let db = test_db();
+        let rid = RelationId(0);
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(rid, attr(b"a"), attr(b"root")).unwrap();
+        tx.insert_tuple(rid, attr(b"b"), attr(b"a")).unwrap();
+        tx.insert_tuple(rid, attr(b"c"), attr(b"b")).unwrap();
+        tx.commit().unwrap();
+
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(rid, attr(b"d"), attr(b"b")).unwrap();
+
+        let ancestors = tx.transitive_closure(rid, attr(b"c"), None).unwrap();
+        assert_eq!(
+            domains(&ancestors),
+            vec![b"c".to_vec(), b"b".to_vec(), b"a".to_vec()]
+        );
+
+        let ancestors = tx.transitive_closure(rid, attr(b"c"), Some(2)).unwrap();
+        assert_eq!(domains(&ancestors), vec![b"c".to_vec(), b"b".to_vec()]);
+
+        let descendants = tx
+            .transitive_closure_reverse(rid, attr(b"a"), None)
+            .unwrap();
+        let mut descendants = domains(&descendants);
+        // b is one hop away; c and d (in either order) are two.
+        assert_eq!(descendants[0], b"b".to_vec());
+        descendants[1..].sort();
+        assert_eq!(
+            descendants,
+            vec![b"b".to_vec(), b"c".to_vec(), b"d".to_vec()]
+        );
+    }
+
+    #[test]
+    fn closure_terminates_on_cycles() {
+        let db = test_db();
+        let rid = RelationId(0);
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(rid, attr(b"x"), attr(b"y")).unwrap();
+        tx.insert_tuple(rid, attr(b"y"), attr(b"z")).unwrap();
+        tx.insert_tuple(rid, attr(b"z"), attr(b"x")).unwrap();
+
+        let reachable = tx.transitive_closure(rid, attr(b"x"), None).unwrap();
+        assert_eq!(
+            domains(&reachable),
+            vec![b"x".to_vec(), b"y".to_vec(), b"z".to_vec()]
+        );
+    }
+
+    #[test]
+    fn reverse_requires_secondary_index() {
+        let db = test_db();
+        let tx = db.clone().start_tx();
+        assert_eq!(
+            tx.transitive_closure_reverse(RelationId(1), attr(b"x"), None)
+                .unwrap_err(),
+            RelationError::NoSecondaryIndex
+        );
+    }
+}
@@ -0,0 +1,171 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::thread::{sleep, yield_now};
+use std::time::{Duration, Instant};
+
+/// How hard a commit tries when the relations it touches are contended: when another commit to
+/// one of them is in progress (`CommitError::RelationContentionConflict`). Other commit errors
+/// are never retried.
+///
+/// With a deadline, a commit waits its turn at contended relations for as long as it allows.
+/// Limited to a number of attempts and no deadline, the attempts don't wait, but back off between
+/// them. With neither (`CommitPolicy::wait()`), it waits for as long as it takes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommitPolicy {
+    /// The most attempts to make, including the first, or `None` for as many as the deadline
+    /// allows.
+    pub max_attempts: Option<u32>,
+    /// How long after the first attempt to give up, if ever.
+    pub deadline: Option<Duration>,
+    /// How long to wait between attempts.
+    pub backoff: Backoff,
+}
+
+/// What to do between attempts at a contended commit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Backoff {
+    /// Just yield to other threads.
+    Yield,
+    /// Sleep, starting at `initial` and doubling each time up to `max`. With `jitter`, each sleep
+    /// is instead a random duration up to that, so that commits contending with each other
+    /// spread out.
+    Exponential {
+        initial: Duration,
+        max: Duration,
+        jitter: bool,
+    },
+}
+
+impl Default for CommitPolicy {
+    /// The first attempt and up to 50 retries, yielding between them.
+    fn default() -> Self {
+        Self {
+            max_attempts: Some(51),
+            deadline: None,
+            backoff: Backoff::Yield,
+        }
+    }
+}
+
+impl CommitPolicy {
+    /// Wait for contended relations for as long as it takes, however long another commit holds
+    /// them up.
+    pub fn wait() -> Self {
+        Self {
+            max_attempts: None,
+            deadline: None,
+            backoff: Backoff::Yield,
+        }
+    }
+
+    /// Make only the one attempt, failing straight away if it's contended.
+    pub fn fail_fast() -> Self {
+        Self {
+            max_attempts: Some(1),
+            deadline: None,
+            backoff: Backoff::Yield,
+        }
+    }
+
+    /// Begin a commit's attempts under this policy.
+    pub(crate) fn start(&self) -> Attempts {
+        Attempts {
+            policy: self.clone(),
+            started: Instant::now(),
+            made: 0,
+        }
+    }
+}
+
+/// How long an attempt at a commit waits for each of the relations it touches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LockWait {
+    /// Not at all: if one's taken, the attempt is contended.
+    Try,
+    /// Until the given time, after which the attempt is contended.
+    Until(Instant),
+    /// However long it takes.
+    Forever,
+}
+
+/// The attempts made at a commit so far, under a given policy.
+pub(crate) struct Attempts {
+    policy: CommitPolicy,
+    started: Instant,
+    made: u32,
+}
+
+impl Attempts {
+    /// Count another attempt, returning how many there have been.
+    pub(crate) fn attempt(&mut self) -> u32 {
+        self.made += 1;
+        self.made
+    }
+
+    /// How long the next attempt should wait for the relations it touches.
+    pub(crate) fn lock_wait(&self) -> LockWait {
+        match (self.policy.deadline, self.policy.max_attempts) {
+            (Some(deadline), _) => LockWait::Until(self.started + deadline),
+            (None, None) => LockWait::Forever,
+            (None, Some(_)) => LockWait::Try,
+        }
+    }
+
+    /// Wait before the next attempt, or return false if the policy says to give up instead.
+    pub(crate) fn backoff(&self) -> bool {
+        if self.policy.max_attempts.is_some_and(|max| self.made >= max) {
+            return false;
+        }
+        let remaining = match self.policy.deadline {
+            Some(deadline) => match deadline.checked_sub(self.started.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => Some(remaining),
+                _ => return false,
+            },
+            None => None,
+        };
+        match &self.policy.backoff {
+            Backoff::Yield => yield_now(),
+            Backoff::Exponential {
+                initial,
+                max,
+                jitter,
+            } => {
+                let doublings = 1u32
+                    .checked_shl(self.made.saturating_sub(1))
+                    .unwrap_or(u32::MAX);
+                let mut delay = initial.saturating_mul(doublings).min(*max);
+                if *jitter {
+                    delay = jittered(delay);
+                }
+                // Don't sleep past the deadline.
+                if let Some(remaining) = remaining {
+                    delay = delay.min(remaining);
+                }
+                sleep(delay);
+            }
+        }
+        true
+    }
+}
+
+/// A random duration up to `delay`. (Each `RandomState` is seeded afresh, which is random enough
+/// for spreading out retries.)
+fn jittered(delay: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    let nanos = u64::try_from(delay.as_nanos()).unwrap_or(u64::MAX);
+    Duration::from_nanos(random % nanos.saturating_add(1))
+}
@@ -0,0 +1,336 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Binary joins between relations, evaluated inside a transaction so that the working set's
+//! uncommitted inserts, updates and removals are reflected in the results.
+
+use std::collections::HashMap;
+
+use daumtils::SliceRef;
+
+use crate::index::IndexType;
+use crate::relbox::RelationInfo;
+use crate::tuples::TupleRef;
+use crate::tx::transaction::Transaction;
+use crate::{RelationError, RelationId};
+
+/// Which attribute of the right-hand relation the left relation's codomain is matched against.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum JoinOn {
+    /// `left.codomain = right.domain`
+    CodomainToDomain,
+    /// `left.codomain = right.codomain`; probing requires a secondary index on the right relation.
+    CodomainToCodomain,
+}
+
+/// How the join is evaluated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum JoinStrategy {
+    /// Scan the left relation, and seek the right relation's index for each left tuple.
+    IndexNestedLoop,
+    /// Scan the right relation, and seek the left relation's codomain index for each right tuple.
+    /// Probing requires a secondary index on the left relation.
+    ReverseIndexNestedLoop,
+    /// Scan both relations, building a hash table over the right relation and probing it with the
+    /// left.
+    HashJoin,
+}
+
+impl JoinStrategy {
+    /// Pick a strategy based on the indexes each side could be probed through: the right-hand
+    /// side's domain (or codomain) index, and the left-hand side's codomain index.
+    ///
+    /// Hash indexes give O(1) point lookups, so probing them per tuple of the other side is
+    /// cheapest; the right side's is preferred if both have one. Ordered indexes cost a tree
+    /// descent (and key conversion) per probe, and an unindexed attribute can't be probed at all,
+    /// so when neither side has a hash index one scan of the right side into a hash table wins.
+    pub fn for_relations(left: &RelationInfo, right: &RelationInfo, on: JoinOn) -> Self {
+        let right_index = match on {
+            JoinOn::CodomainToDomain => Some(right.index_type),
+            JoinOn::CodomainToCodomain => right
+                .secondary_indexed
+                .then_some(right.codomain_index_type)
+                .flatten(),
+        };
+        let left_index = left
+            .secondary_indexed
+            .then_some(left.codomain_index_type)
+            .flatten();
+        match (left_index, right_index) {
+            (_, Some(IndexType::Hash)) => JoinStrategy::IndexNestedLoop,
+            (Some(IndexType::Hash), _) => JoinStrategy::ReverseIndexNestedLoop,
+            _ => JoinStrategy::HashJoin,
+        }
+    }
+}
+
+impl Transaction {
+    /// Join the codomain of `left` against the domain (or codomain) of `right`, returning the
+    /// matching (left, right) tuple pairs. The strategy is chosen from the relations' indexes; see
+    /// `JoinStrategy::for_relations`.
+    pub fn join(
+        &self,
+        left: RelationId,
+        right: RelationId,
+        on: JoinOn,
+    ) -> Result<Vec<(TupleRef, TupleRef)>, RelationError> {
+        let strategy = JoinStrategy::for_relations(
+            &self.relation_info(left)?,
+            &self.relation_info(right)?,
+            on,
+        );
+        self.join_with_strategy(left, right, on, strategy)
+    }
+
+    /// As `join`, but with an explicitly chosen strategy.
+    pub fn join_with_strategy(
+        &self,
+        left: RelationId,
+        right: RelationId,
+        on: JoinOn,
+        strategy: JoinStrategy,
+    ) -> Result<Vec<(TupleRef, TupleRef)>, RelationError> {
+        if strategy == JoinStrategy::IndexNestedLoop
+            && on == JoinOn::CodomainToCodomain
+            && !self.relation_info(right)?.secondary_indexed
+        {
+            return Err(RelationError::NoSecondaryIndex);
+        }
+        if strategy == JoinStrategy::ReverseIndexNestedLoop
+            && !self.relation_info(left)?.secondary_indexed
+        {
+            return Err(RelationError::NoSecondaryIndex);
+        }
+
+        let right_key = |r: &TupleRef| match on {
+            JoinOn::CodomainToDomain => r.domain(),
+            JoinOn::CodomainToCodomain => r.codomain(),
+        };
+        let mut results = vec![];
+        match strategy {
+            JoinStrategy::IndexNestedLoop => {
+                for l in self.predicate_scan(left, &|_| true)? {
+                    let matches = match on {
+                        JoinOn::CodomainToDomain => self.seek_by_domain(right, l.codomain())?,
+                        JoinOn::CodomainToCodomain => self.seek_by_codomain(right, l.codomain())?,
+                    };
+                    for r in matches {
+                        results.push((l.clone(), r));
+                    }
+                }
+            }
+            JoinStrategy::ReverseIndexNestedLoop => {
+                for r in self.predicate_scan(right, &|_| true)? {
+                    for l in self.seek_by_codomain(left, right_key(&r))? {
+                        results.push((l, r.clone()));
+                    }
+                }
+            }
+            JoinStrategy::HashJoin => {
+                let mut table: HashMap<SliceRef, Vec<TupleRef>> = HashMap::new();
+                for r in self.predicate_scan(right, &|_| true)? {
+                    table.entry(right_key(&r)).or_default().push(r);
+                }
+                for l in self.predicate_scan(left, &|_| true)? {
+                    let Some(matches) = table.get(&l.codomain()) else {
+                        continue;
+                    };
+                    for r in matches {
+                        results.push((l.clone(), r.clone()));
+                    }
+                }
+            }
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use daumtils::SliceRef;
+
+    use crate::index::{AttrType, IndexType};
+    use crate::relbox::{RelBox, RelationInfo};
+    use crate::tuples::TupleRef;
+    use crate::tx::join::{JoinOn, JoinStrategy};
+    use crate::{RelationError, RelationId};
+
+    fn attr(slice: &[u8]) -> SliceRef {
+        SliceRef::from_bytes(slice)
+    }
+
+    fn relation(name: &str, index_type: IndexType, secondary: bool) -> RelationInfo {
+        RelationInfo {
+            name: name.to_string(),
+            domain_type: AttrType::String,
+            codomain_type: AttrType::String,
+            secondary_indexed: secondary,
+            unique_domain: true,
+            index_type,
+            codomain_index_type: secondary.then_some(IndexType::Hash),
+            ..Default::default()
+        }
+    }
+
+    /// 0: object -> parent (hash), 1: object -> name (btree, secondary indexed on name)
+    fn test_db() -> Arc<RelBox> {
+        RelBox::new(
+            1 << 24,
+            None,
+            &[
+                relation("parent", IndexType::Hash, false),
+                relation("name", IndexType::BTree, true),
+            ],
+            0,
+        )
+    }
+
+    fn pairs(results: Vec<(TupleRef, TupleRef)>) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut pairs: Vec<_> = results
+            .iter()
+            .map(|(l, r)| {
+                (
+                    l.domain().as_slice().to_vec(),
+                    r.codomain().as_slice().to_vec(),
+                )
+            })
+            .collect();
+        pairs.sort();
+        pairs
+    }
+
+    #[test]
+    fn strategy_selection() {
+        let hash = relation("hash", IndexType::Hash, false);
+        let btree = relation("btree", IndexType::BTree, true);
+        let unindexed = relation("unindexed", IndexType::BTree, false);
+        // The right side's hash index is probed if it has one...
+        assert_eq!(
+            JoinStrategy::for_relations(&unindexed, &hash, JoinOn::CodomainToDomain),
+            JoinStrategy::IndexNestedLoop
+        );
+        assert_eq!(
+            JoinStrategy::for_relations(&unindexed, &btree, JoinOn::CodomainToCodomain),
+            JoinStrategy::IndexNestedLoop
+        );
+        // ...otherwise the left side's,
+        assert_eq!(
+            JoinStrategy::for_relations(&btree, &unindexed, JoinOn::CodomainToDomain),
+            JoinStrategy::ReverseIndexNestedLoop
+        );
+        assert_eq!(
+            JoinStrategy::for_relations(&btree, &hash, JoinOn::CodomainToCodomain),
+            JoinStrategy::ReverseIndexNestedLoop
+        );
+        // and with neither, a hash join.
+        assert_eq!(
+            JoinStrategy::for_relations(&unindexed, &hash, JoinOn::CodomainToCodomain),
+            JoinStrategy::HashJoin
+        );
+        assert_eq!(
+            JoinStrategy::for_relations(&hash, &btree, JoinOn::CodomainToDomain),
+            JoinStrategy::HashJoin
+        );
+    }
+
+    /// Both strategies should agree, and both should see the transaction's uncommitted writes.
+    #[test]
+    fn join_parent_names() {
+        let db = test_db();
+        let (parent, name) = (RelationId(0), RelationId(1));
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(parent, attr(b"a"), attr(b"root")).unwrap();
+        tx.insert_tuple(parent, attr(b"b"), attr(b"root")).unwrap();
+        tx.insert_tuple(parent, attr(b"c"), attr(b"a")).unwrap();
+        tx.insert_tuple(parent, attr(b"e"), attr(b"root")).unwrap();
+        tx.insert_tuple(name, attr(b"root"), attr(b"Root")).unwrap();
+        tx.insert_tuple(name, attr(b"a"), attr(b"Thing A")).unwrap();
+        tx.commit().unwrap();
+
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(parent, attr(b"d"), attr(b"b")).unwrap();
+        tx.insert_tuple(name, attr(b"b"), attr(b"Thing B")).unwrap();
+        tx.remove_by_domain(parent, attr(b"a")).unwrap();
+
+        let expected = vec![
+            (b"b".to_vec(), b"Root".to_vec()),
+            (b"c".to_vec(), b"Thing A".to_vec()),
+            (b"d".to_vec(), b"Thing B".to_vec()),
+            (b"e".to_vec(), b"Root".to_vec()),
+        ];
+        for strategy in [JoinStrategy::IndexNestedLoop, JoinStrategy::HashJoin] {
+            let results = tx
+                .join_with_strategy(parent, name, JoinOn::CodomainToDomain, strategy)
+                .unwrap();
+            assert_eq!(pairs(results), expected);
+        }
+        assert_eq!(
+            pairs(tx.join(parent, name, JoinOn::CodomainToDomain).unwrap()),
+            expected
+        );
+    }
+
+    #[test]
+    fn join_codomain_to_codomain() {
+        let db = test_db();
+        let (parent, name) = (RelationId(0), RelationId(1));
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(name, attr(b"x"), attr(b"shared")).unwrap();
+        tx.insert_tuple(name, attr(b"y"), attr(b"shared")).unwrap();
+        tx.insert_tuple(parent, attr(b"p"), attr(b"shared"))
+            .unwrap();
+
+        for strategy in [JoinStrategy::IndexNestedLoop, JoinStrategy::HashJoin] {
+            let results = tx
+                .join_with_strategy(parent, name, JoinOn::CodomainToCodomain, strategy)
+                .unwrap();
+            let mut rights: Vec<_> = results
+                .iter()
+                .map(|(_, r)| r.domain().as_slice().to_vec())
+                .collect();
+            rights.sort();
+            assert_eq!(rights, vec![b"x".to_vec(), b"y".to_vec()]);
+        }
+
+        // Probing the left side instead, through its codomain index.
+        let results = tx
+            .join_with_strategy(
+                name,
+                parent,
+                JoinOn::CodomainToCodomain,
+                JoinStrategy::ReverseIndexNestedLoop,
+            )
+            .unwrap();
+        let mut lefts: Vec<_> = results
+            .iter()
+            .map(|(l, _)| l.domain().as_slice().to_vec())
+            .collect();
+        lefts.sort();
+        assert_eq!(lefts, vec![b"x".to_vec(), b"y".to_vec()]);
+
+        // No secondary index on the parent relation to probe through.
+        assert_eq!(
+            tx.join_with_strategy(
+                name,
+                parent,
+                JoinOn::CodomainToCodomain,
+                JoinStrategy::IndexNestedLoop
+            )
+            .unwrap_err(),
+            RelationError::NoSecondaryIndex
+        );
+    }
+}
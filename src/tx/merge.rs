@@ -0,0 +1,94 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use daumtils::SliceRef;
+
+use crate::codec::{decode_list, encode_list, Codec};
+use crate::RelationError;
+
+/// How merges (`RelVar::merge_by_domain`) into a relation's codomains combine a delta with the
+/// current value. Concurrent merges into the same tuple don't conflict: at commit, the deltas a
+/// transaction merged are re-applied, in order, on top of the latest committed value.
+#[derive(Clone)]
+pub enum MergeOperator {
+    /// Integers (`i64`s, as encoded by the `Codec`), which deltas are added to, wrapping on
+    /// overflow. With no value yet, the delta is the value.
+    AddInteger,
+    /// Lists of values (see `codec::encode_list`), which deltas (lists themselves) are appended to.
+    Append,
+    /// Sets of values, as lists in order, which deltas (lists themselves) are unioned into.
+    Union,
+    /// Any other merge: given the current value (if there is one) and a delta, produce the new
+    /// value. For the outcome not to depend on the order transactions commit in, it should be
+    /// commutative.
+    Custom(Arc<dyn Fn(Option<&SliceRef>, &SliceRef) -> SliceRef + Send + Sync>),
+}
+
+impl Debug for MergeOperator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeOperator::AddInteger => f.write_str("AddInteger"),
+            MergeOperator::Append => f.write_str("Append"),
+            MergeOperator::Union => f.write_str("Union"),
+            MergeOperator::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl MergeOperator {
+    /// Merge `delta` into `current`.
+    pub(crate) fn merge(
+        &self,
+        current: Option<&SliceRef>,
+        delta: &SliceRef,
+    ) -> Result<SliceRef, RelationError> {
+        let Some(current) = current else {
+            return match self {
+                MergeOperator::Custom(merge) => Ok(merge(None, delta)),
+                // Check that the delta is something which can be merged into later.
+                _ => self.merge(Some(&self.empty()), delta),
+            };
+        };
+        match self {
+            MergeOperator::AddInteger => {
+                let sum = i64::decode(current)?.wrapping_add(i64::decode(delta)?);
+                Ok(sum.encode())
+            }
+            MergeOperator::Append => {
+                let mut values = decode_list(current)?;
+                values.extend(decode_list(delta)?);
+                Ok(encode_list(&values))
+            }
+            MergeOperator::Union => {
+                let mut values = decode_list(current)?;
+                values.extend(decode_list(delta)?);
+                values.sort_by(|a, b| a.as_slice().cmp(b.as_slice()));
+                values.dedup_by(|a, b| a.as_slice() == b.as_slice());
+                Ok(encode_list(&values))
+            }
+            MergeOperator::Custom(merge) => Ok(merge(Some(current), delta)),
+        }
+    }
+
+    /// The value merging into nothing starts from.
+    fn empty(&self) -> SliceRef {
+        match self {
+            MergeOperator::AddInteger => 0i64.encode(),
+            _ => encode_list(&[]),
+        }
+    }
+}
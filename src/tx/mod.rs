@@ -12,10 +12,29 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-pub use transaction::{CommitError, CommitSet, Transaction};
-pub use working_set::WorkingSet;
+pub(crate) use changes::Observers;
+pub use changes::{Change, CommitChanges, RelationChanges, Watch};
+pub(crate) use commit_policy::LockWait;
+pub use commit_policy::{Backoff, CommitPolicy};
+pub use join::{JoinOn, JoinStrategy};
+pub use merge::MergeOperator;
+pub use read_transaction::ReadTransaction;
+pub use relvar::RelVar;
+pub use rules::{Atom, Fact, Predicate, Rule, Term};
+pub use transaction::{
+    CommitError, CommitReceipt, CommitSet, Conflict, DurabilityToken, Transaction,
+};
+pub use working_set::{Savepoint, WorkingSet};
 
+mod changes;
+mod closure;
+mod commit_policy;
+mod join;
+mod merge;
+mod read_set;
+mod read_transaction;
 mod relvar;
+mod rules;
 mod transaction;
 mod tx_tuple;
 mod working_set;
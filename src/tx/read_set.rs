@@ -0,0 +1,171 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound;
+use std::sync::Arc;
+
+use daumtils::SliceRef;
+
+use crate::relbox::CanonicalRelation;
+use crate::RelationId;
+
+/// The queries a serializable transaction performed, kept so they can be re-validated against the
+/// canonical relations at commit time.
+///
+/// Individual tuples that were read are already recorded in the working set as `TxTupleOp::Value`
+/// (and are validated from there); what's recorded here is what's needed to detect *phantoms* --
+/// tuples which would have matched a read had they been committed before it.
+///
+/// Reads are validated against what had been committed to each relation when the transaction
+/// first read it, rather than against the transaction's start: a commit which began (and took its
+/// timestamp) before the transaction did can still land after its reads.
+#[derive(Default)]
+pub(crate) struct ReadSet {
+    /// The timestamp of the latest commit to each relation read, as of the first read of it.
+    /// Commits to a relation land in timestamp order, so anything in it with a later timestamp
+    /// wasn't there to be read.
+    versions: HashMap<RelationId, u64>,
+    domains: HashSet<(RelationId, SliceRef)>,
+    codomains: HashSet<(RelationId, SliceRef)>,
+    ranges: Vec<(RelationId, Bound<SliceRef>, Bound<SliceRef>)>,
+    scans: HashSet<RelationId>,
+}
+
+impl ReadSet {
+    /// Note which commits had been made to the relation as of a read of it, if it's the first.
+    /// (`version` is taken before the read itself, so that a commit landing in between is counted
+    /// as having come after.) Relations which don't exist outside the transaction have none.
+    pub(crate) fn observe<F: FnOnce() -> Option<u64>>(
+        &mut self,
+        relation_id: RelationId,
+        version: F,
+    ) {
+        if relation_id.is_transient_relation() || self.versions.contains_key(&relation_id) {
+            return;
+        }
+        if let Some(version) = version() {
+            self.versions.insert(relation_id, version);
+        }
+    }
+
+    pub(crate) fn read_domain(&mut self, relation_id: RelationId, domain: &SliceRef) {
+        // Transient relations are private to the transaction, so can't be invalidated.
+        if relation_id.is_transient_relation() {
+            return;
+        }
+        self.domains.insert((relation_id, domain.clone()));
+    }
+
+    pub(crate) fn read_codomain(&mut self, relation_id: RelationId, codomain: &SliceRef) {
+        if relation_id.is_transient_relation() {
+            return;
+        }
+        self.codomains.insert((relation_id, codomain.clone()));
+    }
+
+    pub(crate) fn read_range(
+        &mut self,
+        relation_id: RelationId,
+        lower: Bound<&SliceRef>,
+        upper: Bound<&SliceRef>,
+    ) {
+        if relation_id.is_transient_relation() {
+            return;
+        }
+        self.ranges
+            .push((relation_id, lower.cloned(), upper.cloned()));
+    }
+
+    pub(crate) fn read_scan(&mut self, relation_id: RelationId) {
+        if relation_id.is_transient_relation() {
+            return;
+        }
+        self.scans.insert(relation_id);
+    }
+
+    /// The relations the recorded reads were of.
+    pub(crate) fn relations(&self) -> impl Iterator<Item = RelationId> + '_ {
+        self.domains
+            .iter()
+            .chain(&self.codomains)
+            .map(|(relation_id, _)| *relation_id)
+            .chain(self.ranges.iter().map(|(relation_id, _, _)| *relation_id))
+            .chain(self.scans.iter().copied())
+    }
+
+    /// Check that nothing committed since the recorded reads would have changed their results.
+    /// Anything newer than the version of its relation we first read which matches one of them is
+    /// a conflict, and the relation it's in is returned.
+    ///
+    /// `canonical` holds (at least) the relations the reads were of. Relations the transaction
+    /// created itself aren't there yet, and so can't have been changed by anyone else; reads of
+    /// them are skipped.
+    pub(crate) fn validate(
+        &self,
+        canonical: &BTreeMap<RelationId, Arc<CanonicalRelation>>,
+    ) -> Result<(), RelationId> {
+        // (Every relation read was observed first; were one somehow not, treating all of it as new
+        // errs on the side of a conflict.)
+        let seen = |relation_id: &RelationId| self.versions.get(relation_id).copied().unwrap_or(0);
+        // Predicate scans can match anything, so any commit to the relation since we first read it
+        // invalidates them.
+        for relation_id in &self.scans {
+            let Some(relation) = canonical.get(relation_id) else {
+                continue;
+            };
+            let relation = relation.relation.read().unwrap();
+            if relation.ts > seen(relation_id) {
+                return Err(*relation_id);
+            }
+        }
+        for (relation_id, domain) in &self.domains {
+            let Some(relation) = canonical.get(relation_id) else {
+                continue;
+            };
+            let relation = relation.relation.read().unwrap();
+            let tuples = relation
+                .seek_by_domain(domain.clone())
+                .expect("failed to seek for read validation");
+            if tuples.iter().any(|t| t.ts() > seen(relation_id)) {
+                return Err(*relation_id);
+            }
+        }
+        for (relation_id, codomain) in &self.codomains {
+            let Some(relation) = canonical.get(relation_id) else {
+                continue;
+            };
+            let relation = relation.relation.read().unwrap();
+            let tuples = relation
+                .seek_by_codomain(codomain.clone())
+                .expect("failed to seek for read validation");
+            if tuples.iter().any(|t| t.ts() > seen(relation_id)) {
+                return Err(*relation_id);
+            }
+        }
+        for (relation_id, lower, upper) in &self.ranges {
+            let Some(relation) = canonical.get(relation_id) else {
+                continue;
+            };
+            let relation = relation.relation.read().unwrap();
+            let tuples = relation
+                .seek_range_by_domain(lower.as_ref(), upper.as_ref(), false)
+                .expect("failed to seek for read validation");
+            if tuples.iter().any(|t| t.ts() > seen(relation_id)) {
+                return Err(*relation_id);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::collections::HashSet;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use daumtils::SliceRef;
+
+use crate::base_relation::BaseRelation;
+use crate::tuples::TupleRef;
+use crate::{RelationError, RelationId};
+
+/// A read-only view of the database, pinned to the canonical relations as they were when it was
+/// started. There is no working set: all reads are served straight from the pinned relations, and
+/// there is nothing to commit or roll back, so it never contends for the relations' commit locks.
+pub struct ReadTransaction {
+    relations: Vec<Arc<BaseRelation>>,
+}
+
+impl ReadTransaction {
+    pub(crate) fn new(relations: Vec<Arc<BaseRelation>>) -> Self {
+        Self { relations }
+    }
+
+    /// The pinned version of the relation. Relations created after the transaction started aren't
+    /// part of its snapshot, so they're not found, same as ids which were never created.
+    fn relation(&self, relation_id: RelationId) -> Result<&BaseRelation, RelationError> {
+        self.relations
+            .get(relation_id.0)
+            .map(|relation| relation.as_ref())
+            .ok_or(RelationError::RelationNotFound)
+    }
+
+    /// Seek for all tuples that match the given domain.
+    pub fn seek_by_domain(
+        &self,
+        relation_id: RelationId,
+        domain: SliceRef,
+    ) -> Result<HashSet<TupleRef>, RelationError> {
+        self.relation(relation_id)?.seek_by_domain(domain)
+    }
+
+    /// Seek for the single tuple matching the given domain.
+    pub fn seek_unique_by_domain(
+        &self,
+        relation_id: RelationId,
+        domain: SliceRef,
+    ) -> Result<TupleRef, RelationError> {
+        let tuples = self.relation(relation_id)?.seek_by_domain(domain)?;
+        if tuples.len() > 1 {
+            return Err(RelationError::AmbiguousTuple);
+        }
+        tuples
+            .into_iter()
+            .next()
+            .ok_or(RelationError::TupleNotFound)
+    }
+
+    /// Seek for all tuples whose domain falls within the given bounds, in domain order (or reverse
+    /// domain order). Requires an ordered domain index.
+    pub fn seek_range_by_domain(
+        &self,
+        relation_id: RelationId,
+        lower: Bound<&SliceRef>,
+        upper: Bound<&SliceRef>,
+        reverse: bool,
+    ) -> Result<Vec<TupleRef>, RelationError> {
+        self.relation(relation_id)?
+            .seek_range_by_domain(lower, upper, reverse)
+    }
+
+    /// Seek for all tuples that match the given codomain. Requires a secondary index.
+    pub fn seek_by_codomain(
+        &self,
+        relation_id: RelationId,
+        codomain: SliceRef,
+    ) -> Result<HashSet<TupleRef>, RelationError> {
+        let relation = self.relation(relation_id)?;
+        if !relation.info.secondary_indexed {
+            return Err(RelationError::NoSecondaryIndex);
+        }
+        relation.seek_by_codomain(codomain)
+    }
+
+    pub fn predicate_scan<F: Fn(&TupleRef) -> bool>(
+        &self,
+        relation_id: RelationId,
+        f: &F,
+    ) -> Result<Vec<TupleRef>, RelationError> {
+        Ok(self
+            .relation(relation_id)?
+            .predicate_scan(f)
+            .into_iter()
+            .collect())
+    }
+
+    /// Lazily iterate all tuples in the relation.
+    pub fn scan_iter(
+        &self,
+        relation_id: RelationId,
+    ) -> Result<impl Iterator<Item = TupleRef>, RelationError> {
+        Ok(self.relation(relation_id)?.scan_iter())
+    }
+
+    /// Lazily iterate all tuples that match the given domain.
+    pub fn seek_iter(
+        &self,
+        relation_id: RelationId,
+        domain: SliceRef,
+    ) -> Result<impl Iterator<Item = TupleRef>, RelationError> {
+        self.relation(relation_id)?.seek_iter(&domain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use daumtils::SliceRef;
+
+    use crate::index::{AttrType, IndexType};
+    use crate::relbox::{RelBox, RelationInfo};
+    use crate::{RelationError, RelationId};
+
+    fn attr(slice: &[u8]) -> SliceRef {
+        SliceRef::from_bytes(slice)
+    }
+
+    fn test_info(name: &str) -> RelationInfo {
+        RelationInfo {
+            name: name.to_string(),
+            domain_type: AttrType::String,
+            codomain_type: AttrType::String,
+            secondary_indexed: false,
+            unique_domain: true,
+            index_type: IndexType::BTree,
+            codomain_index_type: None,
+            ..Default::default()
+        }
+    }
+
+    fn test_db() -> Arc<RelBox> {
+        RelBox::new(1 << 24, None, &[test_info("test")], 0)
+    }
+
+    #[test]
+    fn read_tx_is_pinned_snapshot() {
+        let db = test_db();
+        let rid = RelationId(0);
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(rid, attr(b"a"), attr(b"1")).unwrap();
+        tx.insert_tuple(rid, attr(b"b"), attr(b"1")).unwrap();
+        tx.commit().unwrap();
+
+        let read_tx = db.start_read_tx();
+
+        let tx = db.clone().start_tx();
+        tx.update_by_domain(rid, attr(b"a"), attr(b"2")).unwrap();
+        tx.remove_by_domain(rid, attr(b"b")).unwrap();
+        tx.insert_tuple(rid, attr(b"c"), attr(b"2")).unwrap();
+        tx.commit().unwrap();
+
+        // The read transaction still sees the world as of when it started...
+        let a = read_tx.seek_unique_by_domain(rid, attr(b"a")).unwrap();
+        assert_eq!(a.codomain().as_slice(), b"1");
+        assert!(read_tx.seek_unique_by_domain(rid, attr(b"b")).is_ok());
+        assert_eq!(
+            read_tx.seek_unique_by_domain(rid, attr(b"c")).unwrap_err(),
+            RelationError::TupleNotFound
+        );
+        assert_eq!(read_tx.predicate_scan(rid, &|_| true).unwrap().len(), 2);
+        assert_eq!(read_tx.scan_iter(rid).unwrap().count(), 2);
+        let range = read_tx
+            .seek_range_by_domain(
+                rid,
+                std::ops::Bound::Unbounded,
+                std::ops::Bound::Unbounded,
+                false,
+            )
+            .unwrap();
+        assert_eq!(range.len(), 2);
+
+        // ... while a new one sees the committed changes.
+        let read_tx = db.start_read_tx();
+        let a = read_tx.seek_unique_by_domain(rid, attr(b"a")).unwrap();
+        assert_eq!(a.codomain().as_slice(), b"2");
+        assert_eq!(read_tx.seek_iter(rid, attr(b"b")).unwrap().count(), 0);
+        assert_eq!(read_tx.seek_iter(rid, attr(b"c")).unwrap().count(), 1);
+        assert_eq!(
+            read_tx.seek_by_codomain(rid, attr(b"2")).unwrap_err(),
+            RelationError::NoSecondaryIndex
+        );
+    }
+
+    #[test]
+    fn read_tx_unknown_relation() {
+        let db = test_db();
+        let read_tx = db.start_read_tx();
+
+        let tx = db.clone().start_tx();
+        let created = tx.create_relation(test_info("created")).unwrap();
+        tx.insert_tuple(created, attr(b"a"), attr(b"1")).unwrap();
+        tx.commit().unwrap();
+
+        // Neither a relation created after the read transaction started, nor one that was never
+        // created, is in its snapshot.
+        for rid in [created, RelationId(99)] {
+            assert_eq!(
+                read_tx.seek_by_domain(rid, attr(b"a")).unwrap_err(),
+                RelationError::RelationNotFound
+            );
+            assert!(matches!(
+                read_tx.scan_iter(rid),
+                Err(RelationError::RelationNotFound)
+            ));
+            assert_eq!(
+                read_tx.predicate_scan(rid, &|_| true).unwrap_err(),
+                RelationError::RelationNotFound
+            );
+        }
+
+        let read_tx = db.start_read_tx();
+        assert_eq!(read_tx.scan_iter(created).unwrap().count(), 1);
+    }
+}
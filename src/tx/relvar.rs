@@ -13,10 +13,15 @@
 //
 
 use std::collections::HashSet;
+use std::ops::{Bound, RangeBounds};
 
 use daumtils::SliceRef;
 
+use crate::codec::{encode_str, Codec};
+use crate::index::AttrType;
+use crate::tuples::attributes;
 use crate::tuples::TupleRef;
+use crate::tx::join::JoinOn;
 use crate::tx::transaction::Transaction;
 use crate::{RelationError, RelationId};
 
@@ -39,17 +44,96 @@ impl<'a> RelVar<'a> {
         self.tx.seek_unique_by_domain(self.id, domain)
     }
 
+    /// Seek for all tuples whose domain falls within `range`, in ascending domain order. Requires an
+    /// ordered (BTree or ART) domain index.
+    pub fn seek_range_by_domain<R: RangeBounds<SliceRef>>(
+        &self,
+        range: R,
+    ) -> Result<Vec<TupleRef>, RelationError> {
+        self.tx
+            .seek_range_by_domain(self.id, range.start_bound(), range.end_bound(), false)
+    }
+
+    /// As `seek_range_by_domain`, but in descending domain order.
+    pub fn seek_range_by_domain_rev<R: RangeBounds<SliceRef>>(
+        &self,
+        range: R,
+    ) -> Result<Vec<TupleRef>, RelationError> {
+        self.tx
+            .seek_range_by_domain(self.id, range.start_bound(), range.end_bound(), true)
+    }
+
     /// Seek for tuples by their indexed codomain value, if there's an index. Panics if there is no
     /// secondary index.
     pub fn seek_by_codomain(&self, codomain: SliceRef) -> Result<HashSet<TupleRef>, RelationError> {
         self.tx.seek_by_codomain(self.id, codomain)
     }
 
+    /// Seek for tuples of an N-ary relation by their (complete, possibly composite) key.
+    pub fn seek_by_key(&self, key: &[SliceRef]) -> Result<HashSet<TupleRef>, RelationError> {
+        let domain = self.tx.relation_info(self.id)?.encode_key(key)?;
+        self.seek_by_domain(domain)
+    }
+
+    /// Seek for all tuples of an N-ary relation whose key starts with the given attributes, in
+    /// key order. Requires an ordered domain index.
+    pub fn seek_by_key_prefix(&self, prefix: &[SliceRef]) -> Result<Vec<TupleRef>, RelationError> {
+        let info = self.tx.relation_info(self.id)?;
+        if prefix.len() > info.key_arity() {
+            return Err(RelationError::AttributeCount(
+                info.key_arity(),
+                prefix.len(),
+            ));
+        }
+        if prefix.len() == info.key_arity() {
+            let domain = info.encode_key(prefix)?;
+            return self.seek_range_by_domain(domain.clone()..=domain);
+        }
+        if prefix.is_empty() {
+            return self.seek_range_by_domain(..);
+        }
+        let lower = attributes::pack_prefix(prefix);
+        let upper = attributes::prefix_upper_bound(&lower);
+        self.seek_range_by_domain(SliceRef::from_vec(lower)..SliceRef::from_vec(upper))
+    }
+
+    /// Seek for tuples through the relation's `index`th attribute index, by the values of the
+    /// attributes it's on.
+    pub fn seek_by_attributes(
+        &self,
+        index: usize,
+        values: &[SliceRef],
+    ) -> Result<HashSet<TupleRef>, RelationError> {
+        let info = self.tx.relation_info(self.id)?;
+        let Some(attribute_index) = info.attribute_indexes.get(index) else {
+            return Err(RelationError::NoSecondaryIndex);
+        };
+        if values.len() != attribute_index.attributes.len() {
+            return Err(RelationError::AttributeCount(
+                attribute_index.attributes.len(),
+                values.len(),
+            ));
+        }
+        self.tx
+            .seek_by_attributes(self.id, index, attributes::pack(values))
+    }
+
     /// Insert a tuple into the relation.
     pub fn insert_tuple(&self, domain: SliceRef, codomain: SliceRef) -> Result<(), RelationError> {
         self.tx.insert_tuple(self.id, domain, codomain)
     }
 
+    /// Insert a tuple into an N-ary relation, given all its attributes in declaration order.
+    pub fn insert_attributes(&self, values: &[SliceRef]) -> Result<(), RelationError> {
+        let (domain, codomain) = self.tx.relation_info(self.id)?.encode_tuple(values)?;
+        self.insert_tuple(domain, codomain)
+    }
+
+    /// The attributes of one of this relation's tuples, in declaration order.
+    pub fn attributes_of(&self, tuple: &TupleRef) -> Result<Vec<SliceRef>, RelationError> {
+        self.tx.relation_info(self.id)?.decode_tuple(tuple)
+    }
+
     /// Update a tuple in the relation.
     pub fn update_by_domain(
         &self,
@@ -68,15 +152,159 @@ impl<'a> RelVar<'a> {
         self.tx.upsert_by_domain(self.id, domain, codomain)
     }
 
+    /// Merge `delta` into the codomain for `domain`, using the merge operator set for the relation
+    /// with `RelBox::set_merge_operator` (inserting if there's no tuple yet). Unlike an upsert,
+    /// this doesn't conflict with concurrent merges into the same tuple: the merge is redone on
+    /// top of whatever has been committed meanwhile.
+    pub fn merge_by_domain(&self, domain: SliceRef, delta: SliceRef) -> Result<(), RelationError> {
+        self.tx.merge_by_domain(self.id, domain, delta)
+    }
+
     /// Remove a tuple from the relation.
     pub fn remove_by_domain(&self, domain: SliceRef) -> Result<(), RelationError> {
         self.tx.remove_by_domain(self.id, domain)
     }
 
+    /// Remove the tuple pairing `domain` with `codomain`, leaving any others for the domain. Useful
+    /// for relations without a unique domain, where a domain may map to many codomains.
+    pub fn remove_tuple(&self, domain: SliceRef, codomain: SliceRef) -> Result<(), RelationError> {
+        self.tx.remove_tuple(self.id, domain, codomain)
+    }
+
     pub fn predicate_scan<F: Fn(&TupleRef) -> bool>(
         &self,
         f: &F,
     ) -> Result<Vec<TupleRef>, RelationError> {
         self.tx.predicate_scan(self.id, f)
     }
+
+    /// Lazily iterate the tuples in this relation. Unlike `predicate_scan`, nothing is collected up
+    /// front, so e.g. `find` stops as soon as it has a match.
+    pub fn scan_iter(&self) -> Result<impl Iterator<Item = TupleRef>, RelationError> {
+        self.tx.scan_iter(self.id)
+    }
+
+    /// Lazily iterate the tuples matching the given domain.
+    pub fn seek_iter(
+        &self,
+        domain: SliceRef,
+    ) -> Result<impl Iterator<Item = TupleRef>, RelationError> {
+        self.tx.seek_iter(self.id, domain)
+    }
+
+    /// Join this relation's codomain against `other`'s domain (or codomain), producing the
+    /// matching (ours, theirs) tuple pairs. See `Transaction::join`.
+    pub fn join(
+        &self,
+        other: RelationId,
+        on: JoinOn,
+    ) -> Result<Vec<(TupleRef, TupleRef)>, RelationError> {
+        self.tx.join(self.id, other, on)
+    }
+
+    /// All tuples reachable from `start` by following domain -> codomain edges, in BFS order.
+    /// See `Transaction::transitive_closure`.
+    pub fn transitive_closure(
+        &self,
+        start: SliceRef,
+        max_depth: Option<usize>,
+    ) -> Result<Vec<TupleRef>, RelationError> {
+        self.tx.transitive_closure(self.id, start, max_depth)
+    }
+
+    /// All tuples which transitively point at `start`, found through the codomain index.
+    pub fn transitive_closure_reverse(
+        &self,
+        start: SliceRef,
+        max_depth: Option<usize>,
+    ) -> Result<Vec<TupleRef>, RelationError> {
+        self.tx
+            .transitive_closure_reverse(self.id, start, max_depth)
+    }
+}
+
+/// Typed access, for relations whose domain is stored in its `codec` encoding, so that e.g. range
+/// scans come back in the order of the values rather than of their bytes.
+impl<'a> RelVar<'a> {
+    /// Check that `encoded` is a domain value for this relation's domain type.
+    fn typed_domain(
+        &self,
+        attr_type: AttrType,
+        encoded: SliceRef,
+    ) -> Result<SliceRef, RelationError> {
+        let domain_type = self.tx.relation_info(self.id)?.domain_type;
+        if domain_type != attr_type {
+            return Err(RelationError::TypeMismatch(domain_type));
+        }
+        Ok(encoded)
+    }
+
+    /// Insert a tuple with a typed domain.
+    pub fn insert_typed<D: Codec>(
+        &self,
+        domain: &D,
+        codomain: SliceRef,
+    ) -> Result<(), RelationError> {
+        let domain = self.typed_domain(D::ATTR_TYPE, domain.encode())?;
+        self.insert_tuple(domain, codomain)
+    }
+
+    /// Seek for the tuples with a typed domain.
+    pub fn seek_typed<D: Codec>(&self, domain: &D) -> Result<HashSet<TupleRef>, RelationError> {
+        let domain = self.typed_domain(D::ATTR_TYPE, domain.encode())?;
+        self.seek_by_domain(domain)
+    }
+
+    /// Seek for all tuples whose (typed) domain falls within `range`, in ascending order. Requires
+    /// an ordered domain index.
+    pub fn range_typed<D: Codec, R: RangeBounds<D>>(
+        &self,
+        range: R,
+    ) -> Result<Vec<TupleRef>, RelationError> {
+        let encode = |bound: Bound<&D>| -> Result<Bound<SliceRef>, RelationError> {
+            Ok(match bound {
+                Bound::Included(v) => Bound::Included(self.typed_domain(D::ATTR_TYPE, v.encode())?),
+                Bound::Excluded(v) => Bound::Excluded(self.typed_domain(D::ATTR_TYPE, v.encode())?),
+                Bound::Unbounded => Bound::Unbounded,
+            })
+        };
+        let lower = encode(range.start_bound())?;
+        let upper = encode(range.end_bound())?;
+        self.tx
+            .seek_range_by_domain(self.id, lower.as_ref(), upper.as_ref(), false)
+    }
+
+    pub fn insert_i64(&self, domain: i64, codomain: SliceRef) -> Result<(), RelationError> {
+        self.insert_typed(&domain, codomain)
+    }
+
+    pub fn seek_i64(&self, domain: i64) -> Result<HashSet<TupleRef>, RelationError> {
+        self.seek_typed(&domain)
+    }
+
+    pub fn range_i64<R: RangeBounds<i64>>(&self, range: R) -> Result<Vec<TupleRef>, RelationError> {
+        self.range_typed(range)
+    }
+
+    pub fn insert_f64(&self, domain: f64, codomain: SliceRef) -> Result<(), RelationError> {
+        self.insert_typed(&domain, codomain)
+    }
+
+    pub fn seek_f64(&self, domain: f64) -> Result<HashSet<TupleRef>, RelationError> {
+        self.seek_typed(&domain)
+    }
+
+    pub fn range_f64<R: RangeBounds<f64>>(&self, range: R) -> Result<Vec<TupleRef>, RelationError> {
+        self.range_typed(range)
+    }
+
+    pub fn insert_str(&self, domain: &str, codomain: SliceRef) -> Result<(), RelationError> {
+        let domain = self.typed_domain(AttrType::String, encode_str(domain))?;
+        self.insert_tuple(domain, codomain)
+    }
+
+    pub fn seek_str(&self, domain: &str) -> Result<HashSet<TupleRef>, RelationError> {
+        let domain = self.typed_domain(AttrType::String, encode_str(domain))?;
+        self.seek_by_domain(domain)
+    }
 }
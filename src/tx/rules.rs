@@ -0,0 +1,432 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! A small datalog-style rule engine. Rules are conjunctions of binary atoms over base relations
+//! (or over other rules' derived predicates), with variables shared between atoms unified by value.
+//! Rules are evaluated semi-naively against a transaction, so base relation lookups go through the
+//! working set and its domain / codomain indexes.
+//!
+//! Only positive (negation-free) rules are supported, so there is no need for stratification.
+
+use std::collections::{HashMap, HashSet};
+
+use daumtils::SliceRef;
+
+use crate::tx::transaction::Transaction;
+use crate::{RelationError, RelationId};
+
+/// A derived (domain, codomain) pair.
+pub type Fact = (SliceRef, SliceRef);
+
+/// Variables bound so far while solving a rule body.
+type Bindings = HashMap<String, SliceRef>;
+
+/// A domain or codomain position in an atom or rule head.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Term {
+    Var(String),
+    Const(SliceRef),
+}
+
+impl Term {
+    pub fn var(name: &str) -> Self {
+        Term::Var(name.to_string())
+    }
+
+    pub fn constant(value: SliceRef) -> Self {
+        Term::Const(value)
+    }
+
+    /// The value of this term under `bindings`, if it has one.
+    fn resolve(&self, bindings: &Bindings) -> Option<SliceRef> {
+        match self {
+            Term::Var(name) => bindings.get(name).cloned(),
+            Term::Const(value) => Some(value.clone()),
+        }
+    }
+
+    /// Unify this term with `value`, binding it if it's a free variable. Returns false on a
+    /// mismatch.
+    fn unify(&self, value: &SliceRef, bindings: &mut Bindings) -> bool {
+        match self {
+            Term::Const(c) => c == value,
+            Term::Var(name) => match bindings.get(name) {
+                Some(bound) => bound == value,
+                None => {
+                    bindings.insert(name.clone(), value.clone());
+                    true
+                }
+            },
+        }
+    }
+}
+
+/// What an atom ranges over.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Predicate {
+    /// A base relation, read through the transaction.
+    Relation(RelationId),
+    /// The facts derived by the rules with this head name.
+    Derived(String),
+}
+
+/// `predicate(domain, codomain)`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Atom {
+    pub predicate: Predicate,
+    pub domain: Term,
+    pub codomain: Term,
+}
+
+impl Atom {
+    pub fn relation(relation_id: RelationId, domain: Term, codomain: Term) -> Self {
+        Self {
+            predicate: Predicate::Relation(relation_id),
+            domain,
+            codomain,
+        }
+    }
+
+    pub fn derived(name: &str, domain: Term, codomain: Term) -> Self {
+        Self {
+            predicate: Predicate::Derived(name.to_string()),
+            domain,
+            codomain,
+        }
+    }
+}
+
+/// `head(domain, codomain) :- body[0], body[1], ...`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub head: String,
+    pub domain: Term,
+    pub codomain: Term,
+    pub body: Vec<Atom>,
+}
+
+impl Rule {
+    pub fn new(head: &str, domain: Term, codomain: Term, body: Vec<Atom>) -> Self {
+        Self {
+            head: head.to_string(),
+            domain,
+            codomain,
+            body,
+        }
+    }
+
+    /// Every variable in the head must be bound by some atom in the body.
+    fn check_range_restricted(&self) -> Result<(), RelationError> {
+        for term in [&self.domain, &self.codomain] {
+            let Term::Var(name) = term else {
+                continue;
+            };
+            let bound = self
+                .body
+                .iter()
+                .any(|atom| atom.domain == *term || atom.codomain == *term);
+            if !bound {
+                return Err(RelationError::UnboundVariable(name.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Transaction {
+    /// Evaluate `rules` to a fixpoint, returning the facts derived for each head name.
+    ///
+    /// Evaluation is semi-naive: after the first round, each rule is only re-fired with one of its
+    /// derived atoms restricted to the facts that were new in the previous round.
+    pub fn evaluate_rules(
+        &self,
+        rules: &[Rule],
+    ) -> Result<HashMap<String, HashSet<Fact>>, RelationError> {
+        for rule in rules {
+            rule.check_range_restricted()?;
+        }
+
+        let mut scans = HashMap::new();
+        let mut total: HashMap<String, HashSet<Fact>> = rules
+            .iter()
+            .map(|rule| (rule.head.clone(), HashSet::new()))
+            .collect();
+
+        let mut delta: HashMap<String, HashSet<Fact>> = HashMap::new();
+        for rule in rules {
+            for fact in self.fire(rule, None, &total, &delta, &mut scans)? {
+                delta.entry(rule.head.clone()).or_default().insert(fact);
+            }
+        }
+
+        while !delta.is_empty() {
+            for (head, facts) in &delta {
+                total.get_mut(head).unwrap().extend(facts.iter().cloned());
+            }
+            let mut next: HashMap<String, HashSet<Fact>> = HashMap::new();
+            for rule in rules {
+                for (i, atom) in rule.body.iter().enumerate() {
+                    let Predicate::Derived(name) = &atom.predicate else {
+                        continue;
+                    };
+                    if !delta.contains_key(name) {
+                        continue;
+                    }
+                    for fact in self.fire(rule, Some(i), &total, &delta, &mut scans)? {
+                        if !total[&rule.head].contains(&fact) {
+                            next.entry(rule.head.clone()).or_default().insert(fact);
+                        }
+                    }
+                }
+            }
+            delta = next;
+        }
+
+        Ok(total)
+    }
+
+    /// Solve the body of `rule` left to right, producing its head facts. The derived atom at
+    /// `delta_position` (if any) ranges over `delta`; all others range over `total`.
+    fn fire(
+        &self,
+        rule: &Rule,
+        delta_position: Option<usize>,
+        total: &HashMap<String, HashSet<Fact>>,
+        delta: &HashMap<String, HashSet<Fact>>,
+        scans: &mut HashMap<RelationId, Vec<Fact>>,
+    ) -> Result<Vec<Fact>, RelationError> {
+        let mut frames = vec![Bindings::new()];
+        for (i, atom) in rule.body.iter().enumerate() {
+            let mut next = vec![];
+            for frame in &frames {
+                let domain = atom.domain.resolve(frame);
+                let codomain = atom.codomain.resolve(frame);
+                let candidates = match &atom.predicate {
+                    Predicate::Relation(relation_id) => {
+                        self.lookup(*relation_id, domain, codomain, scans)?
+                    }
+                    Predicate::Derived(name) => {
+                        let facts = if delta_position == Some(i) {
+                            delta
+                        } else {
+                            total
+                        };
+                        facts
+                            .get(name)
+                            .map(|facts| facts.iter().cloned().collect())
+                            .unwrap_or_default()
+                    }
+                };
+                for (d, c) in candidates {
+                    let mut bindings = frame.clone();
+                    if atom.domain.unify(&d, &mut bindings)
+                        && atom.codomain.unify(&c, &mut bindings)
+                    {
+                        next.push(bindings);
+                    }
+                }
+            }
+            frames = next;
+        }
+
+        // Range restriction was checked up front, so the head always resolves.
+        Ok(frames
+            .iter()
+            .map(|frame| {
+                (
+                    rule.domain.resolve(frame).unwrap(),
+                    rule.codomain.resolve(frame).unwrap(),
+                )
+            })
+            .collect())
+    }
+
+    /// Fetch the candidate tuples of a base relation for an atom, using the domain index if the
+    /// domain is bound, the codomain index if the codomain is bound and indexed, and otherwise a
+    /// (cached) full scan.
+    fn lookup(
+        &self,
+        relation_id: RelationId,
+        domain: Option<SliceRef>,
+        codomain: Option<SliceRef>,
+        scans: &mut HashMap<RelationId, Vec<Fact>>,
+    ) -> Result<Vec<Fact>, RelationError> {
+        let secondary_indexed = self.relation_info(relation_id)?.secondary_indexed;
+        let tuples = match (domain, codomain) {
+            (Some(domain), _) => self.seek_by_domain(relation_id, domain)?,
+            (None, Some(codomain)) if secondary_indexed => {
+                self.seek_by_codomain(relation_id, codomain)?
+            }
+            _ => {
+                if let Some(scan) = scans.get(&relation_id) {
+                    return Ok(scan.clone());
+                }
+                let scan: Vec<Fact> = self
+                    .predicate_scan(relation_id, &|_| true)?
+                    .iter()
+                    .map(|t| (t.domain(), t.codomain()))
+                    .collect();
+                scans.insert(relation_id, scan.clone());
+                return Ok(scan);
+            }
+        };
+        Ok(tuples.iter().map(|t| (t.domain(), t.codomain())).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    use daumtils::SliceRef;
+
+    use crate::index::{AttrType, IndexType};
+    use crate::relbox::{RelBox, RelationInfo};
+    use crate::tx::rules::{Atom, Fact, Rule, Term};
+    use crate::{RelationError, RelationId};
+
+    fn attr(slice: &[u8]) -> SliceRef {
+        SliceRef::from_bytes(slice)
+    }
+
+    /// 0: object -> parent (secondary indexed), 1: object -> name
+    fn test_db() -> Arc<RelBox> {
+        let relation = |name: &str, secondary_indexed: bool| RelationInfo {
+            name: name.to_string(),
+            domain_type: AttrType::String,
+            codomain_type: AttrType::String,
+            secondary_indexed,
+            unique_domain: true,
+            index_type: IndexType::Hash,
+            codomain_index_type: secondary_indexed.then_some(IndexType::Hash),
+            ..Default::default()
+        };
+        RelBox::new(
+            1 << 24,
+            None,
+            &[relation("parent", true), relation("name", false)],
+            0,
+        )
+    }
+
+    fn facts(facts: &HashSet<Fact>) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut facts: Vec<_> = facts
+            .iter()
+            .map(|(d, c)| (d.as_slice().to_vec(), c.as_slice().to_vec()))
+            .collect();
+        facts.sort();
+        facts
+    }
+
+    /// ancestor(x, y) :- parent(x, y).
+    /// ancestor(x, z) :- parent(x, y), ancestor(y, z).
+    fn ancestor_rules(parent: RelationId) -> Vec<Rule> {
+        vec![
+            Rule::new(
+                "ancestor",
+                Term::var("x"),
+                Term::var("y"),
+                vec![Atom::relation(parent, Term::var("x"), Term::var("y"))],
+            ),
+            Rule::new(
+                "ancestor",
+                Term::var("x"),
+                Term::var("z"),
+                vec![
+                    Atom::relation(parent, Term::var("x"), Term::var("y")),
+                    Atom::derived("ancestor", Term::var("y"), Term::var("z")),
+                ],
+            ),
+        ]
+    }
+
+    #[test]
+    fn recursive_ancestors() {
+        let db = test_db();
+        let parent = RelationId(0);
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(parent, attr(b"a"), attr(b"root")).unwrap();
+        tx.insert_tuple(parent, attr(b"b"), attr(b"a")).unwrap();
+        tx.commit().unwrap();
+
+        // Uncommitted writes are visible to the rules, and the cycle back to `b` terminates.
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(parent, attr(b"root"), attr(b"b")).unwrap();
+
+        let derived = tx.evaluate_rules(&ancestor_rules(parent)).unwrap();
+        let mut expected = vec![];
+        let objects: [&[u8]; 3] = [b"a", b"b", b"root"];
+        for x in objects {
+            for y in objects {
+                expected.push((x.to_vec(), y.to_vec()));
+            }
+        }
+        expected.sort();
+        assert_eq!(facts(&derived["ancestor"]), expected);
+    }
+
+    /// named_child(name, p) :- parent(c, p), name(c, name), with `p` fixed to a constant so the
+    /// parent relation is probed through its codomain index.
+    #[test]
+    fn join_with_constant() {
+        let db = test_db();
+        let (parent, name) = (RelationId(0), RelationId(1));
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(parent, attr(b"a"), attr(b"root")).unwrap();
+        tx.insert_tuple(parent, attr(b"b"), attr(b"root")).unwrap();
+        tx.insert_tuple(parent, attr(b"c"), attr(b"a")).unwrap();
+        tx.insert_tuple(name, attr(b"a"), attr(b"Thing A")).unwrap();
+        tx.insert_tuple(name, attr(b"b"), attr(b"Thing B")).unwrap();
+        tx.insert_tuple(name, attr(b"c"), attr(b"Thing C")).unwrap();
+
+        let rules = vec![Rule::new(
+            "named_child",
+            Term::var("n"),
+            Term::constant(attr(b"root")),
+            vec![
+                Atom::relation(parent, Term::var("c"), Term::constant(attr(b"root"))),
+                Atom::relation(name, Term::var("c"), Term::var("n")),
+            ],
+        )];
+        let derived = tx.evaluate_rules(&rules).unwrap();
+        assert_eq!(
+            facts(&derived["named_child"]),
+            vec![
+                (b"Thing A".to_vec(), b"root".to_vec()),
+                (b"Thing B".to_vec(), b"root".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unbound_head_variable() {
+        let db = test_db();
+        let tx = db.clone().start_tx();
+        let rules = vec![Rule::new(
+            "bad",
+            Term::var("x"),
+            Term::var("unbound"),
+            vec![Atom::relation(
+                RelationId(0),
+                Term::var("x"),
+                Term::var("y"),
+            )],
+        )];
+        assert_eq!(
+            tx.evaluate_rules(&rules).unwrap_err(),
+            RelationError::UnboundVariable("unbound".to_string())
+        );
+    }
+}
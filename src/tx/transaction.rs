@@ -13,10 +13,12 @@
 //
 
 use std::cell::RefCell;
-use std::collections::HashSet;
-use std::sync::{Arc, RwLockWriteGuard};
-use std::thread::yield_now;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::ops::{Bound, Range};
+use std::sync::{Arc, RwLock};
 
+use crossbeam_channel::{bounded, Receiver, Sender};
 use thiserror::Error;
 
 use daumtils::{BitArray, Bitset64};
@@ -24,14 +26,21 @@ use daumtils::{PhantomUnsync, SliceRef};
 
 use crate::base_relation::BaseRelation;
 use crate::paging::TupleBox;
-use crate::relbox::RelBox;
+use crate::relbox::{CanonicalRelation, RelBox, RelationInfo, SchemaGuard, SequenceKind};
 use crate::tuples::TupleRef;
+use crate::tx::changes::{Change, CommitChanges, Observers, RelationChanges};
+use crate::tx::commit_policy::CommitPolicy;
+use crate::tx::read_set::ReadSet;
 use crate::tx::relvar::RelVar;
 use crate::tx::tx_tuple::TxTupleOp;
-use crate::tx::working_set::WorkingSet;
+use crate::tx::working_set::{Savepoint, SchemaChange, TupleIter, WorkingSet, MAX_RELATIONS};
 use crate::{RelationError, RelationId};
 
 /// A versioned transaction, which is a fork of the current canonical base relations.
+///
+/// A transaction can be moved between threads (or held across an `.await` on a multi-threaded
+/// executor), so it can be started on one thread and committed on another. It can't be shared
+/// between them, though: it's `Send`, but not `Sync`.
 pub struct Transaction {
     /// Where we came from, for referencing back to the base relations.
     db: Arc<RelBox>,
@@ -39,70 +48,257 @@ pub struct Transaction {
     /// to the transaction, and represents the set of values that will be committed to the base
     /// relations at commit time.
     pub(crate) working_set: RefCell<Option<WorkingSet>>,
-
-    unsync: PhantomUnsync,
+    /// The transaction's own commit policy, if it's been given one rather than following the
+    /// database's.
+    commit_policy: RefCell<Option<CommitPolicy>>,
 }
 
 /// Errors which can occur during a commit.
 #[derive(Debug, Clone, Eq, PartialEq, Error)]
 pub enum CommitError {
-    /// A version conflict was detected during the preparation of the commit set.
-    #[error("Version conflict")]
-    TupleVersionConflict,
-    /// Multiple writers attempted to modify the same tuple at the same time, and our validated
-    /// commit set was potentially invalidated by a concurrent commit.
-    #[error("Relation contention conflict")]
-    RelationContentionConflict,
+    /// A version conflict was detected during the preparation of the commit set: a tuple the
+    /// transaction updated or removed was changed by a concurrent commit.
+    #[error("Version conflict on {0}")]
+    TupleVersionConflict(Conflict),
+    /// Another commit to one of the relations the transaction touched was in progress for as long
+    /// as the commit policy allowed waiting, or for every one of the `attempts` it allowed.
+    #[error("Relation contention conflict after {attempts} attempts")]
+    RelationContentionConflict { attempts: u32 },
     /// A unique constraint violation was detected during the preparation of the commit set.
     /// This can happen when the transaction has prepared an insert into a relation that has already been
-    /// inserted into for the same unique domain.
-    #[error("Unique constraint violation")]
-    UniqueConstraintViolation,
+    /// inserted into for the same unique domain (or, without a unique domain, the same domain and
+    /// codomain).
+    #[error("Unique constraint violation on {0}")]
+    UniqueConstraintViolation(Conflict),
+    /// A serializable transaction read something which a concurrent commit has since changed, so
+    /// committing it could produce a result no serial ordering of the transactions would.
+    #[error("Serialization conflict")]
+    SerializationConflict,
+    /// The schema changed underneath the transaction in a way that's incompatible with what it did:
+    /// it wrote to a relation which has since been dropped, or created a relation whose id has
+    /// since been taken by another.
+    #[error("Schema conflict")]
+    SchemaConflict,
+    /// The transaction moved a transactional sequence which a concurrent commit (or an increment
+    /// outside of any transaction) has moved since.
+    #[error("Sequence {sequence} moved by a concurrent commit")]
+    SequenceConflict { sequence: usize },
+    /// The transaction committed, but whether it was made durable is unknown: the write-ahead log
+    /// writer stopped (or failed) before confirming it.
+    #[error("Commit not confirmed durable")]
+    NotDurable,
+}
+
+/// Where a commit conflicted with a concurrent one, for diagnosing contention.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Conflict {
+    pub relation_id: RelationId,
+    pub relation_name: String,
+    /// The domain of the contended tuple.
+    pub domain: SliceRef,
+    /// The timestamp as of which the transaction read (or created) its version of the tuple.
+    pub read_ts: u64,
+    /// The timestamp of the concurrently committed tuple which won out over ours, if there is one.
+    /// (There isn't if it was removed.)
+    pub winning_ts: Option<u64>,
+}
+
+impl Conflict {
+    fn new(
+        relation: &BaseRelation,
+        domain: SliceRef,
+        read_ts: u64,
+        winning_ts: Option<u64>,
+    ) -> Self {
+        Self {
+            relation_id: relation.id,
+            relation_name: relation.info.name.clone(),
+            domain,
+            read_ts,
+            winning_ts,
+        }
+    }
+}
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}), domain {:?}, read at {}",
+            self.relation_name,
+            self.relation_id.0,
+            self.domain.as_slice(),
+            self.read_ts
+        )?;
+        if let Some(winning_ts) = self.winning_ts {
+            write!(f, ", committed at {winning_ts}")?;
+        }
+        Ok(())
+    }
+}
+
+/// What a successful commit reports.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CommitReceipt {
+    /// The timestamp the transaction committed at.
+    pub ts: u64,
+    /// How many attempts it took, per the commit policy.
+    pub attempts: u32,
+}
+
+/// Returned by a commit, to wait on until the commit is durable: until its write-ahead log entry
+/// has been written and synced.
+pub struct DurabilityToken {
+    receipt: CommitReceipt,
+    durable: Receiver<()>,
+}
+
+impl DurabilityToken {
+    /// The timestamp the transaction committed at.
+    pub fn ts(&self) -> u64 {
+        self.receipt.ts
+    }
+
+    /// What the commit reported.
+    pub fn receipt(&self) -> CommitReceipt {
+        self.receipt
+    }
+
+    /// Block until the commit is durable.
+    pub fn wait(self) -> Result<(), CommitError> {
+        self.durable.recv().map_err(|_| CommitError::NotDurable)
+    }
 }
 
 impl Transaction {
     pub fn new(ts: u64, slotbox: Arc<TupleBox>, db: Arc<RelBox>) -> Self {
-        let ws = WorkingSet::new(slotbox.clone(), &db.relation_info(), ts);
+        let ws = WorkingSet::new(slotbox.clone(), &db.schema(), ts);
 
         Self {
             db,
             working_set: RefCell::new(Some(ws)),
-            unsync: Default::default(),
+            commit_policy: RefCell::new(None),
+        }
+    }
+
+    /// As `new`, but with serializable rather than snapshot isolation: the transaction's reads are
+    /// tracked, and re-validated at commit.
+    pub fn new_serializable(ts: u64, slotbox: Arc<TupleBox>, db: Arc<RelBox>) -> Self {
+        let tx = Self::new(ts, slotbox, db);
+        tx.working_set.borrow_mut().as_mut().unwrap().read_set = Some(ReadSet::default());
+        tx
+    }
+
+    /// Increment this sequence and return its previous value. For a transactional sequence, the
+    /// increment takes effect when the transaction commits; otherwise straight away.
+    pub fn increment_sequence(&self, sequence_number: usize) -> Result<i64, RelationError> {
+        Ok(self.reserve_sequence(sequence_number, 1)?.start)
+    }
+
+    /// Take the next `count` values of this sequence, as if it were incremented that many times,
+    /// and return them.
+    pub fn reserve_sequence(
+        &self,
+        sequence_number: usize,
+        count: u32,
+    ) -> Result<Range<i64>, RelationError> {
+        let sequence = self.db.sequence(sequence_number)?;
+        if sequence.kind == SequenceKind::Immediate {
+            return self.db.clone().reserve_sequence(sequence_number, count);
+        }
+        let mut ws = self.working_set.borrow_mut();
+        Ok(ws
+            .as_mut()
+            .unwrap()
+            .reserve_sequence(sequence_number, &sequence, count))
+    }
+
+    /// The current value of this sequence, including this transaction's own increments to it if
+    /// it's transactional.
+    pub fn sequence_current(&self, sequence_number: usize) -> Result<i64, RelationError> {
+        let sequence = self.db.sequence(sequence_number)?;
+        if sequence.kind == SequenceKind::Immediate {
+            return self.db.clone().sequence_current(sequence_number);
         }
+        let ws = self.working_set.borrow();
+        Ok(ws
+            .as_ref()
+            .unwrap()
+            .sequence_current(sequence_number, &sequence))
+    }
+
+    /// Update this sequence to `value` iff `value` is greater than its current value, taking
+    /// effect as an increment would.
+    pub fn update_sequence_max(
+        &self,
+        sequence_number: usize,
+        value: i64,
+    ) -> Result<(), RelationError> {
+        let sequence = self.db.sequence(sequence_number)?;
+        if sequence.kind == SequenceKind::Immediate {
+            return self.db.clone().update_sequence_max(sequence_number, value);
+        }
+        let mut ws = self.working_set.borrow_mut();
+        ws.as_mut()
+            .unwrap()
+            .update_sequence_max(sequence_number, &sequence, value);
+        Ok(())
+    }
+
+    /// Follow `policy` when committing, rather than the database's commit policy.
+    pub fn set_commit_policy(&self, policy: CommitPolicy) {
+        *self.commit_policy.borrow_mut() = Some(policy);
     }
 
-    pub fn increment_sequence(&self, sequence_number: usize) -> i64 {
-        self.db.clone().increment_sequence(sequence_number)
+    /// Commit the transaction. It's visible to transactions started after this returns, but is
+    /// only queued to be made durable; see `commit_with_durability` for waiting until it is.
+    /// Contended commits are retried as the commit policy says.
+    pub fn commit(&self) -> Result<CommitReceipt, CommitError> {
+        self.commit_notifying(None)
     }
 
-    pub fn sequence_current(&self, sequence_number: usize) -> i64 {
-        self.db.clone().sequence_current(sequence_number)
+    /// As `commit`, returning a token which can be waited on until the commit is durable.
+    pub fn commit_with_durability(&self) -> Result<DurabilityToken, CommitError> {
+        let (sender, durable) = bounded(1);
+        let receipt = self.commit_notifying(Some(sender))?;
+        Ok(DurabilityToken { receipt, durable })
     }
-    pub fn update_sequence_max(&self, sequence_number: usize, value: i64) {
-        self.db.clone().update_sequence_max(sequence_number, value)
+
+    /// As `commit`, but only return once the commit is durable.
+    pub fn commit_durable(&self) -> Result<CommitReceipt, CommitError> {
+        let token = self.commit_with_durability()?;
+        let receipt = token.receipt();
+        token.wait()?;
+        Ok(receipt)
     }
-    pub fn commit(&self) -> Result<(), CommitError> {
-        let mut tries = 0;
-        'retry: loop {
-            tries += 1;
-            let commit_ts = self.db.clone().next_ts();
+
+    /// Commit, signalling `durable` (if given) once the commit is durable.
+    fn commit_notifying(&self, durable: Option<Sender<()>>) -> Result<CommitReceipt, CommitError> {
+        let policy = match &*self.commit_policy.borrow() {
+            Some(policy) => policy.clone(),
+            None => self.db.commit_policy(),
+        };
+        let mut attempts = policy.start();
+        loop {
+            let attempt = attempts.attempt();
             let mut working_set = self.working_set.borrow_mut();
-            let commit_set = self
+            match self
                 .db
-                .prepare_commit_set(commit_ts, working_set.as_mut().unwrap())?;
-            match commit_set.try_commit() {
-                Ok(()) => {
+                .commit_working_set(working_set.as_mut().unwrap(), attempts.lock_wait())
+            {
+                Ok(ts) => {
                     let working_set = working_set.take().unwrap();
-                    self.db.sync(commit_ts, working_set);
-                    return Ok(());
+                    self.db.sync_durable(ts, working_set, durable);
+                    return Ok(CommitReceipt {
+                        ts,
+                        attempts: attempt,
+                    });
                 }
-                Err(CommitError::RelationContentionConflict) => {
-                    if tries > 50 {
-                        return Err(CommitError::RelationContentionConflict);
-                    } else {
-                        // Release the lock, pause a bit, and retry the commit set.
-                        yield_now();
-                        continue 'retry;
+                Err(CommitError::RelationContentionConflict { .. }) => {
+                    // Nothing was locked or changed by the attempt, so pause as the policy says,
+                    // and retry (unless it's waited out the deadline already).
+                    if !attempts.backoff() {
+                        return Err(CommitError::RelationContentionConflict { attempts: attempt });
                     }
                 }
                 Err(e) => {
@@ -124,6 +320,66 @@ impl Transaction {
         Ok(())
     }
 
+    /// Mark the current state of the transaction, so that later changes can be undone with
+    /// `rollback_to` without abandoning the whole transaction. Savepoints nest.
+    pub fn savepoint(&self) -> Savepoint {
+        let mut ws = self.working_set.borrow_mut();
+        ws.as_mut().unwrap().savepoint()
+    }
+
+    /// Undo all changes made since `savepoint` was taken. Savepoints taken after it are discarded,
+    /// but it remains valid itself. Fails if `savepoint` was released, or taken after one since
+    /// rolled back to.
+    pub fn rollback_to(&self, savepoint: &Savepoint) -> Result<(), RelationError> {
+        let mut ws = self.working_set.borrow_mut();
+        ws.as_mut().unwrap().rollback_to(savepoint)
+    }
+
+    /// Forget `savepoint` (and any taken after it), keeping the changes made since. Fails as
+    /// `rollback_to` does.
+    pub fn release(&self, savepoint: Savepoint) -> Result<(), RelationError> {
+        let mut ws = self.working_set.borrow_mut();
+        ws.as_mut().unwrap().release(savepoint)
+    }
+
+    /// The schema description for the given relation.
+    pub(crate) fn relation_info(
+        &self,
+        relation_id: RelationId,
+    ) -> Result<RelationInfo, RelationError> {
+        let ws = self.working_set.borrow();
+        ws.as_ref().unwrap().relation_info(relation_id).cloned()
+    }
+
+    /// Create a new base relation, returning its id. Like any other change, it only becomes
+    /// visible to other transactions (and durable) when this one commits.
+    pub fn create_relation(
+        &self,
+        relation_info: RelationInfo,
+    ) -> Result<RelationId, RelationError> {
+        let mut ws = self.working_set.borrow_mut();
+        ws.as_mut().unwrap().create_relation(relation_info)
+    }
+
+    /// Drop a base relation and all its tuples, as of commit. Its id is not reused.
+    pub fn drop_relation(&self, relation_id: RelationId) -> Result<(), RelationError> {
+        let mut ws = self.working_set.borrow_mut();
+        ws.as_mut().unwrap().drop_relation(relation_id)
+    }
+
+    /// Create a relation which exists only within this transaction, e.g. to hold intermediate
+    /// query results. It supports the same operations as a base relation, but is never committed
+    /// or written to the backing store. Its id has the top (transient) bit set.
+    pub fn create_transient_relation(
+        &self,
+        relation_info: RelationInfo,
+    ) -> Result<RelationId, RelationError> {
+        let mut ws = self.working_set.borrow_mut();
+        ws.as_mut()
+            .unwrap()
+            .create_transient_relation(relation_info)
+    }
+
     /// Grab a handle to a relation, which can be used to perform operations on it in the context
     /// of this transaction.
     pub fn relation(&self, relation_id: RelationId) -> RelVar {
@@ -145,6 +401,20 @@ impl Transaction {
             .seek_by_domain(&self.db, relation_id, domain)
     }
 
+    /// Seek for all tuples whose domain falls within the given bounds, ordered by domain.
+    pub(crate) fn seek_range_by_domain(
+        &self,
+        relation_id: RelationId,
+        lower: Bound<&SliceRef>,
+        upper: Bound<&SliceRef>,
+        reverse: bool,
+    ) -> Result<Vec<TupleRef>, RelationError> {
+        let mut ws = self.working_set.borrow_mut();
+        ws.as_mut()
+            .unwrap()
+            .seek_range_by_domain(&self.db, relation_id, lower, upper, reverse)
+    }
+
     /// Seek for a tuple in the relation by its domain, assuming that the relation has declared a unique domain
     /// constraint, or that there is only one tuple for the given domain.
     pub(crate) fn seek_unique_by_domain(
@@ -171,6 +441,19 @@ impl Transaction {
             .seek_by_codomain(&self.db, relation_id, codomain)
     }
 
+    /// Seek for tuples through one of the relation's attribute indexes.
+    pub(crate) fn seek_by_attributes(
+        &self,
+        relation_id: RelationId,
+        index: usize,
+        key: SliceRef,
+    ) -> Result<HashSet<TupleRef>, RelationError> {
+        let mut ws = self.working_set.borrow_mut();
+        ws.as_mut()
+            .unwrap()
+            .seek_by_attributes(&self.db, relation_id, index, key)
+    }
+
     /// Attempt to insert a tuple into the transaction's working set, with the intent of eventually
     /// committing it to the canonical base relations.
     pub(crate) fn insert_tuple(
@@ -196,6 +479,24 @@ impl Transaction {
             .predicate_scan(&self.db, relation_id, f)
     }
 
+    /// Lazily iterate all tuples in the relation, as visible to this transaction.
+    pub(crate) fn scan_iter(&self, relation_id: RelationId) -> Result<TupleIter, RelationError> {
+        let mut ws = self.working_set.borrow_mut();
+        ws.as_mut().unwrap().scan_iter(&self.db, relation_id)
+    }
+
+    /// Lazily iterate all tuples that match the given domain, as visible to this transaction.
+    pub(crate) fn seek_iter(
+        &self,
+        relation_id: RelationId,
+        domain: SliceRef,
+    ) -> Result<TupleIter, RelationError> {
+        let mut ws = self.working_set.borrow_mut();
+        ws.as_mut()
+            .unwrap()
+            .seek_iter(&self.db, relation_id, domain)
+    }
+
     /// Attempt to update a tuple in the transaction's working set, with the intent of eventually
     /// committing it to the canonical base relations.
     pub(crate) fn update_by_domain(
@@ -224,6 +525,20 @@ impl Transaction {
             .upsert_by_domain(&self.db, relation_id, domain, codomain)
     }
 
+    /// Merge `delta` into the codomain of a tuple in the transaction's working set, using the
+    /// relation's merge operator, redoing the merge against the latest value at commit.
+    pub(crate) fn merge_by_domain(
+        &self,
+        relation_id: RelationId,
+        domain: SliceRef,
+        delta: SliceRef,
+    ) -> Result<(), RelationError> {
+        let mut ws = self.working_set.borrow_mut();
+        ws.as_mut()
+            .unwrap()
+            .merge_by_domain(&self.db, relation_id, domain, delta)
+    }
+
     /// Attempt to delete a tuple in the transaction's working set, with the intent of eventually
     /// committing the delete to the canonical base relations.
     pub(crate) fn remove_by_domain(
@@ -236,65 +551,196 @@ impl Transaction {
             .unwrap()
             .remove_by_domain(&self.db, relation_id, domain)
     }
+
+    /// Attempt to delete the single tuple pairing `domain` with `codomain` in the transaction's
+    /// working set, leaving any others for the domain alone.
+    pub(crate) fn remove_tuple(
+        &self,
+        relation_id: RelationId,
+        domain: SliceRef,
+        codomain: SliceRef,
+    ) -> Result<(), RelationError> {
+        let mut ws = self.working_set.borrow_mut();
+        ws.as_mut()
+            .unwrap()
+            .remove_tuple(&self.db, relation_id, domain, codomain)
+    }
 }
 
 /// A set of tuples to be committed to the canonical base relations, based on a transaction's
 /// working set.
 pub struct CommitSet<'a> {
     ts: u64,
-    relations: Box<BitArray<BaseRelation, 64, Bitset64<1>>>,
+    relations: Box<BitArray<BaseRelation, MAX_RELATIONS, Bitset64<1>>>,
+
+    // The canonical slots of the relations being committed to, whose commit locks are held, and
+    // which we'll swap the new relations into at successful commit.
+    canonical: &'a BTreeMap<RelationId, Arc<CanonicalRelation>>,
+    // All of them, for creating new relations in.
+    all_canonical: &'a RwLock<Vec<Arc<CanonicalRelation>>>,
 
-    // Holds a lock on the base relations, which we'll swap out with the new relations at successful commit
-    write_guard: RwLockWriteGuard<'a, Vec<BaseRelation>>,
+    // And a lock on the schema, which changes along with them if relations are created or dropped.
+    schema_guard: SchemaGuard<'a>,
+    schema_changes: Vec<SchemaChange>,
+
+    // The changes being committed, if anybody's following them.
+    changes: Option<Vec<RelationChanges>>,
+    observers: &'a Observers,
 
     unsync: PhantomUnsync,
 }
 
 impl<'a> CommitSet<'a> {
-    pub(crate) fn new(ts: u64, write_guard: RwLockWriteGuard<'a, Vec<BaseRelation>>) -> Self {
+    pub(crate) fn new(
+        ts: u64,
+        canonical: &'a BTreeMap<RelationId, Arc<CanonicalRelation>>,
+        all_canonical: &'a RwLock<Vec<Arc<CanonicalRelation>>>,
+        schema_guard: SchemaGuard<'a>,
+        observers: &'a Observers,
+    ) -> Self {
         Self {
             ts,
             relations: Box::new(BitArray::new()),
-            write_guard,
+            canonical,
+            all_canonical,
+            schema_guard,
+            schema_changes: vec![],
+            changes: (!observers.is_empty()).then(Vec::new),
+            observers,
             unsync: Default::default(),
         }
     }
 
     pub(crate) fn prepare(&mut self, tx_working_set: &mut WorkingSet) -> Result<(), CommitError> {
+        // New relations take the next free ids, which is only what we picked if nobody else has
+        // created any since we started.
+        let mut next_id = self.schema_guard.len();
+        for change in &tx_working_set.schema_changes {
+            if let SchemaChange::Create(relation_id, _) = change {
+                if relation_id.0 != next_id {
+                    return Err(CommitError::SchemaConflict);
+                }
+                next_id += 1;
+            }
+        }
+        self.schema_changes = tx_working_set.schema_changes.clone();
+
+        // Under serializable isolation, first make sure nothing we read has changed underneath us.
+        let serializable = tx_working_set.read_set.is_some();
+        let read_ts = tx_working_set.ts;
+        let tuplebox = tx_working_set.tuplebox.clone();
+        let canonical_slots = self.canonical;
+        if let Some(read_set) = &tx_working_set.read_set {
+            if let Err(relation_id) = read_set.validate(canonical_slots) {
+                return Err(
+                    canonical_slots[&relation_id].conflict(CommitError::SerializationConflict)
+                );
+            }
+        }
+
+        let mut merged_writes = vec![];
         for (_, local_relation) in tx_working_set.relations.iter_mut() {
             let relation_id = local_relation.id;
+            let mut changes = self.changes.is_some().then(Vec::new);
+
+            // Relations we created have nothing in canonical for our inserts to conflict with.
+            if relation_id.0 >= self.schema_guard.len() {
+                let ts = self.ts;
+                let forked_relation = self.fork(relation_id);
+                for tuple in local_relation.tuples_iter_mut() {
+                    if let TxTupleOp::Insert(tuple) = &mut tuple.op {
+                        tuple.update_timestamp(ts);
+                        forked_relation.insert_tuple(tuple.clone()).unwrap();
+                        record(&mut changes, || Change::Insert {
+                            domain: tuple.domain(),
+                            codomain: tuple.codomain(),
+                        });
+                    }
+                }
+                self.record_relation(relation_id, changes);
+                continue;
+            }
+
+            // Someone else dropped the relation since we started, so our changes to it have
+            // nowhere to go.
+            if self.schema_guard[relation_id.0].is_none()
+                && local_relation
+                    .tuples()
+                    .any(|t| !matches!(t.op, TxTupleOp::Value(_)))
+            {
+                return Err(CommitError::SchemaConflict);
+            }
+            let slot = &canonical_slots[&relation_id];
+            let canonical = slot.relation.read().unwrap();
+            let merges = local_relation.merges.clone();
             // scan through the local working set, and for each tuple, check to see if it's safe to
             // commit. If it is, then we'll add it to the commit set.
             // note we're not actually committing yet, just producing a candidate commit set
             for tuple in local_relation.tuples_iter_mut() {
                 match &mut tuple.op {
+                    // Domains merged into are dealt with below.
+                    TxTupleOp::Insert(tuple)
+                    | TxTupleOp::Update {
+                        to_tuple: tuple, ..
+                    }
+                    | TxTupleOp::Value(tuple)
+                        if merges.contains_key(&tuple.domain()) => {}
                     TxTupleOp::Insert(tuple) => {
                         // Generally inserts should present no conflicts except for constraint violations.
 
                         // If this is an insert, we want to verify that unique constraints are not violated, so we
                         // need to check the canonical relation for the domain value.
                         // Apply timestamp logic, tho.
-                        let canonical = &self.write_guard[relation_id.0];
                         let results_canonical = canonical
                             .seek_by_domain(tuple.domain())
                             .expect("failed to seek for constraints check");
                         let mut replacements = im::HashSet::new();
                         for t in results_canonical {
-                            if canonical.info.unique_domain && t.ts() > tuple.ts() {
-                                return Err(CommitError::UniqueConstraintViolation);
+                            // Without a unique domain, other tuples for the domain are left be,
+                            // and only a concurrent insert of the same pairing conflicts.
+                            if !canonical.info.unique_domain {
+                                if t.codomain() == tuple.codomain() && t.ts() > tuple.ts() {
+                                    return Err(slot.conflict(
+                                        CommitError::UniqueConstraintViolation(Conflict::new(
+                                            &canonical,
+                                            tuple.domain(),
+                                            tuple.ts(),
+                                            Some(t.ts()),
+                                        )),
+                                    ));
+                                }
+                                continue;
                             }
                             // Check the timestamp on the upstream value, if it's newer than the read-timestamp,
                             // we have for this tuple then that's a conflict, because it means someone else has
-                            // already committed a change to this tuple.
+                            // already committed a tuple for this domain.
                             // Otherwise, we clobber their value.
                             if t.ts() > tuple.ts() {
-                                return Err(CommitError::TupleVersionConflict);
+                                return Err(slot.conflict(CommitError::UniqueConstraintViolation(
+                                    Conflict::new(
+                                        &canonical,
+                                        tuple.domain(),
+                                        tuple.ts(),
+                                        Some(t.ts()),
+                                    ),
+                                )));
                             }
                             replacements.insert(t);
                         }
 
                         // Otherwise we can straight-away insert into the our fork of the relation.
                         tuple.update_timestamp(self.ts);
+                        record(&mut changes, || match replacements.iter().next() {
+                            Some(replaced) => Change::Update {
+                                domain: tuple.domain(),
+                                old_codomain: replaced.codomain(),
+                                new_codomain: tuple.codomain(),
+                            },
+                            None => Change::Insert {
+                                domain: tuple.domain(),
+                                codomain: tuple.codomain(),
+                            },
+                        });
                         let forked_relation = self.fork(relation_id);
                         for t in replacements {
                             forked_relation.remove_tuple(&t.id()).unwrap();
@@ -305,14 +751,26 @@ impl<'a> CommitSet<'a> {
                         from_tuple: old_tuple,
                         to_tuple: new_tuple,
                     } => {
-                        let canonical = &self.write_guard[relation_id.0];
-
                         // If this is an update, we want to verify that the tuple we're updating is still there
                         // If it's not, that's a conflict.
                         if !canonical.has_tuple(&old_tuple.id()) {
                             // Someone got here first and deleted the tuple we're trying to update.
-                            // By definition, this is a conflict.
-                            return Err(CommitError::TupleVersionConflict);
+                            // By definition, this is a conflict. Whoever replaced it (if anybody)
+                            // is the newest for the domain.
+                            let winning_ts = canonical
+                                .seek_by_domain(old_tuple.domain())
+                                .expect("failed to seek for conflict diagnosis")
+                                .into_iter()
+                                .map(|t| t.ts())
+                                .max();
+                            return Err(slot.conflict(CommitError::TupleVersionConflict(
+                                Conflict::new(
+                                    &canonical,
+                                    old_tuple.domain(),
+                                    old_tuple.ts(),
+                                    winning_ts,
+                                ),
+                            )));
                         };
 
                         // TODO tuple uniqueness constraint check?
@@ -323,10 +781,13 @@ impl<'a> CommitSet<'a> {
                         forked_relation
                             .update_tuple(&old_tuple.id(), new_tuple.clone())
                             .unwrap();
+                        record(&mut changes, || Change::Update {
+                            domain: new_tuple.domain(),
+                            old_codomain: old_tuple.codomain(),
+                            new_codomain: new_tuple.codomain(),
+                        });
                     }
                     TxTupleOp::Tombstone(tuple, _) => {
-                        let canonical = &self.write_guard[relation_id.0];
-
                         // Check that the tuple we're trying to delete is still there.
                         if !canonical.has_tuple(&tuple.id()) {
                             // Someone got here first and deleted the tuple we're trying to delete.
@@ -338,7 +799,14 @@ impl<'a> CommitSet<'a> {
                                     .expect("failed to seek for constraints check");
                                 for t in results_canonical {
                                     if t.ts() > tuple.ts() {
-                                        return Err(CommitError::UniqueConstraintViolation);
+                                        return Err(slot.conflict(
+                                            CommitError::UniqueConstraintViolation(Conflict::new(
+                                                &canonical,
+                                                tuple.domain(),
+                                                tuple.ts(),
+                                                Some(t.ts()),
+                                            )),
+                                        ));
                                     }
                                 }
                             }
@@ -347,53 +815,255 @@ impl<'a> CommitSet<'a> {
                             // If so, do the del0rt in our fork.
                             let forked_relation = self.fork(relation_id);
                             forked_relation.remove_tuple(&tuple.id()).unwrap();
+                            record(&mut changes, || Change::Delete {
+                                domain: tuple.domain(),
+                                old_codomain: tuple.codomain(),
+                            });
+                        }
+                    }
+                    TxTupleOp::Value(tuple) => {
+                        // A tuple we read is gone (updated or removed) from canonical.
+                        if serializable && !canonical.has_tuple(&tuple.id()) {
+                            return Err(slot.conflict(CommitError::SerializationConflict));
+                        }
+                    }
+                }
+            }
+
+            // Merges don't conflict with what's been committed since: they're redone on top of it.
+            if let Some(merge_operator) = &local_relation.merge_operator {
+                for (domain, deltas) in &merges {
+                    let replaced = canonical
+                        .seek_by_domain(domain.clone())
+                        .expect("failed to seek for merge")
+                        .into_iter()
+                        .next();
+                    let mut value = replaced.as_ref().map(|t| t.codomain());
+                    for delta in deltas {
+                        match merge_operator.merge(value.as_ref(), delta) {
+                            Ok(merged) => value = Some(merged),
+                            // What was committed can't be merged into.
+                            Err(_) => {
+                                return Err(slot.conflict(CommitError::TupleVersionConflict(
+                                    Conflict::new(
+                                        &canonical,
+                                        domain.clone(),
+                                        read_ts,
+                                        replaced.as_ref().map(|t| t.ts()),
+                                    ),
+                                )));
+                            }
                         }
                     }
-                    TxTupleOp::Value(_) => {
-                        continue;
+                    let value = value.expect("Merged without any deltas");
+                    let merged = TupleRef::allocate(
+                        relation_id,
+                        tuplebox.clone(),
+                        self.ts,
+                        domain.as_slice(),
+                        value.as_slice(),
+                    )
+                    .expect("failed to allocate merged tuple");
+                    record(&mut changes, || match &replaced {
+                        Some(replaced) => Change::Update {
+                            domain: domain.clone(),
+                            old_codomain: replaced.codomain(),
+                            new_codomain: value.clone(),
+                        },
+                        None => Change::Insert {
+                            domain: domain.clone(),
+                            codomain: value.clone(),
+                        },
+                    });
+                    merged_writes.push((
+                        relation_id,
+                        domain.clone(),
+                        match &replaced {
+                            Some(replaced) => TxTupleOp::Update {
+                                from_tuple: replaced.clone(),
+                                to_tuple: merged.clone(),
+                            },
+                            None => TxTupleOp::Insert(merged.clone()),
+                        },
+                    ));
+                    let forked_relation = self.fork(relation_id);
+                    if let Some(replaced) = &replaced {
+                        forked_relation.remove_tuple(&replaced.id()).unwrap();
                     }
+                    forked_relation.insert_tuple(merged).unwrap();
                 }
             }
+            drop(canonical);
+            self.record_relation(relation_id, changes);
+        }
+
+        // Nothing can fail from here, so the merges' writes in the working set can be made what was
+        // actually committed for them, which is what gets persisted.
+        for (relation_id, domain, op) in merged_writes {
+            if let Some(local_relation) = tx_working_set.relations.get_mut(relation_id.0) {
+                local_relation.set_committed_write(&domain, op);
+            }
+        }
+
+        // Dropping a relation deletes everything in it, as far as anyone following along is
+        // concerned. (Relations created by the transaction being committed had nothing in them.)
+        if self.changes.is_some() {
+            let canonical_slots = self.canonical;
+            let dropped: Vec<_> = self
+                .schema_changes
+                .iter()
+                .filter_map(|change| match change {
+                    SchemaChange::Drop(relation_id) => canonical_slots.get(relation_id),
+                    SchemaChange::Create(..) => None,
+                })
+                .collect();
+            for slot in dropped {
+                let relation = slot.relation.read().unwrap();
+                let changes = relation
+                    .scan_iter()
+                    .map(|t| Change::Delete {
+                        domain: t.domain(),
+                        old_codomain: t.codomain(),
+                    })
+                    .collect();
+                let relation_id = relation.id;
+                drop(relation);
+                self.record_relation(relation_id, Some(changes));
+            }
         }
         Ok(())
     }
 
+    /// Add the changes to a relation to those being committed, if there are any.
+    fn record_relation(&mut self, relation_id: RelationId, changes: Option<Vec<Change>>) {
+        if let (Some(all), Some(changes)) = (&mut self.changes, changes) {
+            if !changes.is_empty() {
+                all.push(RelationChanges {
+                    relation_id,
+                    changes,
+                });
+            }
+        }
+    }
+
     pub(crate) fn try_commit(mut self) -> Result<(), CommitError> {
         // Everything passed, so we can commit the changes by swapping in the new canonical
-        // before releasing the lock.
+        // before releasing the locks.
         let commit_ts = self.ts;
+        let schema_changes = std::mem::take(&mut self.schema_changes);
+
+        // New relations go on the end of canonical first, so they have a place to be swapped into.
+        for change in &schema_changes {
+            if let SchemaChange::Create(relation_id, relation_info) = change {
+                self.all_canonical
+                    .write()
+                    .unwrap()
+                    .push(CanonicalRelation::new(BaseRelation::new(
+                        *relation_id,
+                        relation_info.clone(),
+                        commit_ts,
+                    )));
+                let SchemaGuard::Exclusive(schema) = &mut self.schema_guard else {
+                    panic!("Schema changes are only committed under the exclusive schema lock");
+                };
+                schema.push(Some(relation_info.clone()));
+            }
+        }
+
         for (_, mut relation) in self.relations.take_all() {
-            let idx = relation.id.0;
+            let slot = match self.canonical.get(&relation.id) {
+                Some(slot) => slot.clone(),
+                None => self.all_canonical.read().unwrap()[relation.id.0].clone(),
+            };
 
             relation.ts = commit_ts;
-            self.write_guard[idx] = relation;
+            *slot.relation.write().unwrap() = Arc::new(relation);
         }
 
+        // Dropped relations leave an empty relation behind, so that ids stay stable. Their tuples
+        // are freed as the last references to them go away.
+        for change in &schema_changes {
+            if let SchemaChange::Drop(relation_id) = change {
+                let mut relation = self.canonical[relation_id].relation.write().unwrap();
+                let relation_info = relation.info.clone();
+                *relation = Arc::new(BaseRelation::new(*relation_id, relation_info, commit_ts));
+                let SchemaGuard::Exclusive(schema) = &mut self.schema_guard else {
+                    panic!("Schema changes are only committed under the exclusive schema lock");
+                };
+                schema[relation_id.0] = None;
+            }
+        }
+
+        // Subscribers and watchers see commits in timestamp order, so these changes are held back
+        // until any earlier commits still in progress are done.
+        let changes = self
+            .changes
+            .take()
+            .filter(|relations| !relations.is_empty())
+            .map(|relations| CommitChanges {
+                ts: commit_ts,
+                relations,
+            });
+        self.observers.publish(commit_ts, changes);
+
         Ok(())
     }
 
     /// Fork the given base relation into the commit set, if it's not already there.
     fn fork(&mut self, relation_id: RelationId) -> &mut BaseRelation {
         if self.relations.get(relation_id.0).is_none() {
-            let r = self.write_guard[relation_id.0].clone();
+            let r = match self.canonical.get(&relation_id) {
+                Some(c) => BaseRelation::clone(&c.relation.read().unwrap()),
+                None => self.created_relation(relation_id),
+            };
             self.relations.set(relation_id.0, r);
         }
         self.relations.get_mut(relation_id.0).unwrap()
     }
+
+    /// A new, empty, relation for one created by the transaction being committed.
+    fn created_relation(&self, relation_id: RelationId) -> BaseRelation {
+        let relation_info = self
+            .schema_changes
+            .iter()
+            .find_map(|change| match change {
+                SchemaChange::Create(id, relation_info) if *id == relation_id => {
+                    Some(relation_info.clone())
+                }
+                _ => None,
+            })
+            .expect("Relation was not created by this transaction");
+        BaseRelation::new(relation_id, relation_info, self.ts)
+    }
+}
+
+/// Record a change, if changes are being captured.
+fn record<F: FnOnce() -> Change>(changes: &mut Option<Vec<Change>>, change: F) {
+    if let Some(changes) = changes {
+        changes.push(change());
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
+    use std::time::Duration;
 
     use rand::Rng;
 
     use daumtils::SliceRef;
 
+    use crate::codec::{decode_list, encode_list, Codec};
     use crate::index::{AttrType, IndexType};
-    use crate::relbox::{RelBox, RelationInfo};
+    use crate::relbox::{
+        Attribute, AttributeIndex, ConflictCounts, RelBox, RelationInfo, SchemaError, SequenceKind,
+    };
     use crate::tuples::TupleRef;
+    use crate::tx::changes::{Change, RelationChanges};
+    use crate::tx::commit_policy::{Backoff, CommitPolicy, LockWait};
+    use crate::tx::merge::MergeOperator;
     use crate::tx::transaction::CommitError;
+    use crate::tx::working_set::MAX_RELATIONS;
     use crate::{RelationError, RelationId, Transaction};
 
     fn attr(slice: &[u8]) -> SliceRef {
@@ -401,7 +1071,7 @@ mod tests {
     }
 
     fn attr2(i: i64) -> SliceRef {
-        SliceRef::from_bytes(&i.to_le_bytes())
+        i.encode()
     }
 
     fn test_db() -> Arc<RelBox> {
@@ -417,6 +1087,7 @@ mod tests {
                     unique_domain: true,
                     index_type: IndexType::Hash,
                     codomain_index_type: Some(IndexType::Hash),
+                    ..Default::default()
                 },
                 RelationInfo {
                     name: "test2".to_string(),
@@ -426,6 +1097,17 @@ mod tests {
                     unique_domain: true,
                     index_type: IndexType::AdaptiveRadixTree,
                     codomain_index_type: None,
+                    ..Default::default()
+                },
+                RelationInfo {
+                    name: "test3".to_string(),
+                    domain_type: AttrType::String,
+                    codomain_type: AttrType::String,
+                    secondary_indexed: false,
+                    unique_domain: true,
+                    index_type: IndexType::BTree,
+                    codomain_index_type: None,
+                    ..Default::default()
                 },
             ],
             0,
@@ -744,9 +1426,21 @@ mod tests {
         tx2.insert_tuple(rid, attr(b"abc"), attr(b"zzz")).unwrap();
 
         assert!(tx1.commit().is_ok());
+        let CommitError::UniqueConstraintViolation(conflict) =
+            tx2.commit().expect_err("Expected constraint violation")
+        else {
+            panic!("Expected constraint violation");
+        };
+        assert_eq!(conflict.relation_id, rid);
+        assert_eq!(conflict.relation_name, "test");
+        assert_eq!(conflict.domain, attr(b"abc"));
+        assert!(conflict.winning_ts.unwrap() > conflict.read_ts);
         assert_eq!(
-            tx2.commit().expect_err("Expected constraint violation"),
-            CommitError::UniqueConstraintViolation
+            db.conflict_counts()[0],
+            ConflictCounts {
+                unique_violations: 1,
+                ..Default::default()
+            }
         );
     }
 
@@ -788,11 +1482,18 @@ mod tests {
         // 3. First transaction commits with success but second transaction fails with Conflict,
         // because it is younger than the first transaction, and the first transaction committed
         // a change to the tuple before we could get to it.
-        assert!(tx1.commit().is_ok());
-        assert_eq!(
-            tx2.commit().expect_err("Expected conflict"),
-            CommitError::TupleVersionConflict
-        );
+        let committed = tx1.commit().unwrap();
+        let CommitError::TupleVersionConflict(conflict) =
+            tx2.commit().expect_err("Expected conflict")
+        else {
+            panic!("Expected version conflict");
+        };
+        assert_eq!(conflict.relation_id, rid);
+        assert_eq!(conflict.domain, attr(b"abc"));
+        assert!(conflict.read_ts < committed.ts);
+        assert_eq!(conflict.winning_ts, Some(committed.ts));
+        assert_eq!(db.conflict_counts()[0].version_conflicts, 1);
+        assert_eq!(db.conflict_counts()[1], ConflictCounts::default());
     }
 
     fn random_tuple() -> (Vec<u8>, Vec<u8>) {
@@ -957,9 +1658,1491 @@ mod tests {
         assert_same(&tuples, &items);
     }
 
+    fn int_domains(tuples: &[TupleRef]) -> Vec<i64> {
+        tuples
+            .iter()
+            .map(|t| i64::decode(&t.domain()).unwrap())
+            .collect()
+    }
+
+    /// Range scans over an ART-indexed relation should merge the canonical tuples with the
+    /// transaction's own inserts, updates and removals, in key order.
+    #[test]
+    fn range_scan_art() {
+        let db = test_db();
+        let rid = RelationId(1);
+        let tx = db.clone().start_tx();
+        for i in -5..5 {
+            tx.insert_tuple(rid, attr2(i), attr(b"v")).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(rid, attr2(10), attr(b"new")).unwrap();
+        tx.remove_by_domain(rid, attr2(0)).unwrap();
+        tx.update_by_domain(rid, attr2(-1), attr(b"upd")).unwrap();
+
+        let r = tx.relation(rid);
+        let tuples = r.seek_range_by_domain(attr2(-2)..attr2(2)).unwrap();
+        assert_eq!(int_domains(&tuples), vec![-2, -1, 1]);
+        assert_eq!(tuples[1].codomain(), attr(b"upd"));
+
+        let tuples = r.seek_range_by_domain(attr2(-2)..=attr2(2)).unwrap();
+        assert_eq!(int_domains(&tuples), vec![-2, -1, 1, 2]);
+
+        let tuples = r.seek_range_by_domain(attr2(3)..).unwrap();
+        assert_eq!(int_domains(&tuples), vec![3, 4, 10]);
+
+        let tuples = r.seek_range_by_domain_rev(..attr2(-3)).unwrap();
+        assert_eq!(int_domains(&tuples), vec![-4, -5]);
+
+        assert!(r
+            .seek_range_by_domain(attr2(3)..attr2(3))
+            .unwrap()
+            .is_empty());
+        assert!(r
+            .seek_range_by_domain(attr2(3)..attr2(1))
+            .unwrap()
+            .is_empty());
+
+        // Scanning again after the canonical tuples have been pulled into the working set should
+        // give the same answer.
+        let tuples = r.seek_range_by_domain(..).unwrap();
+        assert_eq!(
+            int_domains(&tuples),
+            vec![-5, -4, -3, -2, -1, 1, 2, 3, 4, 10]
+        );
+        tx.commit().unwrap();
+
+        let tx = db.clone().start_tx();
+        let tuples = tx.relation(rid).seek_range_by_domain_rev(..).unwrap();
+        assert_eq!(
+            int_domains(&tuples),
+            vec![10, 4, 3, 2, 1, -1, -2, -3, -4, -5]
+        );
+    }
+
+    /// A window from the middle of a large ART-indexed relation, either way.
+    #[test]
+    fn range_scan_art_window() {
+        let db = test_db();
+        let rid = RelationId(1);
+        let tx = db.clone().start_tx();
+        for i in -5000..5000 {
+            tx.insert_tuple(rid, attr2(i * 2), attr(b"v")).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let tx = db.clone().start_tx();
+        let r = tx.relation(rid);
+        let tuples = r.seek_range_by_domain(attr2(1001)..attr2(1010)).unwrap();
+        assert_eq!(int_domains(&tuples), vec![1002, 1004, 1006, 1008]);
+        let tuples = r
+            .seek_range_by_domain_rev(attr2(-1010)..=attr2(-1002))
+            .unwrap();
+        assert_eq!(
+            int_domains(&tuples),
+            vec![-1002, -1004, -1006, -1008, -1010]
+        );
+        let tuples = r.seek_range_by_domain_rev(..attr2(-9996)).unwrap();
+        assert_eq!(int_domains(&tuples), vec![-9998, -10000]);
+    }
+
+    #[test]
+    fn range_scan_btree() {
+        let db = test_db();
+        let rid = RelationId(2);
+        let tx = db.clone().start_tx();
+        for d in [b"a", b"b", b"c", b"d"] {
+            tx.insert_tuple(rid, attr(d), attr(b"v")).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(rid, attr(b"bb"), attr(b"v")).unwrap();
+        tx.remove_by_domain(rid, attr(b"c")).unwrap();
+
+        let domains = |tuples: Vec<TupleRef>| {
+            tuples
+                .iter()
+                .map(|t| t.domain().as_slice().to_vec())
+                .collect::<Vec<_>>()
+        };
+        let r = tx.relation(rid);
+        assert_eq!(
+            domains(r.seek_range_by_domain(attr(b"b")..=attr(b"d")).unwrap()),
+            vec![b"b".to_vec(), b"bb".to_vec(), b"d".to_vec()]
+        );
+        assert_eq!(
+            domains(r.seek_range_by_domain_rev(attr(b"b")..attr(b"d")).unwrap()),
+            vec![b"bb".to_vec(), b"b".to_vec()]
+        );
+    }
+
+    #[test]
+    fn range_scan_unordered() {
+        let db = test_db();
+        let tx = db.clone().start_tx();
+        assert_eq!(
+            tx.relation(RelationId(0))
+                .seek_range_by_domain(attr(b"a")..attr(b"z"))
+                .unwrap_err(),
+            RelationError::UnorderedIndex
+        );
+    }
+
+    #[test]
+    fn lazy_scan_and_seek() {
+        let db = test_db();
+        let rid = RelationId(0);
+        let tx = db.clone().start_tx();
+        for d in [b"a", b"b", b"c", b"d"] {
+            tx.insert_tuple(rid, attr(d), attr(b"v1")).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let tx = db.clone().start_tx();
+        tx.remove_by_domain(rid, attr(b"a")).unwrap();
+        tx.update_by_domain(rid, attr(b"b"), attr(b"v2")).unwrap();
+        tx.insert_tuple(rid, attr(b"e"), attr(b"v2")).unwrap();
+
+        let r = tx.relation(rid);
+        assert_same(
+            &r.scan_iter().unwrap().collect::<Vec<_>>(),
+            &[
+                (b"b".to_vec(), b"v2".to_vec()),
+                (b"c".to_vec(), b"v1".to_vec()),
+                (b"d".to_vec(), b"v1".to_vec()),
+                (b"e".to_vec(), b"v2".to_vec()),
+            ],
+        );
+
+        // Early termination.
+        let found = r
+            .scan_iter()
+            .unwrap()
+            .find(|t| t.codomain().as_slice() == b"v2")
+            .unwrap();
+        assert!(matches!(found.domain().as_slice(), b"b" | b"e"));
+        assert_eq!(r.scan_iter().unwrap().take(2).count(), 2);
+
+        // Seeks see local updates and removals, and canonical tuples we haven't touched.
+        assert_eq!(r.seek_iter(attr(b"a")).unwrap().count(), 0);
+        let b: Vec<_> = r.seek_iter(attr(b"b")).unwrap().collect();
+        assert_eq!(b.len(), 1);
+        assert_eq!(b[0].codomain().as_slice(), b"v2");
+        let c: Vec<_> = r.seek_iter(attr(b"c")).unwrap().collect();
+        assert_eq!(c.len(), 1);
+        assert_eq!(c[0].codomain().as_slice(), b"v1");
+        assert_eq!(r.seek_iter(attr(b"e")).unwrap().count(), 1);
+        assert_eq!(r.seek_iter(attr(b"z")).unwrap().count(), 0);
+    }
+
+    /// Classic write skew: two transactions each read both of two tuples, and each update a
+    /// different one. Snapshot isolation lets both commit; serializable isolation must not.
+    #[test]
+    fn serializable_write_skew() {
+        let db = test_db();
+        let rid = RelationId(0);
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(rid, attr(b"a"), attr(b"1")).unwrap();
+        tx.insert_tuple(rid, attr(b"b"), attr(b"1")).unwrap();
+        tx.commit().unwrap();
+
+        let skew = |tx: &Transaction, write: &[u8]| {
+            tx.seek_unique_by_domain(rid, attr(b"a")).unwrap();
+            tx.seek_unique_by_domain(rid, attr(b"b")).unwrap();
+            tx.update_by_domain(rid, attr(write), attr(b"0")).unwrap();
+        };
+
+        let tx1 = db.clone().start_tx();
+        let tx2 = db.clone().start_tx();
+        skew(&tx1, b"a");
+        skew(&tx2, b"b");
+        tx1.commit().unwrap();
+        tx2.commit().unwrap();
+
+        let tx1 = db.clone().start_serializable_tx();
+        let tx2 = db.clone().start_serializable_tx();
+        skew(&tx1, b"a");
+        skew(&tx2, b"b");
+        tx1.commit().unwrap();
+        assert_eq!(tx2.commit(), Err(CommitError::SerializationConflict));
+        assert_eq!(db.conflict_counts()[0].serialization_conflicts, 1);
+    }
+
+    /// A serializable transaction's scans and seeks are invalidated by concurrently committed
+    /// inserts which would have matched them.
+    #[test]
+    fn serializable_phantoms() {
+        let db = test_db();
+        let rid = RelationId(0);
+
+        // Seek for a domain that isn't there (yet).
+        let tx1 = db.clone().start_serializable_tx();
+        assert!(tx1.seek_by_domain(rid, attr(b"x")).unwrap().is_empty());
+        tx1.insert_tuple(rid, attr(b"y"), attr(b"1")).unwrap();
+        let tx2 = db.clone().start_tx();
+        tx2.insert_tuple(rid, attr(b"x"), attr(b"1")).unwrap();
+        tx2.commit().unwrap();
+        assert_eq!(tx1.commit(), Err(CommitError::SerializationConflict));
+
+        // Scan, racing an insert elsewhere in the relation.
+        let tx1 = db.clone().start_serializable_tx();
+        assert_eq!(tx1.predicate_scan(rid, &|_| true).unwrap().len(), 1);
+        tx1.insert_tuple(rid, attr(b"z"), attr(b"1")).unwrap();
+        let tx2 = db.clone().start_tx();
+        tx2.insert_tuple(rid, attr(b"w"), attr(b"1")).unwrap();
+        tx2.commit().unwrap();
+        assert_eq!(tx1.commit(), Err(CommitError::SerializationConflict));
+
+        // Without interference, both commit fine.
+        let tx1 = db.clone().start_serializable_tx();
+        assert_eq!(tx1.predicate_scan(rid, &|_| true).unwrap().len(), 2);
+        assert!(tx1.seek_by_domain(rid, attr(b"q")).unwrap().is_empty());
+        tx1.insert_tuple(rid, attr(b"q"), attr(b"1")).unwrap();
+        tx1.commit().unwrap();
+    }
+
+    /// Reads are checked against what had landed when they were made, not against when the
+    /// transaction started: a writer which started (or even took its commit timestamp) first can
+    /// still land after them.
+    #[test]
+    fn serializable_earlier_writer() {
+        let db = test_db();
+        let (held_rid, rid) = (RelationId(0), RelationId(2));
+
+        // The writer gets as far as taking its commit timestamp, then is held up on another
+        // relation it writes. (Timestamps are consecutive until it takes one.)
+        let writer = db.clone().start_tx();
+        writer
+            .insert_tuple(held_rid, attr(b"a"), attr(b"1"))
+            .unwrap();
+        writer.insert_tuple(rid, attr(b"x"), attr(b"1")).unwrap();
+        let held_slot = db.canonical.read().unwrap()[held_rid.0].clone();
+        let held = held_slot.relation.write().unwrap();
+        let mut last_ts = db.clone().next_ts();
+        let committer = std::thread::spawn(move || writer.commit());
+        loop {
+            let ts = db.clone().next_ts();
+            if ts > last_ts + 1 {
+                break;
+            }
+            last_ts = ts;
+            std::thread::yield_now();
+        }
+
+        // So the reader starts after it, but reads before it lands.
+        let reader = db.clone().start_serializable_tx();
+        assert!(reader.seek_by_domain(rid, attr(b"x")).unwrap().is_empty());
+        reader.insert_tuple(rid, attr(b"y"), attr(b"1")).unwrap();
+        drop(held);
+        committer.join().unwrap().unwrap();
+        assert_eq!(reader.commit(), Err(CommitError::SerializationConflict));
+
+        // A writer which started first but landed before the reads is no conflict.
+        let writer = db.clone().start_tx();
+        let reader = db.clone().start_serializable_tx();
+        writer.insert_tuple(rid, attr(b"z"), attr(b"1")).unwrap();
+        writer.commit().unwrap();
+        assert_eq!(reader.seek_by_domain(rid, attr(b"z")).unwrap().len(), 1);
+        assert_eq!(reader.predicate_scan(rid, &|_| true).unwrap().len(), 2);
+        reader.insert_tuple(rid, attr(b"y"), attr(b"1")).unwrap();
+        reader.commit().unwrap();
+    }
+
+    #[test]
+    fn savepoint_rollback() {
+        let db = test_db();
+        let rid = RelationId(0);
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(rid, attr(b"c"), attr(b"1")).unwrap();
+        tx.commit().unwrap();
+
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(rid, attr(b"a"), attr(b"1")).unwrap();
+        let sp = tx.savepoint();
+        tx.insert_tuple(rid, attr(b"b"), attr(b"1")).unwrap();
+        tx.update_by_domain(rid, attr(b"a"), attr(b"2")).unwrap();
+        tx.remove_by_domain(rid, attr(b"c")).unwrap();
+
+        tx.rollback_to(&sp).unwrap();
+        let expected = [
+            (b"a".to_vec(), b"1".to_vec()),
+            (b"c".to_vec(), b"1".to_vec()),
+        ];
+        assert_same(&tx.predicate_scan(rid, &|_| true).unwrap(), &expected);
+        // The local indexes were restored too, so `b` is free to insert again.
+        tx.insert_tuple(rid, attr(b"b"), attr(b"3")).unwrap();
+
+        // Nested savepoints; rolling back to the outer one discards the inner one.
+        let inner = tx.savepoint();
+        tx.remove_by_domain(rid, attr(b"b")).unwrap();
+        tx.rollback_to(&inner).unwrap();
+        assert_eq!(
+            tx.seek_unique_by_domain(rid, attr(b"b"))
+                .unwrap()
+                .codomain()
+                .as_slice(),
+            b"3"
+        );
+        tx.rollback_to(&sp).unwrap();
+        assert_same(&tx.predicate_scan(rid, &|_| true).unwrap(), &expected);
+        // ... after which it's gone.
+        assert_eq!(
+            tx.rollback_to(&inner),
+            Err(RelationError::SavepointNotFound)
+        );
+        assert_eq!(tx.release(inner), Err(RelationError::SavepointNotFound));
+
+        // Releasing keeps changes made since.
+        tx.insert_tuple(rid, attr(b"d"), attr(b"1")).unwrap();
+        let inner = tx.savepoint();
+        tx.release(sp).unwrap();
+        assert_eq!(
+            tx.rollback_to(&inner),
+            Err(RelationError::SavepointNotFound)
+        );
+        tx.commit().unwrap();
+
+        let tx = db.clone().start_tx();
+        assert_same(
+            &tx.predicate_scan(rid, &|_| true).unwrap(),
+            &[
+                (b"a".to_vec(), b"1".to_vec()),
+                (b"c".to_vec(), b"1".to_vec()),
+                (b"d".to_vec(), b"1".to_vec()),
+            ],
+        );
+    }
+
+    #[test]
+    fn transient_relations() {
+        let db = test_db();
+        let rid = RelationId(0);
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(rid, attr(b"a"), attr(b"x")).unwrap();
+        tx.insert_tuple(rid, attr(b"b"), attr(b"y")).unwrap();
+
+        let info = db.relation_info()[0].clone();
+        let tmp = tx.create_transient_relation(info.clone()).unwrap();
+        assert!(tmp.is_transient_relation());
+        let r = tx.relation(tmp);
+        r.insert_tuple(attr(b"x"), attr(b"1")).unwrap();
+        r.insert_tuple(attr(b"z"), attr(b"2")).unwrap();
+        r.update_by_domain(attr(b"z"), attr(b"3")).unwrap();
+        assert_eq!(
+            r.seek_unique_by_domain(attr(b"z"))
+                .unwrap()
+                .codomain()
+                .as_slice(),
+            b"3"
+        );
+        assert_eq!(r.predicate_scan(&|_| true).unwrap().len(), 2);
+        assert_eq!(r.scan_iter().unwrap().count(), 2);
+
+        // Usable as a join input alongside base relations.
+        let joined = tx
+            .relation(rid)
+            .join(tmp, crate::JoinOn::CodomainToDomain)
+            .unwrap();
+        assert_eq!(joined.len(), 1);
+        assert_eq!(joined[0].0.domain().as_slice(), b"a");
+
+        // Transient relations created after a savepoint are discarded by rolling back to it.
+        let sp = tx.savepoint();
+        let tmp2 = tx.create_transient_relation(info.clone()).unwrap();
+        tx.insert_tuple(tmp2, attr(b"q"), attr(b"1")).unwrap();
+        tx.rollback_to(&sp).unwrap();
+        assert_eq!(tx.create_transient_relation(info), Ok(tmp2));
+
+        // Committing leaves the base relations untouched by the transient ones.
+        tx.commit().unwrap();
+        let tx = db.clone().start_tx();
+        assert_same(
+            &tx.predicate_scan(rid, &|_| true).unwrap(),
+            &[
+                (b"a".to_vec(), b"x".to_vec()),
+                (b"b".to_vec(), b"y".to_vec()),
+            ],
+        );
+    }
+
+    #[test]
+    fn create_and_drop_relations() {
+        let db = test_db();
+        let info = db.relation_info()[2].clone();
+
+        let tx = db.clone().start_tx();
+        let before = db.clone().start_tx();
+        let rid = tx.create_relation(info.clone()).unwrap();
+        assert_eq!(rid, RelationId(3));
+        tx.insert_tuple(rid, attr(b"a"), attr(b"1")).unwrap();
+        tx.insert_tuple(RelationId(2), attr(b"b"), attr(b"2"))
+            .unwrap();
+        assert_eq!(tx.scan_iter(rid).unwrap().count(), 1);
+        // Not visible to anyone else until commit.
+        assert_eq!(db.relation_info().len(), 3);
+        tx.commit().unwrap();
+        before.commit().unwrap();
+
+        assert_eq!(db.relation_info().len(), 4);
+        let tx = db.clone().start_tx();
+        assert_eq!(
+            tx.seek_unique_by_domain(rid, attr(b"a"))
+                .unwrap()
+                .codomain()
+                .as_slice(),
+            b"1"
+        );
+
+        // Dropping takes the relation and its tuples with it, and its id isn't handed out again.
+        tx.drop_relation(RelationId(2)).unwrap();
+        assert_eq!(
+            tx.drop_relation(RelationId(2)).unwrap_err(),
+            RelationError::RelationNotFound
+        );
+        assert_eq!(
+            tx.seek_by_domain(RelationId(2), attr(b"b")).unwrap_err(),
+            RelationError::RelationNotFound
+        );
+        assert!(matches!(
+            tx.relation(RelationId(2)).scan_iter(),
+            Err(RelationError::RelationNotFound)
+        ));
+        assert_eq!(
+            tx.drop_relation(RelationId(99)).unwrap_err(),
+            RelationError::RelationNotFound
+        );
+        tx.commit().unwrap();
+        assert!(db.schema()[2].is_none());
+        // (Its description is still there, for anything indexing by relation id.)
+        assert_eq!(db.relation_info()[2], info);
+        assert!(db.copy_canonical()[2].predicate_scan(&|_| true).is_empty());
+        let tx = db.clone().start_tx();
+        assert_eq!(tx.create_relation(info.clone()), Ok(RelationId(4)));
+        tx.rollback().unwrap();
+
+        // Two transactions can't both create the same relation id.
+        let tx_a = db.clone().start_tx();
+        let tx_b = db.clone().start_tx();
+        assert_eq!(tx_a.create_relation(info.clone()), Ok(RelationId(4)));
+        assert_eq!(tx_b.create_relation(info.clone()), Ok(RelationId(4)));
+        tx_a.commit().unwrap();
+        assert_eq!(tx_b.commit(), Err(CommitError::SchemaConflict));
+
+        // Nor write to a relation which was dropped while they were running.
+        let tx_c = db.clone().start_tx();
+        let tx_d = db.clone().start_tx();
+        tx_c.drop_relation(RelationId(1)).unwrap();
+        tx_d.insert_tuple(RelationId(1), attr2(1), attr(b"1"))
+            .unwrap();
+        tx_c.commit().unwrap();
+        assert_eq!(tx_d.commit(), Err(CommitError::SchemaConflict));
+
+        // Relation ids run out eventually.
+        let tx = db.clone().start_tx();
+        for id in 5..MAX_RELATIONS {
+            assert_eq!(tx.create_relation(info.clone()), Ok(RelationId(id)));
+        }
+        assert_eq!(
+            tx.create_relation(info),
+            Err(RelationError::TooManyRelations(MAX_RELATIONS))
+        );
+    }
+
+    /// Relations without a unique domain map a domain to many codomains, and treat each pairing
+    /// as its own tuple, for both removal and commit conflicts.
+    #[test]
+    fn non_unique_domain() {
+        let db = RelBox::new(
+            1 << 24,
+            None,
+            &[RelationInfo {
+                name: "multi".to_string(),
+                domain_type: AttrType::String,
+                codomain_type: AttrType::String,
+                secondary_indexed: false,
+                unique_domain: false,
+                index_type: IndexType::Hash,
+                codomain_index_type: None,
+                ..Default::default()
+            }],
+            0,
+        );
+        let rid = RelationId(0);
+        let codomains = |tx: &Transaction, domain: &[u8]| {
+            let mut codomains: Vec<_> = tx
+                .seek_by_domain(rid, attr(domain))
+                .unwrap()
+                .iter()
+                .map(|t| t.codomain().as_slice().to_vec())
+                .collect();
+            codomains.sort();
+            codomains
+        };
+
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(rid, attr(b"a"), attr(b"1")).unwrap();
+        tx.insert_tuple(rid, attr(b"a"), attr(b"2")).unwrap();
+        tx.insert_tuple(rid, attr(b"a"), attr(b"3")).unwrap();
+        assert_eq!(
+            tx.insert_tuple(rid, attr(b"a"), attr(b"1")),
+            Err(RelationError::UniqueConstraintViolation)
+        );
+        tx.remove_tuple(rid, attr(b"a"), attr(b"3")).unwrap();
+        assert_eq!(codomains(&tx, b"a"), vec![b"1".to_vec(), b"2".to_vec()]);
+        tx.commit().unwrap();
+
+        // Removing one pairing leaves the other, and the same pairing can't be removed twice.
+        let tx = db.clone().start_tx();
+        assert_eq!(
+            tx.insert_tuple(rid, attr(b"a"), attr(b"2")),
+            Err(RelationError::UniqueConstraintViolation)
+        );
+        tx.remove_tuple(rid, attr(b"a"), attr(b"1")).unwrap();
+        assert_eq!(
+            tx.remove_tuple(rid, attr(b"a"), attr(b"1")),
+            Err(RelationError::TupleNotFound)
+        );
+        assert_eq!(codomains(&tx, b"a"), vec![b"2".to_vec()]);
+        // But it can be put back.
+        tx.remove_tuple(rid, attr(b"a"), attr(b"2")).unwrap();
+        tx.insert_tuple(rid, attr(b"a"), attr(b"2")).unwrap();
+        tx.commit().unwrap();
+        let tx = db.clone().start_tx();
+        assert_eq!(codomains(&tx, b"a"), vec![b"2".to_vec()]);
+        tx.commit().unwrap();
+
+        // Concurrent inserts of different codomains for the same domain don't conflict...
+        let tx_a = db.clone().start_tx();
+        let tx_b = db.clone().start_tx();
+        tx_a.insert_tuple(rid, attr(b"b"), attr(b"1")).unwrap();
+        tx_b.insert_tuple(rid, attr(b"b"), attr(b"2")).unwrap();
+        tx_a.commit().unwrap();
+        tx_b.commit().unwrap();
+
+        // ... but of the same pairing do.
+        let tx_a = db.clone().start_tx();
+        let tx_b = db.clone().start_tx();
+        tx_a.insert_tuple(rid, attr(b"b"), attr(b"3")).unwrap();
+        tx_b.insert_tuple(rid, attr(b"b"), attr(b"3")).unwrap();
+        tx_a.commit().unwrap();
+        assert!(matches!(
+            tx_b.commit(),
+            Err(CommitError::UniqueConstraintViolation(_))
+        ));
+
+        // And removes of different pairings leave each other be.
+        let tx_a = db.clone().start_tx();
+        let tx_b = db.clone().start_tx();
+        tx_a.remove_tuple(rid, attr(b"b"), attr(b"1")).unwrap();
+        tx_b.remove_tuple(rid, attr(b"b"), attr(b"2")).unwrap();
+        tx_a.commit().unwrap();
+        tx_b.commit().unwrap();
+        let tx = db.clone().start_tx();
+        assert_eq!(codomains(&tx, b"b"), vec![b"3".to_vec()]);
+    }
+
+    /// Relations of more than two attributes, with a composite key, and further indexes on
+    /// single attributes.
+    #[test]
+    fn n_ary_relations() {
+        let attribute = |name: &str, attr_type| Attribute {
+            name: name.to_string(),
+            attr_type,
+        };
+        let db = RelBox::new(
+            1 << 24,
+            None,
+            &[RelationInfo {
+                name: "people".to_string(),
+                domain_type: AttrType::Bytes,
+                codomain_type: AttrType::Integer,
+                secondary_indexed: false,
+                unique_domain: true,
+                index_type: IndexType::BTree,
+                codomain_index_type: None,
+                attributes: vec![
+                    attribute("last", AttrType::String),
+                    attribute("first", AttrType::String),
+                    attribute("age", AttrType::Integer),
+                ],
+                key_attributes: 2,
+                attribute_indexes: vec![
+                    AttributeIndex {
+                        attributes: vec![2],
+                        index_type: IndexType::Hash,
+                    },
+                    AttributeIndex {
+                        attributes: vec![1],
+                        index_type: IndexType::BTree,
+                    },
+                ],
+                ..Default::default()
+            }],
+            0,
+        );
+        let rid = RelationId(0);
+
+        let tx = db.clone().start_tx();
+        let people = tx.relation(rid);
+        people
+            .insert_attributes(&[attr(b"smith"), attr(b"bob"), attr2(40)])
+            .unwrap();
+        people
+            .insert_attributes(&[attr(b"smith"), attr(b"alice"), attr2(30)])
+            .unwrap();
+        people
+            .insert_attributes(&[attr(b"jones"), attr(b"alice"), attr2(30)])
+            .unwrap();
+        assert_eq!(
+            people.insert_attributes(&[attr(b"jones"), attr(b"alice")]),
+            Err(RelationError::AttributeCount(3, 2))
+        );
+        assert_eq!(
+            people.insert_attributes(&[attr(b"jones"), attr(b"alice"), attr2(31)]),
+            Err(RelationError::UniqueConstraintViolation)
+        );
+
+        let bob = people.seek_by_key(&[attr(b"smith"), attr(b"bob")]).unwrap();
+        assert_eq!(bob.len(), 1);
+        assert_eq!(
+            people.attributes_of(bob.iter().next().unwrap()).unwrap(),
+            vec![attr(b"smith"), attr(b"bob"), attr2(40)]
+        );
+
+        // Keys sharing their leading attributes are adjacent, and can be found by them.
+        let smiths: Vec<_> = people
+            .seek_by_key_prefix(&[attr(b"smith")])
+            .unwrap()
+            .iter()
+            .map(|t| people.attributes_of(t).unwrap()[1].clone())
+            .collect();
+        assert_eq!(smiths, vec![attr(b"alice"), attr(b"bob")]);
+        assert_eq!(people.seek_by_key_prefix(&[]).unwrap().len(), 3);
+        assert_eq!(people.seek_by_attributes(0, &[attr2(30)]).unwrap().len(), 2);
+        tx.commit().unwrap();
+
+        // And once committed, through the canonical attribute indexes, with the working set
+        // applied over top.
+        let tx = db.clone().start_tx();
+        let people = tx.relation(rid);
+        assert_eq!(people.seek_by_attributes(0, &[attr2(30)]).unwrap().len(), 2);
+        assert_eq!(
+            people
+                .seek_by_attributes(1, &[attr(b"alice")])
+                .unwrap()
+                .len(),
+            2
+        );
+        let info = db.relation_info()[0].clone();
+        people
+            .remove_by_domain(info.encode_key(&[attr(b"jones"), attr(b"alice")]).unwrap())
+            .unwrap();
+        people
+            .insert_attributes(&[attr(b"jones"), attr(b"carol"), attr2(30)])
+            .unwrap();
+        let thirty: Vec<_> = people
+            .seek_by_attributes(0, &[attr2(30)])
+            .unwrap()
+            .iter()
+            .map(|t| people.attributes_of(t).unwrap()[1].clone())
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        assert_eq!(thirty, vec![attr(b"alice"), attr(b"carol")]);
+        // Updates move tuples between keys of the working set's own indexes, too.
+        people
+            .update_by_domain(
+                info.encode_key(&[attr(b"jones"), attr(b"carol")]).unwrap(),
+                attr2(31),
+            )
+            .unwrap();
+        assert_eq!(people.seek_by_attributes(0, &[attr2(30)]).unwrap().len(), 1);
+        assert_eq!(people.seek_by_attributes(0, &[attr2(31)]).unwrap().len(), 1);
+        assert_eq!(
+            people.seek_by_attributes(2, &[attr2(30)]),
+            Err(RelationError::NoSecondaryIndex)
+        );
+        tx.commit().unwrap();
+
+        let tx = db.clone().start_tx();
+        let alices = tx
+            .relation(rid)
+            .seek_by_attributes(1, &[attr(b"alice")])
+            .unwrap();
+        assert_eq!(alices.len(), 1);
+    }
+
+    /// Declarations which couldn't be indexed as they say are turned away up front, rather than
+    /// failing on the first tuple written.
+    #[test]
+    fn invalid_relations() {
+        let attribute = |name: &str, attr_type| Attribute {
+            name: name.to_string(),
+            attr_type,
+        };
+        let valid = RelationInfo {
+            name: "pairs".to_string(),
+            domain_type: AttrType::Bytes,
+            codomain_type: AttrType::String,
+            index_type: IndexType::BTree,
+            attributes: vec![
+                attribute("a", AttrType::Integer),
+                attribute("b", AttrType::Integer),
+                attribute("c", AttrType::String),
+            ],
+            key_attributes: 2,
+            ..Default::default()
+        };
+        let invalid = [
+            // An attribute index on an attribute that isn't there.
+            RelationInfo {
+                attribute_indexes: vec![AttributeIndex {
+                    attributes: vec![3],
+                    index_type: IndexType::Hash,
+                }],
+                ..valid.clone()
+            },
+            // A composite key in an integer domain.
+            RelationInfo {
+                domain_type: AttrType::Integer,
+                ..valid.clone()
+            },
+            // Radix trees over strings, and over several attributes.
+            RelationInfo {
+                attribute_indexes: vec![AttributeIndex {
+                    attributes: vec![2],
+                    index_type: IndexType::AdaptiveRadixTree,
+                }],
+                ..valid.clone()
+            },
+            RelationInfo {
+                attribute_indexes: vec![AttributeIndex {
+                    attributes: vec![0, 1],
+                    index_type: IndexType::AdaptiveRadixTree,
+                }],
+                ..valid.clone()
+            },
+            RelationInfo {
+                domain_type: AttrType::String,
+                index_type: IndexType::AdaptiveRadixTree,
+                ..Default::default()
+            },
+        ];
+
+        let db = RelBox::new(1 << 24, None, &[valid.clone()], 0);
+        for info in invalid {
+            assert!(matches!(
+                RelBox::open(1 << 24, None, &[valid.clone(), info.clone()], 0, &[]),
+                Err(SchemaError::InvalidRelation {
+                    relation_id: RelationId(1),
+                    error: RelationError::InvalidRelation(_),
+                })
+            ));
+            let tx = db.clone().start_tx();
+            assert!(matches!(
+                tx.create_relation(info.clone()),
+                Err(RelationError::InvalidRelation(_))
+            ));
+            assert!(matches!(
+                tx.create_transient_relation(info),
+                Err(RelationError::InvalidRelation(_))
+            ));
+        }
+        let tx = db.clone().start_tx();
+        let valid = RelationInfo {
+            attribute_indexes: vec![AttributeIndex {
+                attributes: vec![0],
+                index_type: IndexType::AdaptiveRadixTree,
+            }],
+            ..valid
+        };
+        assert_eq!(tx.create_relation(valid), Ok(RelationId(1)));
+    }
+
+    /// Typed domains are encoded so that ranges over them come back in value order, whatever
+    /// the (ordered) index.
+    #[test]
+    fn typed_domains() {
+        let relation = |name: &str, domain_type, index_type| RelationInfo {
+            name: name.to_string(),
+            domain_type,
+            codomain_type: AttrType::String,
+            secondary_indexed: false,
+            unique_domain: true,
+            index_type,
+            codomain_index_type: None,
+            ..Default::default()
+        };
+        let db = RelBox::new(
+            1 << 24,
+            None,
+            &[
+                relation("ints", AttrType::Integer, IndexType::AdaptiveRadixTree),
+                relation("floats", AttrType::Float, IndexType::BTree),
+                relation("strings", AttrType::String, IndexType::BTree),
+            ],
+            0,
+        );
+        let tx = db.clone().start_tx();
+        let ints = tx.relation(RelationId(0));
+        let floats = tx.relation(RelationId(1));
+        let strings = tx.relation(RelationId(2));
+        for i in [300, -1, 256, 0, -300, 1, i64::MIN] {
+            ints.insert_i64(i, attr(b"v")).unwrap();
+            floats.insert_f64(i as f64 / 2.0, attr(b"v")).unwrap();
+        }
+        for s in ["b", "a", "", "ab", "a\0"] {
+            strings.insert_str(s, attr(b"v")).unwrap();
+        }
+
+        let decoded = |tuples: Vec<TupleRef>| -> Vec<i64> {
+            tuples
+                .iter()
+                .map(|t| i64::decode(&t.domain()).unwrap())
+                .collect()
+        };
+        assert_eq!(
+            decoded(ints.range_i64(-1..=256).unwrap()),
+            vec![-1, 0, 1, 256]
+        );
+        assert_eq!(
+            decoded(ints.range_i64(..).unwrap()),
+            vec![i64::MIN, -300, -1, 0, 1, 256, 300]
+        );
+        assert_eq!(ints.seek_i64(-300).unwrap().len(), 1);
+
+        let halves: Vec<_> = floats
+            .range_f64(-150.0..0.5)
+            .unwrap()
+            .iter()
+            .map(|t| f64::decode(&t.domain()).unwrap())
+            .collect();
+        assert_eq!(halves, vec![-150.0, -0.5, 0.0]);
+        assert_eq!(floats.seek_f64(128.0).unwrap().len(), 1);
+
+        let ordered: Vec<_> = strings
+            .range_typed::<String, _>(..)
+            .unwrap()
+            .iter()
+            .map(|t| String::decode(&t.domain()).unwrap())
+            .collect();
+        assert_eq!(ordered, vec!["", "a", "a\0", "ab", "b"]);
+        assert_eq!(strings.seek_str("ab").unwrap().len(), 1);
+
+        // Values of the wrong type are refused.
+        assert_eq!(
+            strings.insert_i64(1, attr(b"v")),
+            Err(RelationError::TypeMismatch(AttrType::String))
+        );
+        assert_eq!(
+            ints.seek_str("a").unwrap_err(),
+            RelationError::TypeMismatch(AttrType::Integer)
+        );
+        tx.commit().unwrap();
+
+        // And stay in order once committed.
+        let tx = db.clone().start_tx();
+        assert_eq!(
+            decoded(tx.relation(RelationId(0)).range_i64(..0).unwrap()),
+            vec![i64::MIN, -300, -1]
+        );
+    }
+
+    #[test]
+    fn commit_changes() {
+        let db = test_db();
+        let changes = db.subscribe();
+
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(RelationId(0), attr(b"a"), attr(b"1"))
+            .unwrap();
+        tx.insert_tuple(RelationId(0), attr(b"b"), attr(b"2"))
+            .unwrap();
+        tx.commit().unwrap();
+
+        let tx = db.clone().start_tx();
+        tx.update_by_domain(RelationId(0), attr(b"a"), attr(b"3"))
+            .unwrap();
+        tx.remove_by_domain(RelationId(0), attr(b"b")).unwrap();
+        tx.insert_tuple(RelationId(2), attr(b"c"), attr(b"4"))
+            .unwrap();
+        tx.commit().unwrap();
+
+        // Transactions which only read have nothing to report.
+        let tx = db.clone().start_tx();
+        tx.seek_unique_by_domain(RelationId(0), attr(b"a")).unwrap();
+        tx.commit().unwrap();
+
+        let first = changes.try_recv().unwrap();
+        assert_eq!(first.relations.len(), 1);
+        assert_eq!(first.relations[0].relation_id, RelationId(0));
+        let inserts = &first.relations[0].changes;
+        assert_eq!(inserts.len(), 2);
+        for (domain, codomain) in [(b"a", b"1"), (b"b", b"2")] {
+            assert!(inserts.contains(&Change::Insert {
+                domain: attr(domain),
+                codomain: attr(codomain),
+            }));
+        }
+
+        let mut second = changes.try_recv().unwrap();
+        assert!(second.ts > first.ts);
+        second.relations.sort_by_key(|r| r.relation_id);
+        assert_eq!(second.relations.len(), 2);
+        let updated = &second.relations[0];
+        assert_eq!(updated.relation_id, RelationId(0));
+        assert_eq!(updated.changes.len(), 2);
+        assert!(updated.changes.contains(&Change::Update {
+            domain: attr(b"a"),
+            old_codomain: attr(b"1"),
+            new_codomain: attr(b"3"),
+        }));
+        assert!(updated.changes.contains(&Change::Delete {
+            domain: attr(b"b"),
+            old_codomain: attr(b"2"),
+        }));
+        assert_eq!(
+            second.relations[1],
+            RelationChanges {
+                relation_id: RelationId(2),
+                changes: vec![Change::Insert {
+                    domain: attr(b"c"),
+                    codomain: attr(b"4"),
+                }],
+            }
+        );
+        assert!(changes.try_recv().is_err());
+
+        // Dropping a relation deletes what was in it.
+        let tx = db.clone().start_tx();
+        tx.drop_relation(RelationId(2)).unwrap();
+        tx.commit().unwrap();
+        let dropped = changes.try_recv().unwrap();
+        assert_eq!(
+            dropped.relations,
+            vec![RelationChanges {
+                relation_id: RelationId(2),
+                changes: vec![Change::Delete {
+                    domain: attr(b"c"),
+                    old_codomain: attr(b"4"),
+                }],
+            }]
+        );
+
+        // Once the receiver's gone, nothing more is captured for it.
+        drop(changes);
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(RelationId(0), attr(b"d"), attr(b"5"))
+            .unwrap();
+        tx.commit().unwrap();
+        assert!(db.observers.is_empty());
+    }
+
+    #[test]
+    fn key_watches() {
+        let db = test_db();
+        let a = db.watch(RelationId(0), attr(b"a"));
+        let b = db.watch(RelationId(0), attr(b"b"));
+        assert_eq!(a.try_wait(), None);
+
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(RelationId(0), attr(b"a"), attr(b"1"))
+            .unwrap();
+        // The same domain in another relation is another key.
+        tx.insert_tuple(RelationId(2), attr(b"b"), attr(b"1"))
+            .unwrap();
+        tx.commit().unwrap();
+        let inserted = a.try_wait().unwrap();
+        assert_eq!(a.try_wait(), None);
+        assert_eq!(b.try_wait(), None);
+
+        // Waiters block until the key changes.
+        let waiter = std::thread::spawn(move || a.wait_timeout(Duration::from_secs(10)));
+        let tx = db.clone().start_tx();
+        tx.update_by_domain(RelationId(0), attr(b"a"), attr(b"2"))
+            .unwrap();
+        tx.commit().unwrap();
+        let updated = waiter.join().unwrap().unwrap();
+        assert!(updated > inserted);
+
+        let a = db.watch(RelationId(0), attr(b"a"));
+        let tx = db.clone().start_tx();
+        tx.remove_by_domain(RelationId(0), attr(b"a")).unwrap();
+        tx.commit().unwrap();
+        assert!(a.wait() > updated);
+
+        drop(a);
+        drop(b);
+        assert!(db.observers.is_empty());
+    }
+
+    #[test]
+    fn durable_commit_without_storage() {
+        // With nowhere to write it, a commit is as durable as it gets straight away.
+        let db = test_db();
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(RelationId(0), attr(b"a"), attr(b"1"))
+            .unwrap();
+        tx.commit_durable().unwrap();
+
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(RelationId(0), attr(b"b"), attr(b"2"))
+            .unwrap();
+        let token = tx.commit_with_durability().unwrap();
+        token.wait().unwrap();
+    }
+
+    #[test]
+    fn validated_types() {
+        let db = RelBox::new(
+            1 << 24,
+            None,
+            &[
+                RelationInfo {
+                    name: "ages".to_string(),
+                    domain_type: AttrType::String,
+                    codomain_type: AttrType::Integer,
+                    secondary_indexed: false,
+                    unique_domain: true,
+                    index_type: IndexType::Hash,
+                    codomain_index_type: None,
+                    validate_types: true,
+                    ..Default::default()
+                },
+                RelationInfo {
+                    name: "points".to_string(),
+                    domain_type: AttrType::Bytes,
+                    codomain_type: AttrType::Float,
+                    secondary_indexed: false,
+                    unique_domain: true,
+                    index_type: IndexType::BTree,
+                    codomain_index_type: None,
+                    attributes: vec![
+                        Attribute {
+                            name: "x".to_string(),
+                            attr_type: AttrType::Integer,
+                        },
+                        Attribute {
+                            name: "y".to_string(),
+                            attr_type: AttrType::Integer,
+                        },
+                        Attribute {
+                            name: "weight".to_string(),
+                            attr_type: AttrType::Float,
+                        },
+                    ],
+                    key_attributes: 2,
+                    validate_types: true,
+                    ..Default::default()
+                },
+            ],
+            0,
+        );
+        let tx = db.clone().start_tx();
+        let ages = tx.relation(RelationId(0));
+        ages.insert_tuple(attr(b"bob"), 42i64.encode()).unwrap();
+        ages.insert_tuple("alice".to_string().encode(), 7i64.encode())
+            .unwrap();
+        assert_eq!(
+            ages.insert_tuple(attr(b"carol"), attr(b"42")),
+            Err(RelationError::TypeMismatch(AttrType::Integer))
+        );
+        assert_eq!(
+            ages.insert_tuple(attr(b"\xff"), 42i64.encode()),
+            Err(RelationError::TypeMismatch(AttrType::String))
+        );
+        assert_eq!(
+            ages.update_by_domain(attr(b"bob"), attr(b"old")),
+            Err(RelationError::TypeMismatch(AttrType::Integer))
+        );
+        assert_eq!(
+            ages.upsert_by_domain(attr(b"dave"), attr(b"")),
+            Err(RelationError::TypeMismatch(AttrType::Integer))
+        );
+        ages.update_by_domain(attr(b"bob"), 43i64.encode()).unwrap();
+
+        let points = tx.relation(RelationId(1));
+        points
+            .insert_attributes(&[1i64.encode(), 2i64.encode(), 0.5f64.encode()])
+            .unwrap();
+        assert_eq!(
+            points.insert_attributes(&[1i64.encode(), attr(b"2"), 0.5f64.encode()]),
+            Err(RelationError::TypeMismatch(AttrType::Integer))
+        );
+        // A domain which isn't packed from the right number of attributes is refused too.
+        assert_eq!(
+            points.insert_tuple(attr(b"12"), 0.5f64.encode()),
+            Err(RelationError::TypeMismatch(AttrType::Integer))
+        );
+        tx.commit().unwrap();
+
+        let tx = db.clone().start_tx();
+        let ages = tx.relation(RelationId(0));
+        let bob = ages.seek_unique_by_domain(attr(b"bob")).unwrap();
+        assert_eq!(i64::decode(&bob.codomain()).unwrap(), 43);
+    }
+
+    #[test]
+    fn disjoint_commits() {
+        let db = test_db();
+        // While a commit to one relation is (as good as) in progress...
+        let slot = db.canonical.read().unwrap()[0].clone();
+        let held = slot.commit_lock.lock(LockWait::Forever).unwrap();
+
+        // ...commits to others go ahead,
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(RelationId(1), attr2(1), attr(b"1"))
+            .unwrap();
+        tx.commit().unwrap();
+
+        // while those to the same relation wait their turn.
+        let waiting = {
+            let db = db.clone();
+            std::thread::spawn(move || {
+                let tx = db.start_tx();
+                tx.set_commit_policy(CommitPolicy::wait());
+                tx.insert_tuple(RelationId(0), attr(b"a"), attr(b"1"))
+                    .unwrap();
+                tx.commit()
+            })
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+        drop(held);
+        assert_eq!(waiting.join().unwrap().unwrap().attempts, 1);
+
+        let canonical = db.copy_canonical();
+        assert_eq!(canonical[0].predicate_scan(&|_| true).len(), 1);
+        assert_eq!(canonical[1].predicate_scan(&|_| true).len(), 1);
+    }
+
+    #[test]
+    fn commits_published_in_order() {
+        let db = test_db();
+        let changes = db.subscribe();
+
+        // The first commit takes its timestamp, then is held up. (Timestamps are consecutive until
+        // it takes one.)
+        let first = db.clone().start_tx();
+        first
+            .insert_tuple(RelationId(0), attr(b"a"), attr(b"1"))
+            .unwrap();
+        let held_slot = db.canonical.read().unwrap()[0].clone();
+        let held = held_slot.relation.write().unwrap();
+        let mut last_ts = db.clone().next_ts();
+        let committer = std::thread::spawn(move || first.commit());
+        loop {
+            let ts = db.clone().next_ts();
+            if ts > last_ts + 1 {
+                break;
+            }
+            last_ts = ts;
+            std::thread::yield_now();
+        }
+
+        // A later commit to another relation goes through, but isn't published ahead of it.
+        let second = db.clone().start_tx();
+        second
+            .insert_tuple(RelationId(1), attr2(1), attr(b"1"))
+            .unwrap();
+        second.commit().unwrap();
+        assert!(changes.try_recv().is_err());
+
+        drop(held);
+        committer.join().unwrap().unwrap();
+        let first = changes.try_recv().unwrap();
+        let second = changes.try_recv().unwrap();
+        assert!(first.ts < second.ts);
+        assert_eq!(first.relations[0].relation_id, RelationId(0));
+        assert_eq!(second.relations[0].relation_id, RelationId(1));
+    }
+
+    #[test]
+    fn snapshots_during_commits() {
+        let db = test_db();
+        let ids = db
+            .create_sequence("ids", SequenceKind::Transactional)
+            .unwrap();
+        let tx = db.clone().start_tx();
+        tx.increment_sequence(ids).unwrap();
+        tx.insert_tuple(RelationId(0), attr(b"a"), attr(b"1"))
+            .unwrap();
+
+        // A commit in progress (held up here on the sequence it moved)...
+        let sequence = db.sequence(ids).unwrap();
+        let held = sequence.value.lock().unwrap();
+        let committer = std::thread::spawn(move || tx.commit());
+        std::thread::sleep(Duration::from_millis(50));
+
+        // ...doesn't hold up read transactions, which see things as they were before it.
+        let read_tx = db.start_read_tx();
+        assert!(read_tx
+            .seek_by_domain(RelationId(0), attr(b"a"))
+            .unwrap()
+            .is_empty());
+
+        drop(held);
+        committer.join().unwrap().unwrap();
+        let read_tx = db.start_read_tx();
+        assert_eq!(
+            read_tx
+                .seek_by_domain(RelationId(0), attr(b"a"))
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn commit_policies() {
+        let db = test_db();
+        let slot = db.canonical.read().unwrap()[0].clone();
+        let held = slot.commit_lock.lock(LockWait::Forever).unwrap();
+        let contended = |policy: Option<CommitPolicy>| {
+            let tx = db.clone().start_tx();
+            if let Some(policy) = policy {
+                tx.set_commit_policy(policy);
+            }
+            tx.insert_tuple(RelationId(0), attr(b"a"), attr(b"1"))
+                .unwrap();
+            tx.commit()
+        };
+
+        // By default, a commit makes a bounded number of attempts, rather than waiting forever.
+        assert_eq!(
+            CommitPolicy::default(),
+            CommitPolicy {
+                max_attempts: Some(51),
+                deadline: None,
+                backoff: Backoff::Yield,
+            }
+        );
+        assert_eq!(
+            contended(None),
+            Err(CommitError::RelationContentionConflict { attempts: 51 })
+        );
+
+        // The database's policy applies unless the transaction has its own. Policies limited to a
+        // number of attempts don't wait for the relations, but back off between attempts.
+        db.set_commit_policy(CommitPolicy::fail_fast());
+        assert_eq!(
+            contended(None),
+            Err(CommitError::RelationContentionConflict { attempts: 1 })
+        );
+        assert_eq!(
+            contended(Some(CommitPolicy {
+                max_attempts: Some(3),
+                deadline: None,
+                backoff: Backoff::Exponential {
+                    initial: Duration::from_millis(1),
+                    max: Duration::from_millis(4),
+                    jitter: true,
+                },
+            })),
+            Err(CommitError::RelationContentionConflict { attempts: 3 })
+        );
+
+        // A deadline bounds how long the commit waits for them, however many attempts are
+        // allowed.
+        let started = std::time::Instant::now();
+        let result = contended(Some(CommitPolicy {
+            max_attempts: None,
+            deadline: Some(Duration::from_millis(20)),
+            backoff: Backoff::Yield,
+        }));
+        assert!(started.elapsed() >= Duration::from_millis(20));
+        assert_eq!(
+            result,
+            Err(CommitError::RelationContentionConflict { attempts: 1 })
+        );
+
+        // Uncontended commits take the one attempt.
+        drop(held);
+        assert_eq!(contended(None).unwrap().attempts, 1);
+    }
+
+    #[test]
+    fn transactions_move_between_threads() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Transaction>();
+
+        let db = test_db();
+        let tx = db.clone().start_tx();
+        tx.insert_tuple(RelationId(0), attr(b"a"), attr(b"1"))
+            .unwrap();
+        // Carried to another thread part way through, and finished there.
+        std::thread::spawn(move || {
+            tx.insert_tuple(RelationId(0), attr(b"b"), attr(b"2"))
+                .unwrap();
+            tx.commit().unwrap();
+        })
+        .join()
+        .unwrap();
+
+        let tx = db.clone().start_tx();
+        for (domain, codomain) in [(b"a", b"1"), (b"b", b"2")] {
+            let tuple = tx
+                .seek_unique_by_domain(RelationId(0), attr(domain))
+                .unwrap();
+            assert_eq!(tuple.codomain(), attr(codomain));
+        }
+    }
+
+    #[test]
+    fn merge_operators() {
+        let db = test_db();
+        let counters = RelationId(2);
+        db.set_merge_operator(counters, MergeOperator::AddInteger)
+            .unwrap();
+        let count = |tx: &Transaction| {
+            let tuple = tx.seek_unique_by_domain(counters, attr(b"hits")).unwrap();
+            i64::decode(&tuple.codomain()).unwrap()
+        };
+
+        // Concurrent merges into the same (not yet existing) tuple all commit, even serializably.
+        let tx1 = db.clone().start_tx();
+        let tx2 = db.clone().start_serializable_tx();
+        tx1.merge_by_domain(counters, attr(b"hits"), 1i64.encode())
+            .unwrap();
+        tx1.merge_by_domain(counters, attr(b"hits"), 3i64.encode())
+            .unwrap();
+        tx2.merge_by_domain(counters, attr(b"hits"), 2i64.encode())
+            .unwrap();
+        assert_eq!(count(&tx1), 4);
+        tx1.commit().unwrap();
+        tx2.commit().unwrap();
+        assert_eq!(count(&db.clone().start_tx()), 6);
+
+        // Writing the tuple outright supersedes earlier merges, and conflicts as usual.
+        let tx1 = db.clone().start_tx();
+        let tx2 = db.clone().start_tx();
+        tx1.merge_by_domain(counters, attr(b"hits"), 1i64.encode())
+            .unwrap();
+        tx1.upsert_by_domain(counters, attr(b"hits"), 10i64.encode())
+            .unwrap();
+        tx2.merge_by_domain(counters, attr(b"hits"), 1i64.encode())
+            .unwrap();
+        tx2.commit().unwrap();
+        assert!(matches!(
+            tx1.commit(),
+            Err(CommitError::TupleVersionConflict(_))
+        ));
+        assert_eq!(count(&db.clone().start_tx()), 7);
+
+        // Sets union, whichever order the merges commit in.
+        let tags = RelationId(0);
+        db.set_merge_operator(tags, MergeOperator::Union).unwrap();
+        let tx1 = db.clone().start_tx();
+        let tx2 = db.clone().start_tx();
+        tx1.merge_by_domain(tags, attr(b"x"), encode_list(&[attr(b"b"), attr(b"a")]))
+            .unwrap();
+        tx2.merge_by_domain(tags, attr(b"x"), encode_list(&[attr(b"c"), attr(b"b")]))
+            .unwrap();
+        tx2.commit().unwrap();
+        tx1.commit().unwrap();
+        let tx = db.clone().start_tx();
+        let tuple = tx.seek_unique_by_domain(tags, attr(b"x")).unwrap();
+        assert_eq!(
+            decode_list(&tuple.codomain()).unwrap(),
+            vec![attr(b"a"), attr(b"b"), attr(b"c")]
+        );
+
+        // Only relations given a merge operator can be merged into.
+        assert_eq!(
+            tx.merge_by_domain(RelationId(1), attr2(1), attr(b"1")),
+            Err(RelationError::NoMergeOperator)
+        );
+        assert_eq!(
+            db.set_merge_operator(RelationId(99), MergeOperator::Append),
+            Err(RelationError::RelationNotFound)
+        );
+    }
+
+    #[test]
+    fn sequences() {
+        let db = test_db();
+        let ids = db
+            .create_sequence("ids", SequenceKind::Transactional)
+            .unwrap();
+        let tickets = db
+            .create_sequence("tickets", SequenceKind::Immediate)
+            .unwrap();
+        assert_eq!(db.sequence_id("ids"), Some(ids));
+        assert_eq!(db.sequence_id("nope"), None);
+        assert_eq!(
+            db.create_sequence("ids", SequenceKind::Immediate),
+            Err(RelationError::SequenceExists("ids".to_string()))
+        );
+        assert_eq!(
+            db.create_sequence("", SequenceKind::Immediate),
+            Err(RelationError::EmptySequenceName)
+        );
+
+        // Transactional increments are only seen by their transaction until it commits, and
+        // concurrent ones conflict.
+        let tx1 = db.clone().start_tx();
+        let tx2 = db.clone().start_tx();
+        assert_eq!(tx1.increment_sequence(ids), Ok(-1));
+        assert_eq!(tx1.reserve_sequence(ids, 3), Ok(0..3));
+        assert_eq!(tx1.sequence_current(ids), Ok(3));
+        assert_eq!(db.clone().sequence_current(ids), Ok(-1));
+        assert_eq!(tx2.increment_sequence(ids), Ok(-1));
+        tx1.commit().unwrap();
+        assert_eq!(db.clone().sequence_current(ids), Ok(3));
+        assert_eq!(
+            tx2.commit(),
+            Err(CommitError::SequenceConflict { sequence: ids })
+        );
+
+        // Rolling back undoes them, as does rolling back to a savepoint.
+        let tx = db.clone().start_tx();
+        tx.reserve_sequence(ids, 5).unwrap();
+        tx.rollback().unwrap();
+        let tx = db.clone().start_tx();
+        tx.increment_sequence(ids).unwrap();
+        let savepoint = tx.savepoint();
+        tx.reserve_sequence(ids, 5).unwrap();
+        tx.rollback_to(&savepoint).unwrap();
+        // Not moving it doesn't count.
+        tx.update_sequence_max(ids, 0).unwrap();
+        tx.commit().unwrap();
+        assert_eq!(db.clone().sequence_current(ids), Ok(4));
+
+        // Immediate increments take effect whatever becomes of the transaction.
+        let tx = db.clone().start_tx();
+        assert_eq!(tx.reserve_sequence(tickets, 10), Ok(-1..9));
+        assert_eq!(db.clone().sequence_current(tickets), Ok(9));
+        tx.rollback().unwrap();
+        assert_eq!(db.clone().increment_sequence(tickets), Ok(9));
+        db.clone().update_sequence_max(tickets, 20).unwrap();
+        assert_eq!(db.clone().sequence_current(tickets), Ok(20));
+
+        // Sequences that don't exist are an error, rather than a panic.
+        let missing = tickets + 1;
+        let tx = db.clone().start_tx();
+        assert_eq!(
+            tx.increment_sequence(missing),
+            Err(RelationError::SequenceNotFound(missing))
+        );
+        assert_eq!(
+            tx.sequence_current(missing),
+            Err(RelationError::SequenceNotFound(missing))
+        );
+        assert_eq!(
+            db.clone().reserve_sequence(missing, 2),
+            Err(RelationError::SequenceNotFound(missing))
+        );
+        assert_eq!(
+            db.clone().update_sequence_max(missing, 2),
+            Err(RelationError::SequenceNotFound(missing))
+        );
+    }
+
     // TODO: More tests for transaction.rs and transactions generally
     //    Loom tests? Stateright tests?
-    //    Test sequences & their behaviour
     //    Consistency across multiple relations
     //    Index consistency, secondary index consistency
 }
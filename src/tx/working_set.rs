@@ -12,42 +12,103 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::{Bound, Range};
 use std::sync::Arc;
 use tracing::{error, warn};
 
+use daumtils::SliceRef;
 use daumtils::{BitArray, Bitset64};
-use daumtils::{PhantomUnsync, SliceRef};
 
-use crate::index::{pick_tx_index, Index};
+use crate::base_relation::BaseRelation;
+use crate::index::{pick_tx_attribute_indexes, pick_tx_index, Index};
 use crate::paging::TupleBox;
-use crate::relbox::{RelBox, RelationInfo};
+use crate::relbox::{RelBox, RelationInfo, Sequence};
 use crate::tuples::{TupleId, TupleRef};
+use crate::tx::merge::MergeOperator;
+use crate::tx::read_set::ReadSet;
 use crate::tx::tx_tuple::{DataSource, OpSource, TupleApply, TxTupleEvent, TxTupleOp};
 use crate::{RelationError, RelationId};
 
 /// The local tx "working set" of mutations to base relations, and consists of the set of operations
 /// we will attempt to make permanent when the transaction commits.
 /// The working set is also referred to for reads/updates during the lifetime of the transaction.  
+/// How many base relations there can be. Their ids index fixed-size bit arrays, and ids of dropped
+/// relations aren't reused.
+pub(crate) const MAX_RELATIONS: usize = 64;
+
 /// It effectively "is" the transaction in regards to *base relations*.
 pub struct WorkingSet {
     pub(crate) ts: u64,
-    pub(crate) schema: Vec<RelationInfo>,
+    /// The schema as this transaction sees it, indexed by relation id. Dropped relations are `None`.
+    pub(crate) schema: Vec<Option<RelationInfo>>,
+    /// Relations created or dropped by this transaction, in the order it did so, to be applied at
+    /// commit.
+    pub(crate) schema_changes: Vec<SchemaChange>,
     pub(crate) tuplebox: Arc<TupleBox>,
-    pub(crate) relations: Box<BitArray<TxBaseRelation, 64, Bitset64<1>>>,
+    pub(crate) relations: Box<BitArray<TxBaseRelation, MAX_RELATIONS, Bitset64<1>>>,
+    /// The queries made by this transaction, if it's running serializably.
+    pub(crate) read_set: Option<ReadSet>,
+    /// The transactional sequences this transaction has moved, by id.
+    pub(crate) sequences: BTreeMap<usize, TxSequence>,
+    /// Relations created by (and private to) this transaction. They live only here, and so are never
+    /// part of a commit.
+    transients: Vec<TxBaseRelation>,
+    /// Empty stand-ins for the canonical versions of relations which only exist in this
+    /// transaction (transient relations, and relations it created), so that reads can treat them
+    /// like any other relation.
+    local_canonical: HashMap<RelationId, BaseRelation>,
+    /// The state of the working set as of each open savepoint, innermost last.
+    savepoints: Vec<SavedState>,
+    next_savepoint: usize,
+}
+
+/// A transactional sequence, as moved by a transaction.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TxSequence {
+    /// Its value when the transaction first moved it, which it must still have at commit.
+    pub(crate) read: i64,
+    /// Its value as far as the transaction is concerned.
+    pub(crate) value: i64,
+}
+
+/// A point in a transaction which its working set can be rolled back to.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Savepoint(usize);
 
-    unsync: PhantomUnsync,
+/// Copies of the relations (and schema) as they were when a savepoint was taken.
+struct SavedState {
+    id: usize,
+    relations: Vec<TxBaseRelation>,
+    transients: Vec<TxBaseRelation>,
+    schema: Vec<Option<RelationInfo>>,
+    schema_changes: usize,
+    local_canonical: HashMap<RelationId, BaseRelation>,
+    sequences: BTreeMap<usize, TxSequence>,
+}
+
+/// A change to the set of base relations, made by a transaction.
+#[derive(Clone, Debug)]
+pub(crate) enum SchemaChange {
+    Create(RelationId, RelationInfo),
+    Drop(RelationId),
 }
 
 impl WorkingSet {
-    pub(crate) fn new(slotbox: Arc<TupleBox>, schema: &[RelationInfo], ts: u64) -> Self {
+    pub(crate) fn new(slotbox: Arc<TupleBox>, schema: &[Option<RelationInfo>], ts: u64) -> Self {
         let relations = Box::new(BitArray::new());
         Self {
             ts,
             tuplebox: slotbox,
             schema: schema.to_vec(),
+            schema_changes: vec![],
             relations,
-            unsync: Default::default(),
+            read_set: None,
+            sequences: BTreeMap::new(),
+            transients: vec![],
+            local_canonical: HashMap::new(),
+            savepoints: vec![],
+            next_savepoint: 0,
         }
     }
 
@@ -56,31 +117,245 @@ impl WorkingSet {
             // let Some(rel) = rel else { continue };
             rel.1.clear();
         }
+        for rel in self.transients.iter_mut() {
+            rel.clear();
+        }
+        self.sequences.clear();
     }
 
-    fn get_relation_mut<'a>(
+    /// The schema of the given (base or transient) relation.
+    pub(crate) fn relation_info(
+        &self,
         relation_id: RelationId,
-        schema: &[RelationInfo],
-        relations: &'a mut BitArray<TxBaseRelation, 64, Bitset64<1>>,
-    ) -> &'a mut TxBaseRelation {
-        if relations.check(relation_id.0) {
-            return relations.get_mut(relation_id.0).unwrap();
+    ) -> Result<&RelationInfo, RelationError> {
+        if relation_id.is_transient_relation() {
+            return self
+                .transients
+                .get(relation_id.transient_index())
+                .map(|r| &r.relation_info)
+                .ok_or(RelationError::RelationNotFound);
         }
-        let r = &schema[relation_id.0];
+        self.schema
+            .get(relation_id.0)
+            .and_then(Option::as_ref)
+            .ok_or(RelationError::RelationNotFound)
+    }
 
-        let (domain_index, codomain_index) = pick_tx_index(r);
+    /// Create a new, empty, relation private to this working set.
+    pub(crate) fn create_transient_relation(
+        &mut self,
+        relation_info: RelationInfo,
+    ) -> Result<RelationId, RelationError> {
+        relation_info.validate()?;
+        let relation_id = RelationId::transient(self.transients.len());
+        self.local_canonical.insert(
+            relation_id,
+            BaseRelation::new(relation_id, relation_info.clone(), self.ts),
+        );
+        self.transients
+            .push(TxBaseRelation::new(relation_id, relation_info));
+        Ok(relation_id)
+    }
 
-        let new_relation = TxBaseRelation {
-            id: relation_id,
-            relation_info: r.clone(),
-            tx_tuple_events: HashMap::new(),
-            domain_index,
-            codomain_index,
-            unsync: Default::default(),
+    /// Create a new base relation, which comes into being (for everyone else) at commit. It takes
+    /// the next unused relation id, unless they've run out.
+    pub(crate) fn create_relation(
+        &mut self,
+        relation_info: RelationInfo,
+    ) -> Result<RelationId, RelationError> {
+        relation_info.validate()?;
+        let relation_id = RelationId(self.schema.len());
+        if relation_id.0 >= MAX_RELATIONS {
+            return Err(RelationError::TooManyRelations(MAX_RELATIONS));
+        }
+        self.local_canonical.insert(
+            relation_id,
+            BaseRelation::new(relation_id, relation_info.clone(), self.ts),
+        );
+        self.schema.push(Some(relation_info.clone()));
+        self.schema_changes
+            .push(SchemaChange::Create(relation_id, relation_info));
+        Ok(relation_id)
+    }
+
+    /// Drop a base relation, discarding any changes made to it so far. Its id is not reused.
+    pub(crate) fn drop_relation(&mut self, relation_id: RelationId) -> Result<(), RelationError> {
+        if relation_id.is_transient_relation() {
+            return Err(RelationError::RelationNotFound);
+        }
+        let Some(info) = self.schema.get_mut(relation_id.0) else {
+            return Err(RelationError::RelationNotFound);
         };
+        if info.take().is_none() {
+            return Err(RelationError::RelationNotFound);
+        }
+        if let Some(relation) = self.relations.get_mut(relation_id.0) {
+            relation.clear();
+        }
+        self.schema_changes.push(SchemaChange::Drop(relation_id));
+        Ok(())
+    }
+
+    /// The relations committing this working set has to lock: those it holds tuples from, those it
+    /// drops, and (if it's serializable) those it read from.
+    pub(crate) fn touched_relations(&self) -> BTreeSet<RelationId> {
+        let mut touched: BTreeSet<_> = self.relations.iter().map(|(_, r)| r.id).collect();
+        touched.extend(
+            self.schema_changes
+                .iter()
+                .filter_map(|change| match change {
+                    SchemaChange::Drop(relation_id) => Some(*relation_id),
+                    SchemaChange::Create(..) => None,
+                }),
+        );
+        if let Some(read_set) = &self.read_set {
+            touched.extend(read_set.relations());
+        }
+        touched
+    }
+
+    /// Record the current state of the working set, to be returned to by `rollback_to`.
+    pub(crate) fn savepoint(&mut self) -> Savepoint {
+        let id = self.next_savepoint;
+        self.next_savepoint += 1;
+        let relations = self.relations.iter().map(|(_, r)| r.fork()).collect();
+        let transients = self.transients.iter().map(|r| r.fork()).collect();
+        self.savepoints.push(SavedState {
+            id,
+            relations,
+            transients,
+            schema: self.schema.clone(),
+            schema_changes: self.schema_changes.len(),
+            local_canonical: self.local_canonical.clone(),
+            sequences: self.sequences.clone(),
+        });
+        Savepoint(id)
+    }
+
+    /// Restore the working set to how it was at `savepoint`, discarding any savepoints taken since.
+    /// The savepoint itself remains, and can be rolled back to again. Tuples allocated since are
+    /// freed as their last references (here) are dropped.
+    pub(crate) fn rollback_to(&mut self, savepoint: &Savepoint) -> Result<(), RelationError> {
+        let position = self.savepoint_position(savepoint)?;
+        self.savepoints.truncate(position + 1);
+        let saved = &self.savepoints[position];
+        let mut relations = Box::new(BitArray::new());
+        for relation in &saved.relations {
+            relations.set(relation.id.0, relation.fork());
+        }
+        self.relations = relations;
+        // Relations created (or dropped) since the savepoint go away (or come back) with it.
+        self.transients = saved.transients.iter().map(|r| r.fork()).collect();
+        self.schema = saved.schema.clone();
+        self.schema_changes.truncate(saved.schema_changes);
+        self.local_canonical = saved.local_canonical.clone();
+        self.sequences = saved.sequences.clone();
+        Ok(())
+    }
+
+    /// Discard `savepoint` (and any taken since), keeping the changes made after it.
+    pub(crate) fn release(&mut self, savepoint: Savepoint) -> Result<(), RelationError> {
+        let position = self.savepoint_position(&savepoint)?;
+        self.savepoints.truncate(position);
+        Ok(())
+    }
+
+    fn savepoint_position(&self, savepoint: &Savepoint) -> Result<usize, RelationError> {
+        self.savepoints
+            .iter()
+            .position(|saved| saved.id == savepoint.0)
+            .ok_or(RelationError::SavepointNotFound)
+    }
+
+    /// The current value of a transactional sequence, as this transaction sees it.
+    pub(crate) fn sequence_current(&self, sequence_number: usize, sequence: &Sequence) -> i64 {
+        match self.sequences.get(&sequence_number) {
+            Some(moved) => moved.value,
+            None => *sequence.value.lock().unwrap(),
+        }
+    }
+
+    /// Take the next `count` values of a transactional sequence, for this transaction.
+    pub(crate) fn reserve_sequence(
+        &mut self,
+        sequence_number: usize,
+        sequence: &Sequence,
+        count: u32,
+    ) -> Range<i64> {
+        let moved = self.moved_sequence(sequence_number, sequence);
+        let start = moved.value;
+        moved.value += i64::from(count);
+        start..moved.value
+    }
+
+    /// Move a transactional sequence up to `value`, for this transaction, if it's behind it.
+    pub(crate) fn update_sequence_max(
+        &mut self,
+        sequence_number: usize,
+        sequence: &Sequence,
+        value: i64,
+    ) {
+        if self.sequence_current(sequence_number, sequence) < value {
+            self.moved_sequence(sequence_number, sequence).value = value;
+        }
+    }
+
+    fn moved_sequence(&mut self, sequence_number: usize, sequence: &Sequence) -> &mut TxSequence {
+        self.sequences.entry(sequence_number).or_insert_with(|| {
+            let value = *sequence.value.lock().unwrap();
+            TxSequence { read: value, value }
+        })
+    }
+
+    fn get_relation_mut<'a>(
+        relation_id: RelationId,
+        schema: &[Option<RelationInfo>],
+        relations: &'a mut BitArray<TxBaseRelation, MAX_RELATIONS, Bitset64<1>>,
+        transients: &'a mut [TxBaseRelation],
+    ) -> Result<&'a mut TxBaseRelation, RelationError> {
+        if relation_id.is_transient_relation() {
+            return transients
+                .get_mut(relation_id.transient_index())
+                .ok_or(RelationError::RelationNotFound);
+        }
+        // (Dropped relations, and those created since we started, aren't in our schema.)
+        let relation_info = schema
+            .get(relation_id.0)
+            .and_then(Option::as_ref)
+            .ok_or(RelationError::RelationNotFound)?;
+        if relations.check(relation_id.0) {
+            return Ok(relations.get_mut(relation_id.0).unwrap());
+        }
+        let new_relation = TxBaseRelation::new(relation_id, relation_info.clone());
 
         relations.set(relation_id.0, new_relation);
-        relations.get_mut(relation_id.0).unwrap()
+        Ok(relations.get_mut(relation_id.0).unwrap())
+    }
+
+    /// Run `f` against the canonical version of the relation. (For relations local to the
+    /// transaction, that's an empty relation.)
+    fn with_canonical<R, F: Fn(&BaseRelation) -> R>(
+        db: &Arc<RelBox>,
+        local_canonical: &HashMap<RelationId, BaseRelation>,
+        relation_id: RelationId,
+        f: F,
+    ) -> R {
+        if let Some(relation) = local_canonical.get(&relation_id) {
+            return f(relation);
+        }
+        db.with_relation(relation_id, f)
+    }
+
+    /// The version of the canonical relation `with_canonical` would read, for the read set.
+    fn canonical_version(
+        db: &Arc<RelBox>,
+        local_canonical: &HashMap<RelationId, BaseRelation>,
+        relation_id: RelationId,
+    ) -> Option<u64> {
+        match local_canonical.get(&relation_id) {
+            Some(relation) => Some(relation.ts),
+            None => db.relation_version(relation_id),
+        }
     }
 
     pub(crate) fn seek_by_domain(
@@ -89,15 +364,30 @@ impl WorkingSet {
         relation_id: RelationId,
         domain: SliceRef,
     ) -> Result<HashSet<TupleRef>, RelationError> {
-        let relation = Self::get_relation_mut(relation_id, &self.schema, self.relations.as_mut());
+        if let Some(read_set) = &mut self.read_set {
+            read_set.observe(relation_id, || {
+                Self::canonical_version(db, &self.local_canonical, relation_id)
+            });
+            read_set.read_domain(relation_id, &domain);
+        }
+        let relation = Self::get_relation_mut(
+            relation_id,
+            &self.schema,
+            &mut self.relations,
+            &mut self.transients,
+        )?;
 
         // Get the list of matches from the base relation, and then apply the local working set overtop.
-        let tuples = db.with_relation(relation_id, |relation| {
+        let tuples = Self::with_canonical(db, &self.local_canonical, relation_id, |relation| {
             relation.seek_by_domain(domain.clone())
         })?;
 
-        // Stash local references to the tuple we've seen, in case updates happen upstream.
+        // Stash local references to the tuple we've seen, in case updates happen upstream. (Unless
+        // we already hold a local version of it, which takes precedence.)
         for t in tuples {
+            if relation.has_local_version(&t)? {
+                continue;
+            }
             let apply = TupleApply {
                 data_source: DataSource::Base,
                 op_source: OpSource::Seek,
@@ -108,7 +398,12 @@ impl WorkingSet {
             relation.tuple_apply(apply)?;
         }
 
-        let relation = Self::get_relation_mut(relation_id, &self.schema, &mut self.relations);
+        let relation = Self::get_relation_mut(
+            relation_id,
+            &self.schema,
+            &mut self.relations,
+            &mut self.transients,
+        )?;
         let domain_tuples = relation.domain_index.seek(&domain)?;
         let tuples = domain_tuples.filter_map(|tid| {
             let t = relation.tx_tuple_events.get(&tid).unwrap();
@@ -123,13 +418,86 @@ impl WorkingSet {
         Ok(tuples.collect())
     }
 
+    /// Seek for all tuples whose domain falls within the given bounds, in domain order (or reverse
+    /// domain order), with the local working set applied over top of the canonical relation.
+    pub(crate) fn seek_range_by_domain(
+        &mut self,
+        db: &Arc<RelBox>,
+        relation_id: RelationId,
+        lower: Bound<&SliceRef>,
+        upper: Bound<&SliceRef>,
+        reverse: bool,
+    ) -> Result<Vec<TupleRef>, RelationError> {
+        let relation = Self::get_relation_mut(
+            relation_id,
+            &self.schema,
+            &mut self.relations,
+            &mut self.transients,
+        )?;
+        if !relation.relation_info.index_type.is_ordered() {
+            return Err(RelationError::UnorderedIndex);
+        }
+        if let Some(read_set) = &mut self.read_set {
+            read_set.observe(relation_id, || {
+                Self::canonical_version(db, &self.local_canonical, relation_id)
+            });
+            read_set.read_range(relation_id, lower, upper);
+        }
+
+        let tuples = Self::with_canonical(db, &self.local_canonical, relation_id, |relation| {
+            relation.seek_range_by_domain(lower, upper, false)
+        })?;
+
+        // Stash local references to the tuples we've seen, in case updates happen upstream. Tuples
+        // we already have a local version of (updated, removed, or previously seen) take precedence
+        // over the canonical copy.
+        for t in tuples {
+            if relation.has_local_version(&t)? {
+                continue;
+            }
+            let apply = TupleApply {
+                data_source: DataSource::Base,
+                op_source: OpSource::Seek,
+                replacement_op: Some(TxTupleOp::Value(t.clone())),
+                add_tuple: Some(t.clone()),
+                del_tuple: None,
+            };
+            relation.tuple_apply(apply)?;
+        }
+
+        // The local index now holds everything in range, both from canonical and our own inserts
+        // and updates, so it can produce the merged, ordered result.
+        let tuple_ids = relation.domain_index.seek_range(lower, upper, reverse)?;
+        let tuples = tuple_ids.filter_map(|tid| {
+            let t = relation.tx_tuple_events.get(&tid).unwrap();
+            match &t.op {
+                TxTupleOp::Insert(t)
+                | TxTupleOp::Update { to_tuple: t, .. }
+                | TxTupleOp::Value(t) => Some(t.clone()),
+                TxTupleOp::Tombstone { .. } => None,
+            }
+        });
+        Ok(tuples.collect())
+    }
+
     pub(crate) fn seek_unique_by_domain(
         &mut self,
         db: &Arc<RelBox>,
         relation_id: RelationId,
         domain: SliceRef,
     ) -> Result<TupleRef, RelationError> {
-        let relation = Self::get_relation_mut(relation_id, &self.schema, self.relations.as_mut());
+        if let Some(read_set) = &mut self.read_set {
+            read_set.observe(relation_id, || {
+                Self::canonical_version(db, &self.local_canonical, relation_id)
+            });
+            read_set.read_domain(relation_id, &domain);
+        }
+        let relation = Self::get_relation_mut(
+            relation_id,
+            &self.schema,
+            &mut self.relations,
+            &mut self.transients,
+        )?;
 
         // Check local first.
         {
@@ -149,7 +517,7 @@ impl WorkingSet {
                 };
             }
         }
-        let canon_t = db.with_relation(relation_id, |relation| {
+        let canon_t = Self::with_canonical(db, &self.local_canonical, relation_id, |relation| {
             let tuples = relation.seek_by_domain(domain.clone())?;
             if tuples.is_empty() {
                 return Err(RelationError::TupleNotFound);
@@ -181,19 +549,30 @@ impl WorkingSet {
         relation_id: RelationId,
         codomain: SliceRef,
     ) -> Result<HashSet<TupleRef>, RelationError> {
+        if let Some(read_set) = &mut self.read_set {
+            read_set.observe(relation_id, || {
+                Self::canonical_version(db, &self.local_canonical, relation_id)
+            });
+            read_set.read_codomain(relation_id, &codomain);
+        }
         // The codomain index is not guaranteed to be up to date with the working set, so we need
         // to go back to the canonical relation, get the list of tuples for the codomain, then materialize
         // them into our local working set -- which will update the codomain index -- and then actually
         // use the local index.  Complicated enough?
         let tuples_for_codomain = {
-            let relation = Self::get_relation_mut(relation_id, &self.schema, &mut self.relations);
+            let relation = Self::get_relation_mut(
+                relation_id,
+                &self.schema,
+                &mut self.relations,
+                &mut self.transients,
+            )?;
 
             // If there's no secondary index, we panic.  You should not have tried this.
             if !relation.relation_info.secondary_indexed {
                 panic!("Attempted to seek by codomain on a relation with no secondary index");
             }
 
-            db.with_relation(relation_id, |relation| {
+            Self::with_canonical(db, &self.local_canonical, relation_id, |relation| {
                 relation.seek_by_codomain(codomain.clone())
             })?
         };
@@ -203,7 +582,12 @@ impl WorkingSet {
             let _ = self.seek_unique_by_domain(db, relation_id, tuple.domain());
         }
 
-        let relation = Self::get_relation_mut(relation_id, &self.schema, &mut self.relations);
+        let relation = Self::get_relation_mut(
+            relation_id,
+            &self.schema,
+            &mut self.relations,
+            &mut self.transients,
+        )?;
 
         let codomain_tuples = relation.codomain_index.as_ref().unwrap().seek(&codomain)?;
         let tuples = codomain_tuples.filter_map(|tid| {
@@ -218,6 +602,53 @@ impl WorkingSet {
         Ok(tuples.collect())
     }
 
+    /// Seek for all tuples whose attributes in the given attribute index match `key`. Matches in
+    /// the canonical relation are pulled into the working set, which then has (through its own
+    /// index) everything that matches as of this transaction.
+    pub(crate) fn seek_by_attributes(
+        &mut self,
+        db: &Arc<RelBox>,
+        relation_id: RelationId,
+        index: usize,
+        key: SliceRef,
+    ) -> Result<HashSet<TupleRef>, RelationError> {
+        // (Checked before going to the canonical relation, which may not exist.)
+        self.relation_info(relation_id)?;
+        if let Some(read_set) = &mut self.read_set {
+            // There's nothing finer-grained to validate the read against.
+            read_set.observe(relation_id, || {
+                Self::canonical_version(db, &self.local_canonical, relation_id)
+            });
+            read_set.read_scan(relation_id);
+        }
+        let tuples = Self::with_canonical(db, &self.local_canonical, relation_id, |relation| {
+            relation.seek_by_attributes(index, key.clone())
+        })?;
+        let domains: HashSet<_> = tuples.iter().map(|t| t.domain()).collect();
+        for domain in domains {
+            self.seek_by_domain(db, relation_id, domain)?;
+        }
+
+        let relation = Self::get_relation_mut(
+            relation_id,
+            &self.schema,
+            &mut self.relations,
+            &mut self.transients,
+        )?;
+        let Some(attribute_index) = relation.attribute_indexes.get(index) else {
+            return Err(RelationError::NoSecondaryIndex);
+        };
+        let tuples = attribute_index.seek(&key)?.filter_map(|tid| {
+            match &relation.tx_tuple_events.get(&tid).unwrap().op {
+                TxTupleOp::Insert(t)
+                | TxTupleOp::Update { to_tuple: t, .. }
+                | TxTupleOp::Value(t) => Some(t.clone()),
+                TxTupleOp::Tombstone { .. } => None,
+            }
+        });
+        Ok(tuples.collect())
+    }
+
     pub(crate) fn insert_tuple(
         &mut self,
         db: &Arc<RelBox>,
@@ -225,15 +656,47 @@ impl WorkingSet {
         domain: SliceRef,
         codomain: SliceRef,
     ) -> Result<(), RelationError> {
-        let relation = Self::get_relation_mut(relation_id, &self.schema, &mut self.relations);
+        let relation = Self::get_relation_mut(
+            relation_id,
+            &self.schema,
+            &mut self.relations,
+            &mut self.transients,
+        )?;
+        relation.relation_info.check_types(&domain, &codomain)?;
 
         // Enforce unique domain constraint before doing anything else
         relation.domain_index.check_constraints(&domain)?;
-        db.with_relation(relation_id, |relation| {
+        Self::with_canonical(db, &self.local_canonical, relation_id, |relation| {
             relation.check_domain_constraints(&domain)?;
             Ok(())
         })?;
 
+        // Without one, a domain can map to many codomains, but each pairing only once.
+        if !relation.relation_info.unique_domain {
+            let local_ids: Vec<_> = relation.domain_index.seek(&domain)?.collect();
+            for tid in local_ids {
+                match &relation.tx_tuple_events.get(&tid).unwrap().op {
+                    TxTupleOp::Insert(t)
+                    | TxTupleOp::Update { to_tuple: t, .. }
+                    | TxTupleOp::Value(t)
+                        if t.codomain() == codomain =>
+                    {
+                        return Err(RelationError::UniqueConstraintViolation);
+                    }
+                    _ => continue,
+                }
+            }
+            let canon_tuples =
+                Self::with_canonical(db, &self.local_canonical, relation_id, |relation| {
+                    relation.seek_by_domain(domain.clone())
+                })?;
+            for t in canon_tuples {
+                if t.codomain() == codomain && !relation.has_local_version(&t)? {
+                    return Err(RelationError::UniqueConstraintViolation);
+                }
+            }
+        }
+
         let new_t = TupleRef::allocate(
             relation_id,
             self.tuplebox.clone(),
@@ -259,9 +722,18 @@ impl WorkingSet {
         relation_id: RelationId,
         f: F,
     ) -> Result<Vec<TupleRef>, RelationError> {
+        self.relation_info(relation_id)?;
+        if let Some(read_set) = &mut self.read_set {
+            read_set.observe(relation_id, || {
+                Self::canonical_version(db, &self.local_canonical, relation_id)
+            });
+            read_set.read_scan(relation_id);
+        }
         // First collect all the tuples from the canonical relation that match.
-        let mut tuples: HashMap<TupleId, TupleRef> = db
-            .with_relation(relation_id, |relation| relation.predicate_scan(&f))
+        let mut tuples: HashMap<TupleId, TupleRef> =
+            Self::with_canonical(db, &self.local_canonical, relation_id, |relation| {
+                relation.predicate_scan(&f)
+            })
             .iter()
             .map(|t| (t.id(), t.clone()))
             .collect();
@@ -276,7 +748,12 @@ impl WorkingSet {
         // Now pull in the local working set and apply overtop.
         // Apply any changes to the tuples we've already collected, and add in any inserts, and
         // remove anything tombstoned.
-        let relation = Self::get_relation_mut(relation_id, &self.schema, &mut self.relations);
+        let relation = Self::get_relation_mut(
+            relation_id,
+            &self.schema,
+            &mut self.relations,
+            &mut self.transients,
+        )?;
         for (tr, t) in &relation.tx_tuple_events {
             if t.op.ts() > self.ts {
                 // Not visible to us.  Prune it out.
@@ -320,6 +797,74 @@ impl WorkingSet {
         Ok(tuples.values().cloned().collect())
     }
 
+    /// A lazy iterator over all tuples in the relation, with the local working set applied over
+    /// top of a snapshot of the canonical relation. Canonical tuples are only produced as the
+    /// iterator is consumed, so stopping early avoids materializing the whole relation.
+    pub(crate) fn scan_iter(
+        &mut self,
+        db: &Arc<RelBox>,
+        relation_id: RelationId,
+    ) -> Result<TupleIter, RelationError> {
+        self.relation_info(relation_id)?;
+        if let Some(read_set) = &mut self.read_set {
+            read_set.observe(relation_id, || {
+                Self::canonical_version(db, &self.local_canonical, relation_id)
+            });
+            read_set.read_scan(relation_id);
+        }
+        let canonical = Self::with_canonical(db, &self.local_canonical, relation_id, |relation| {
+            relation.scan_iter()
+        });
+        let ts = self.ts;
+        let relation = Self::get_relation_mut(
+            relation_id,
+            &self.schema,
+            &mut self.relations,
+            &mut self.transients,
+        )?;
+        Ok(TupleIter::new(
+            canonical,
+            relation.tx_tuple_events.iter(),
+            relation.relation_info.unique_domain,
+            ts,
+        ))
+    }
+
+    /// As `scan_iter`, but only over the tuples matching the given domain.
+    pub(crate) fn seek_iter(
+        &mut self,
+        db: &Arc<RelBox>,
+        relation_id: RelationId,
+        domain: SliceRef,
+    ) -> Result<TupleIter, RelationError> {
+        self.relation_info(relation_id)?;
+        if self.read_set.is_some() {
+            // A serializable transaction has to hold on to what it read so that removals can be
+            // detected at commit, which means materializing the matches into the working set.
+            self.seek_by_domain(db, relation_id, domain.clone())?;
+        }
+        let canonical = Self::with_canonical(db, &self.local_canonical, relation_id, |relation| {
+            relation.seek_iter(&domain)
+        })?;
+        let ts = self.ts;
+        let relation = Self::get_relation_mut(
+            relation_id,
+            &self.schema,
+            &mut self.relations,
+            &mut self.transients,
+        )?;
+        let local_ids: Vec<_> = relation.domain_index.seek(&domain)?.collect();
+        let events = local_ids
+            .iter()
+            .map(|tid| (tid, relation.tx_tuple_events.get(tid).unwrap()));
+        Ok(TupleIter::new(
+            canonical,
+            events,
+            relation.relation_info.unique_domain,
+            ts,
+        ))
+    }
+
     pub(crate) fn update_by_domain(
         &mut self,
         db: &Arc<RelBox>,
@@ -327,7 +872,13 @@ impl WorkingSet {
         domain: SliceRef,
         codomain: SliceRef,
     ) -> Result<(), RelationError> {
-        let relation = Self::get_relation_mut(relation_id, &self.schema, &mut self.relations);
+        let relation = Self::get_relation_mut(
+            relation_id,
+            &self.schema,
+            &mut self.relations,
+            &mut self.transients,
+        )?;
+        relation.relation_info.check_types(&domain, &codomain)?;
 
         // If we have existing copies, we will update each, but keep their existing derivation
         // timestamps and operation types.
@@ -371,11 +922,12 @@ impl WorkingSet {
         // Check canonical for existing values.  And get timestamps for each...
         // We will use the ts on that to determine the derivation timestamp for our own version.
         // If there's nothing there or its tombstoned, that's NotFound, and die.
-        let canon_tuples = db.with_relation(relation_id, |relation| {
-            let tuples = relation.seek_by_domain(domain.clone())?;
+        let canon_tuples =
+            Self::with_canonical(db, &self.local_canonical, relation_id, |relation| {
+                let tuples = relation.seek_by_domain(domain.clone())?;
 
-            Ok(tuples)
-        })?;
+                Ok(tuples)
+            })?;
         if unique_constraint && canon_tuples.len() > 1 {
             error!("Ambiguous tuple in base");
             return Err(RelationError::AmbiguousTuple);
@@ -428,7 +980,13 @@ impl WorkingSet {
         domain: SliceRef,
         codomain: SliceRef,
     ) -> Result<(), RelationError> {
-        let relation = Self::get_relation_mut(relation_id, &self.schema, &mut self.relations);
+        let relation = Self::get_relation_mut(
+            relation_id,
+            &self.schema,
+            &mut self.relations,
+            &mut self.transients,
+        )?;
+        relation.relation_info.check_types(&domain, &codomain)?;
 
         // If we have an existing copy, we will update it, but keep its existing derivation
         // timestamp.
@@ -457,7 +1015,7 @@ impl WorkingSet {
         }
 
         // Nothing, local, do canonical...
-        let apply = db.with_relation(relation_id, |relation| {
+        let apply = Self::with_canonical(db, &self.local_canonical, relation_id, |relation| {
             let old_tuples = relation.seek_by_domain(domain.clone())?;
             // If there's more than one value for this domain, this operation makes no sense, so raise an
             // ambig error.
@@ -516,6 +1074,68 @@ impl WorkingSet {
         Ok(())
     }
 
+    /// Merge `delta` into the codomain of the tuple with the given domain (inserting one, if
+    /// there isn't one yet) with the relation's merge operator. At commit, the deltas merged are
+    /// redone on top of whatever has been committed by then, rather than conflicting with it.
+    pub(crate) fn merge_by_domain(
+        &mut self,
+        db: &Arc<RelBox>,
+        relation_id: RelationId,
+        domain: SliceRef,
+        delta: SliceRef,
+    ) -> Result<(), RelationError> {
+        if relation_id.is_transient_relation() {
+            return Err(RelationError::NoMergeOperator);
+        }
+        let merge_operator = db
+            .merge_operator(relation_id)
+            .ok_or(RelationError::NoMergeOperator)?;
+
+        // The value merged into doesn't count as read, since it's only what the merge is redone on
+        // top of.
+        let read_set = self.read_set.take();
+        let result = self.merge_unread(db, relation_id, domain, delta, merge_operator);
+        self.read_set = read_set;
+        result
+    }
+
+    fn merge_unread(
+        &mut self,
+        db: &Arc<RelBox>,
+        relation_id: RelationId,
+        domain: SliceRef,
+        delta: SliceRef,
+        merge_operator: MergeOperator,
+    ) -> Result<(), RelationError> {
+        let current = self.seek_unique_by_domain(db, relation_id, domain.clone());
+        let current = match current {
+            Ok(tuple) => Some(tuple.codomain()),
+            Err(RelationError::TupleNotFound) => None,
+            Err(e) => return Err(e),
+        };
+        let merged = merge_operator.merge(current.as_ref(), &delta)?;
+
+        // Writing the merged value supersedes the deltas merged before, so they're put back after.
+        let relation = Self::get_relation_mut(
+            relation_id,
+            &self.schema,
+            &mut self.relations,
+            &mut self.transients,
+        )?;
+        let mut deltas = relation.merges.remove(&domain).unwrap_or_default();
+        deltas.push(delta);
+        self.upsert_by_domain(db, relation_id, domain.clone(), merged)?;
+        let relation = Self::get_relation_mut(
+            relation_id,
+            &self.schema,
+            &mut self.relations,
+            &mut self.transients,
+        )?;
+        relation.merges.insert(domain, deltas);
+        relation.merge_operator = Some(merge_operator);
+        Ok(())
+    }
+
     /// Attempt to delete tuples in the transaction's working set, with the intent of eventually
     /// committing the delete to the canonical base relation.
     pub(crate) fn remove_by_domain(
@@ -524,7 +1144,12 @@ impl WorkingSet {
         relation_id: RelationId,
         domain: SliceRef,
     ) -> Result<(), RelationError> {
-        let relation = Self::get_relation_mut(relation_id, &self.schema, &mut self.relations);
+        let relation = Self::get_relation_mut(
+            relation_id,
+            &self.schema,
+            &mut self.relations,
+            &mut self.transients,
+        )?;
 
         let mut found = false;
         // Delete is basically an update but where we stick a Tombstone in there.
@@ -550,19 +1175,20 @@ impl WorkingSet {
             return Ok(());
         }
 
-        let old_tuples = db.with_relation(relation_id, |relation| {
-            let tuples = relation.seek_by_domain(domain.clone())?;
+        let old_tuples =
+            Self::with_canonical(db, &self.local_canonical, relation_id, |relation| {
+                let tuples = relation.seek_by_domain(domain.clone())?;
 
-            if relation.info.unique_domain {
-                if tuples.is_empty() {
-                    return Err(RelationError::TupleNotFound);
-                }
-                if tuples.len() > 1 {
-                    return Err(RelationError::AmbiguousTuple);
+                if relation.info.unique_domain {
+                    if tuples.is_empty() {
+                        return Err(RelationError::TupleNotFound);
+                    }
+                    if tuples.len() > 1 {
+                        return Err(RelationError::AmbiguousTuple);
+                    }
                 }
-            }
-            Ok(tuples)
-        })?;
+                Ok(tuples)
+            })?;
 
         for old_tuple in old_tuples {
             let apply = TupleApply {
@@ -576,6 +1202,138 @@ impl WorkingSet {
         }
         Ok(())
     }
+
+    /// Attempt to delete the one tuple pairing `domain` with `codomain`, leaving any others for the
+    /// same domain in place.
+    pub(crate) fn remove_tuple(
+        &mut self,
+        db: &Arc<RelBox>,
+        relation_id: RelationId,
+        domain: SliceRef,
+        codomain: SliceRef,
+    ) -> Result<(), RelationError> {
+        let relation = Self::get_relation_mut(
+            relation_id,
+            &self.schema,
+            &mut self.relations,
+            &mut self.transients,
+        )?;
+
+        // A local version of the tuple takes precedence over canonical.
+        let local_ids: Vec<_> = relation.domain_index.seek(&domain)?.collect();
+        let mut local_apply = None;
+        for tid in local_ids {
+            let tuple_op = relation
+                .tx_tuple_events
+                .get(&tid)
+                .expect("Tuple op not found for indexed tuple");
+            match &tuple_op.op {
+                TxTupleOp::Insert(t)
+                | TxTupleOp::Update { to_tuple: t, .. }
+                | TxTupleOp::Value(t)
+                    if t.codomain() == codomain =>
+                {
+                    local_apply = tuple_op.transform_to_remove()?;
+                    break;
+                }
+                _ => continue,
+            }
+        }
+        if let Some(apply) = local_apply {
+            relation.tuple_apply(apply)?;
+            return Ok(());
+        }
+
+        let old_tuples =
+            Self::with_canonical(db, &self.local_canonical, relation_id, |relation| {
+                relation.seek_by_domain(domain.clone())
+            })?;
+        for old_tuple in old_tuples {
+            if old_tuple.codomain() != codomain || relation.has_local_version(&old_tuple)? {
+                continue;
+            }
+            let apply = TupleApply {
+                data_source: DataSource::Base,
+                op_source: OpSource::Remove,
+                replacement_op: Some(TxTupleOp::Tombstone(old_tuple.clone(), self.ts)),
+                add_tuple: Some(old_tuple),
+                del_tuple: None,
+            };
+            relation.tuple_apply(apply)?;
+            return Ok(());
+        }
+        Err(RelationError::TupleNotFound)
+    }
+}
+
+/// Lazily merges a canonical relation snapshot with the (already materialized, and usually small)
+/// local changes from the working set: canonical tuples which have been updated, removed, or
+/// replaced locally are skipped, and the local inserts and updates follow the canonical tuples.
+pub(crate) struct TupleIter {
+    canonical: Box<dyn Iterator<Item = TupleRef>>,
+    shadowed: HashSet<TupleId>,
+    shadowed_domains: HashSet<SliceRef>,
+    local: std::vec::IntoIter<TupleRef>,
+}
+
+impl TupleIter {
+    fn new<'a>(
+        canonical: Box<dyn Iterator<Item = TupleRef>>,
+        events: impl Iterator<Item = (&'a TupleId, &'a TxTupleEvent)>,
+        unique_domain: bool,
+        ts: u64,
+    ) -> Self {
+        let mut shadowed = HashSet::new();
+        let mut shadowed_domains = HashSet::new();
+        let mut local = vec![];
+        for (tid, event) in events {
+            if event.op.ts() > ts {
+                // Not visible to us.
+                shadowed.insert(*tid);
+                continue;
+            }
+            match &event.op {
+                TxTupleOp::Insert(t) => {
+                    // An insert into a unique domain replaces whatever canonical holds for it.
+                    if unique_domain {
+                        shadowed_domains.insert(t.domain());
+                    }
+                    local.push(t.clone());
+                }
+                TxTupleOp::Update {
+                    from_tuple,
+                    to_tuple,
+                } => {
+                    shadowed.insert(from_tuple.id());
+                    local.push(to_tuple.clone());
+                }
+                TxTupleOp::Tombstone(t, _) => {
+                    shadowed.insert(t.id());
+                }
+                TxTupleOp::Value(_) => continue,
+            }
+        }
+        Self {
+            canonical,
+            shadowed,
+            shadowed_domains,
+            local: local.into_iter(),
+        }
+    }
+}
+
+impl Iterator for TupleIter {
+    type Item = TupleRef;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for t in self.canonical.by_ref() {
+            if self.shadowed.contains(&t.id()) || self.shadowed_domains.contains(&t.domain()) {
+                continue;
+            }
+            return Some(t);
+        }
+        self.local.next()
+    }
 }
 
 /// The transaction-local storage for tuples in relations, originally derived from base relations.
@@ -584,13 +1342,34 @@ pub(crate) struct TxBaseRelation {
     pub relation_info: RelationInfo,
 
     tx_tuple_events: HashMap<TupleId, TxTupleEvent>,
-    domain_index: Box<dyn Index>,
-    codomain_index: Option<Box<dyn Index>>,
+    domain_index: Box<dyn Index + Send + Sync>,
+    codomain_index: Option<Box<dyn Index + Send + Sync>>,
+    /// One per declared attribute index, over the tuples held here.
+    attribute_indexes: Vec<Box<dyn Index + Send + Sync>>,
 
-    unsync: PhantomUnsync,
+    /// The deltas merged into each domain, in order, to be redone at commit. Any other write to a
+    /// domain supersedes the merges into it.
+    pub(crate) merges: HashMap<SliceRef, Vec<SliceRef>>,
+    /// The merge operator they were merged with.
+    pub(crate) merge_operator: Option<MergeOperator>,
 }
 
 impl TxBaseRelation {
+    fn new(relation_id: RelationId, relation_info: RelationInfo) -> Self {
+        let (domain_index, codomain_index) = pick_tx_index(&relation_info);
+        let attribute_indexes = pick_tx_attribute_indexes(&relation_info);
+        Self {
+            id: relation_id,
+            relation_info,
+            tx_tuple_events: HashMap::new(),
+            domain_index,
+            codomain_index,
+            attribute_indexes,
+            merges: HashMap::new(),
+            merge_operator: None,
+        }
+    }
+
     pub fn tuples(&self) -> impl Iterator<Item = &TxTupleEvent> {
         self.tx_tuple_events.values()
     }
@@ -599,12 +1378,79 @@ impl TxBaseRelation {
         self.tx_tuple_events.values_mut()
     }
 
+    /// Make the write to `domain` `op` instead, as it was finally committed. (Merges are redone on
+    /// top of what was committed since, so what's committed isn't what was written here.)
+    pub(crate) fn set_committed_write(&mut self, domain: &SliceRef, op: TxTupleOp) {
+        let write = self
+            .tx_tuple_events
+            .values_mut()
+            .find(|event| match &event.op {
+                TxTupleOp::Insert(tuple)
+                | TxTupleOp::Update {
+                    to_tuple: tuple, ..
+                } => tuple.domain() == *domain,
+                TxTupleOp::Value(_) | TxTupleOp::Tombstone(..) => false,
+            });
+        write
+            .expect("Merges are written along with the deltas merged")
+            .op = op;
+    }
+
+    /// A copy of this relation's local state, including its indexes.
+    fn fork(&self) -> Self {
+        Self {
+            id: self.id,
+            relation_info: self.relation_info.clone(),
+            tx_tuple_events: self.tx_tuple_events.clone(),
+            domain_index: self.domain_index.clone_index(),
+            codomain_index: self
+                .codomain_index
+                .as_ref()
+                .map(|index| index.clone_index()),
+            attribute_indexes: self
+                .attribute_indexes
+                .iter()
+                .map(|index| index.clone_index())
+                .collect(),
+            merges: self.merges.clone(),
+            merge_operator: self.merge_operator.clone(),
+        }
+    }
+
     pub(crate) fn clear(&mut self) {
         self.tx_tuple_events.clear();
+        self.merges.clear();
         self.domain_index.clear();
         if let Some(index) = &mut self.codomain_index {
             index.clear();
         }
+        for index in &mut self.attribute_indexes {
+            index.clear();
+        }
+    }
+
+    /// The keys of `tuple` in each of the attribute indexes.
+    fn attribute_keys(&self, tuple: &TupleRef) -> Result<Vec<SliceRef>, RelationError> {
+        (0..self.attribute_indexes.len())
+            .map(|index| self.relation_info.attribute_index_key(index, tuple))
+            .collect()
+    }
+
+    /// Check whether the working set already holds a version (seen value, update, or tombstone) of
+    /// the given canonical tuple, in which case the local version takes precedence over it.
+    fn has_local_version(&self, tuple: &TupleRef) -> Result<bool, RelationError> {
+        let mut local = self.domain_index.seek(&tuple.domain())?;
+        // With a unique domain, anything we hold locally for the domain supersedes canonical.
+        if self.relation_info.unique_domain {
+            return Ok(local.next().is_some());
+        }
+        Ok(
+            local.any(|tid| match &self.tx_tuple_events.get(&tid).unwrap().op {
+                TxTupleOp::Value(t) | TxTupleOp::Tombstone(t, _) => t.id() == tuple.id(),
+                TxTupleOp::Update { from_tuple, .. } => from_tuple.id() == tuple.id(),
+                TxTupleOp::Insert(_) => false,
+            }),
+        )
     }
 
     // Check for dupes for the given domain value.
@@ -619,6 +1465,17 @@ impl TxBaseRelation {
     /// This is done to have a consistent process for applying updates to the working set, to avoid having to
     /// do the index updates in multiple places.
     fn tuple_apply(&mut self, apply: TupleApply) -> Result<Option<TupleId>, RelationError> {
+        // Anything but a seek is a write, which supersedes the merges into the domains it's to.
+        let written: Vec<_> = if apply.op_source == OpSource::Seek {
+            vec![]
+        } else {
+            apply
+                .add_tuple
+                .iter()
+                .chain(&apply.del_tuple)
+                .map(|t| t.domain())
+                .collect()
+        };
         let TupleApply {
             replacement_op,
             add_tuple,
@@ -627,17 +1484,22 @@ impl TxBaseRelation {
         } = apply;
         // Perform deletes as appropriate.
         if let Some(del_tuple) = &del_tuple {
-            if self
+            let unindexed = self
                 .domain_index
                 .unindex_tuple(&del_tuple.domain(), del_tuple.id())
-                .is_ok()
-                && self.tx_tuple_events.remove(&del_tuple.id()).is_none()
-            {
+                .is_ok();
+            if unindexed && self.tx_tuple_events.remove(&del_tuple.id()).is_none() {
                 warn!("Tried to remove a tuple that wasn't there");
             }
             if let Some(index) = &mut self.codomain_index {
                 index.unindex_tuple(&del_tuple.codomain(), del_tuple.id())?;
             }
+            if unindexed {
+                let keys = self.attribute_keys(del_tuple)?;
+                for (index, key) in self.attribute_indexes.iter_mut().zip(keys) {
+                    index.unindex_tuple(&key, del_tuple.id())?;
+                }
+            }
         }
         // Then add the new tuple, if there is one.
         if let Some(add_tuple) = add_tuple {
@@ -650,6 +1512,9 @@ impl TxBaseRelation {
             if self.has_tuple(&add_tuple) {
                 return Err(RelationError::UniqueConstraintViolation);
             }
+            // (Anything that can't be keyed for the attribute indexes is turned away here, rather
+            // than at commit.)
+            let keys = self.attribute_keys(&add_tuple)?;
 
             // Insert the new tuple & operation into the indexes and tuple op map.
             self.domain_index
@@ -657,6 +1522,9 @@ impl TxBaseRelation {
             if let Some(index) = &mut self.codomain_index {
                 index.index_tuple(&add_tuple.codomain(), add_tuple.id())?;
             }
+            for (index, key) in self.attribute_indexes.iter_mut().zip(keys) {
+                index.index_tuple(&key, add_tuple.id())?;
+            }
 
             // Shove in the operation at the new locale.
             if let Some(replacement_op) = replacement_op {
@@ -670,6 +1538,9 @@ impl TxBaseRelation {
                 );
             }
         }
+        for domain in written {
+            self.merges.remove(&domain);
+        }
         Ok(del_tuple.map(|t| t.id()))
     }
 }
@@ -0,0 +1,80 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use daumtils::SliceRef;
+use relbox::codec::Codec;
+use relbox::{AttrType, IndexType, RelBox, RelationId, Relations};
+
+#[derive(Relations)]
+enum WorldState {
+    #[relation(domain = Integer, codomain = String, index = AdaptiveRadixTree)]
+    ObjectName,
+    #[relation(name = "parents", domain = Integer, codomain = Integer)]
+    #[relation(secondary_index = Hash, validate_types)]
+    ObjectParent,
+    #[relation(domain = Integer, codomain = Bytes, unique_domain = false)]
+    ObjectVerbs,
+}
+
+#[test]
+fn derived_schema() {
+    let relations = WorldState::relations();
+    assert_eq!(relations.len(), 3);
+    assert_eq!(relations[0].name, "ObjectName");
+    assert_eq!(relations[0].index_type, IndexType::AdaptiveRadixTree);
+    assert_eq!(relations[0].codomain_type, AttrType::String);
+    assert!(!relations[0].secondary_indexed);
+    assert_eq!(relations[1].name, "parents");
+    assert_eq!(relations[1].codomain_index_type, Some(IndexType::Hash));
+    assert!(relations[1].validate_types);
+    assert!(!relations[2].unique_domain);
+    assert_eq!(WorldState::ObjectVerbs.relation_id(), RelationId(2));
+}
+
+#[test]
+fn derived_accessors() {
+    let db = RelBox::new(1 << 24, None, &WorldState::relations(), 0);
+    let tx = db.clone().start_tx();
+    tx.object_name()
+        .insert_tuple(1i64.encode(), SliceRef::from_bytes(b"root"))
+        .unwrap();
+    tx.object_parent()
+        .insert_tuple(2i64.encode(), 1i64.encode())
+        .unwrap();
+    for verb in [&b"look"[..], b"get"] {
+        tx.object_verbs()
+            .insert_tuple(2i64.encode(), SliceRef::from_bytes(verb))
+            .unwrap();
+    }
+    tx.commit().unwrap();
+
+    let tx = db.clone().start_tx();
+    let children = tx.object_parent().seek_by_codomain(1i64.encode()).unwrap();
+    assert_eq!(children.len(), 1);
+    assert_eq!(
+        tx.object_verbs()
+            .seek_by_domain(2i64.encode())
+            .unwrap()
+            .len(),
+        2
+    );
+    assert_eq!(
+        tx.relation(WorldState::ObjectName.relation_id())
+            .seek_unique_by_domain(1i64.encode())
+            .unwrap()
+            .codomain()
+            .as_slice(),
+        b"root"
+    );
+}
@@ -22,6 +22,7 @@ mod tests {
     use std::sync::Arc;
     use tracing_test::traced_test;
 
+    use relbox::codec::Codec;
     use relbox::RelBox;
     use relbox::{RelationId, Transaction};
 
@@ -31,12 +32,10 @@ mod tests {
     use super::*;
 
     fn from_val(value: i64) -> SliceRef {
-        SliceRef::from_bytes(&value.to_le_bytes()[..])
+        value.encode()
     }
     fn to_val(value: SliceRef) -> i64 {
-        let mut bytes = [0; 8];
-        bytes.copy_from_slice(value.as_slice());
-        i64::from_le_bytes(bytes)
+        i64::decode(&value).unwrap()
     }
 
     fn check_expected(
@@ -17,25 +17,26 @@ mod support;
 
 #[cfg(test)]
 mod test {
+    use daumtils::SliceRef;
     use std::collections::HashMap;
+    use std::ops::Bound;
     use std::path::PathBuf;
     use std::rc::Rc;
     use std::sync::Arc;
-    use daumtils::SliceRef;
     use tracing_test::traced_test;
 
     use crate::support::{History, Type, Value};
+    use relbox::codec::Codec;
     use relbox::index::{AttrType, IndexType};
-    use relbox::{RelBox, RelationInfo};
-    use relbox::{RelationId, Transaction};
+    use relbox::{MergeOperator, Migration, RelBox, RelationInfo, SchemaError, SequenceKind};
+    use relbox::{RelationError, RelationId, Transaction};
 
+    // The relations are radix-tree indexed, which wants keys in their `codec` encoding.
     fn from_val(value: i64) -> SliceRef {
-        SliceRef::from_bytes(&value.to_le_bytes()[..])
+        value.encode()
     }
     fn to_val(value: SliceRef) -> i64 {
-        let mut bytes = [0; 8];
-        bytes.copy_from_slice(value.as_slice());
-        i64::from_le_bytes(bytes)
+        i64::decode(&value).unwrap()
     }
 
     fn fill_db(
@@ -93,12 +94,26 @@ mod test {
                 unique_domain: true,
                 index_type: IndexType::AdaptiveRadixTree,
                 codomain_index_type: None,
+                ..Default::default()
             })
             .collect::<Vec<_>>();
 
         RelBox::new(1 << 24, Some(dir), &relations, 0)
     }
 
+    fn relation_info(name: &str) -> RelationInfo {
+        RelationInfo {
+            name: name.to_string(),
+            domain_type: AttrType::Integer,
+            codomain_type: AttrType::Integer,
+            secondary_indexed: false,
+            unique_domain: true,
+            index_type: IndexType::AdaptiveRadixTree,
+            codomain_index_type: None,
+            ..Default::default()
+        }
+    }
+
     // Open a db in a test dir, fill it with some goop, close it, reopen it, and check that the goop is still there.
     #[test]
     #[traced_test]
@@ -166,4 +181,294 @@ mod test {
             db.shutdown();
         }
     }
+
+    // Create and drop relations at runtime, close the db, reopen it with the original schema, and
+    // check that the changes are still in effect.
+    #[test]
+    #[traced_test]
+    fn reopen_after_schema_changes() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let declared = [relation_info("first"), relation_info("second")];
+        {
+            let db = RelBox::new(1 << 24, Some(tmpdir.path().into()), &declared, 0);
+            let tx = db.clone().start_tx();
+            tx.relation(RelationId(0))
+                .insert_tuple(from_val(1), from_val(1))
+                .unwrap();
+            tx.relation(RelationId(1))
+                .insert_tuple(from_val(2), from_val(2))
+                .unwrap();
+            tx.commit().unwrap();
+
+            let tx = db.clone().start_tx();
+            let created = tx.create_relation(relation_info("created")).unwrap();
+            assert_eq!(created, RelationId(2));
+            tx.relation(created)
+                .insert_tuple(from_val(3), from_val(3))
+                .unwrap();
+            tx.drop_relation(RelationId(1)).unwrap();
+            tx.commit().unwrap();
+            db.shutdown();
+        }
+
+        for _ in 0..3 {
+            let db = RelBox::new(1 << 24, Some(tmpdir.path().into()), &declared, 0);
+            let schema = db.schema();
+            assert_eq!(schema.len(), 3);
+            assert!(schema[1].is_none());
+            assert_eq!(schema[2].as_ref().unwrap().name, "created");
+
+            db.with_relation(RelationId(0), |r| {
+                assert_eq!(r.seek_by_domain(from_val(1)).unwrap().len(), 1);
+            });
+            db.with_relation(RelationId(1), |r| {
+                assert!(r.predicate_scan(&|_| true).is_empty());
+            });
+            db.with_relation(RelationId(2), |r| {
+                assert_eq!(r.seek_by_domain(from_val(3)).unwrap().len(), 1);
+            });
+            db.shutdown();
+        }
+    }
+
+    // Commits can be waited on until they're durable, and are there when the db is reopened.
+    #[test]
+    #[traced_test]
+    fn durable_commits() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let declared = [relation_info("first")];
+        {
+            let db = RelBox::new(1 << 24, Some(tmpdir.path().into()), &declared, 0);
+            let tx = db.clone().start_tx();
+            tx.relation(RelationId(0))
+                .insert_tuple(from_val(1), from_val(1))
+                .unwrap();
+            let first = tx.commit_with_durability().unwrap();
+
+            let tx = db.clone().start_tx();
+            tx.relation(RelationId(0))
+                .insert_tuple(from_val(2), from_val(2))
+                .unwrap();
+            tx.commit_durable().unwrap();
+
+            // Either order of waiting works, and later commits have later timestamps.
+            let tx = db.clone().start_tx();
+            tx.relation(RelationId(0))
+                .update_by_domain(from_val(1), from_val(3))
+                .unwrap();
+            let third = tx.commit_with_durability().unwrap();
+            assert!(third.ts() > first.ts());
+            third.wait().unwrap();
+            first.wait().unwrap();
+            db.shutdown();
+        }
+
+        let db = RelBox::new(1 << 24, Some(tmpdir.path().into()), &declared, 0);
+        db.with_relation(RelationId(0), |r| {
+            let updated = r.seek_by_domain(from_val(1)).unwrap();
+            let codomains: Vec<_> = updated.iter().map(|t| to_val(t.codomain())).collect();
+            assert_eq!(codomains, vec![3]);
+            assert_eq!(r.seek_by_domain(from_val(2)).unwrap().len(), 1);
+        });
+        db.shutdown();
+    }
+
+    // Concurrent merges are persisted as they were redone at commit, on top of each other, rather
+    // than as each transaction merged locally.
+    #[test]
+    #[traced_test]
+    fn reopen_after_concurrent_merges() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let declared = [relation_info("counters")];
+        {
+            let db = RelBox::new(1 << 24, Some(tmpdir.path().into()), &declared, 0);
+            db.set_merge_operator(RelationId(0), MergeOperator::AddInteger)
+                .unwrap();
+            let tx = db.clone().start_tx();
+            tx.relation(RelationId(0))
+                .insert_tuple(from_val(1), from_val(10))
+                .unwrap();
+            tx.commit_durable().unwrap();
+
+            // Counter 1 is merged into from what was there; counter 2 from nothing.
+            let first = db.clone().start_tx();
+            let second = db.clone().start_tx();
+            for (tx, delta) in [(&first, 5), (&second, 7)] {
+                let counters = tx.relation(RelationId(0));
+                counters
+                    .merge_by_domain(from_val(1), from_val(delta))
+                    .unwrap();
+                counters
+                    .merge_by_domain(from_val(2), from_val(delta))
+                    .unwrap();
+            }
+            first.commit_durable().unwrap();
+            second.commit_durable().unwrap();
+            db.shutdown();
+        }
+
+        let db = RelBox::new(1 << 24, Some(tmpdir.path().into()), &declared, 0);
+        db.with_relation(RelationId(0), |r| {
+            for (counter, sum) in [(1, 22), (2, 12)] {
+                let counters: Vec<_> = r
+                    .seek_by_domain(from_val(counter))
+                    .unwrap()
+                    .iter()
+                    .map(|t| to_val(t.codomain()))
+                    .collect();
+                assert_eq!(counters, vec![sum]);
+            }
+        });
+        db.shutdown();
+    }
+
+    // Sequences, declared and created at runtime, keep their values (and names) across a reopen,
+    // without waiting on a commit to be written out.
+    #[test]
+    #[traced_test]
+    fn reopen_with_sequences() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let declared = [relation_info("first")];
+        {
+            let db = RelBox::new(1 << 24, Some(tmpdir.path().into()), &declared, 1);
+            assert_eq!(db.clone().increment_sequence(0), Ok(-1));
+            let ids = db
+                .create_sequence("ids", SequenceKind::Transactional)
+                .unwrap();
+            let tickets = db
+                .create_sequence("tickets", SequenceKind::Immediate)
+                .unwrap();
+
+            let tx = db.clone().start_tx();
+            tx.reserve_sequence(ids, 5).unwrap();
+            tx.commit_durable().unwrap();
+            let tx = db.clone().start_tx();
+            tx.increment_sequence(ids).unwrap();
+            tx.rollback().unwrap();
+
+            assert_eq!(db.clone().reserve_sequence(tickets, 100), Ok(-1..99));
+            db.shutdown();
+        }
+
+        let db = RelBox::new(1 << 24, Some(tmpdir.path().into()), &declared, 1);
+        assert_eq!(db.sequence_id("ids"), Some(1));
+        assert_eq!(db.sequence_id("tickets"), Some(2));
+        assert_eq!(db.clone().sequence_current(0), Ok(0));
+        assert_eq!(db.clone().sequence_current(1), Ok(4));
+        assert_eq!(db.clone().sequence_current(2), Ok(99));
+        let tx = db.clone().start_tx();
+        tx.increment_sequence(1).unwrap();
+        let other = db.clone().start_tx();
+        other.increment_sequence(1).unwrap();
+        tx.commit().unwrap();
+        assert!(other.commit().is_err());
+        db.shutdown();
+
+        // Declaring more sequences than before would give them the ids of those created since.
+        let result = RelBox::open(1 << 24, Some(tmpdir.path().into()), &declared, 2, &[]);
+        assert_eq!(
+            result.unwrap_err(),
+            SchemaError::SequenceCollision {
+                sequence: 1,
+                name: "ids".to_string(),
+            }
+        );
+    }
+
+    // Reopen a db with its relations declared differently from how they were stored, which is
+    // refused unless a migration is declared for the difference.
+    #[test]
+    #[traced_test]
+    fn reopen_with_changed_schema() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let declared = [relation_info("first"), relation_info("second")];
+        {
+            let db = RelBox::new(1 << 24, Some(tmpdir.path().into()), &declared, 0);
+            let tx = db.clone().start_tx();
+            tx.relation(RelationId(0))
+                .insert_tuple(from_val(1), from_val(1))
+                .unwrap();
+            tx.commit().unwrap();
+            db.shutdown();
+        }
+
+        // Reordered relations would otherwise have their tuples loaded into the wrong relation.
+        let reordered = [declared[1].clone(), declared[0].clone()];
+        let result = RelBox::open(1 << 24, Some(tmpdir.path().into()), &reordered, 0, &[]);
+        assert_eq!(
+            result.unwrap_err(),
+            SchemaError::RelationMismatch {
+                relation_id: RelationId(0),
+                declared: reordered[0].clone(),
+                stored: declared[0].clone(),
+            }
+        );
+
+        let mut rehashed = declared.clone();
+        rehashed[0].index_type = IndexType::Hash;
+        let result = RelBox::open(1 << 24, Some(tmpdir.path().into()), &rehashed, 0, &[]);
+        assert!(result.is_err());
+
+        // Once migrated, the new declaration is what's stored, and the migration is a no-op.
+        for migrations in [vec![Migration::Reindex(RelationId(0))], vec![]] {
+            let db = RelBox::open(
+                1 << 24,
+                Some(tmpdir.path().into()),
+                &rehashed,
+                0,
+                &migrations,
+            )
+            .unwrap();
+            db.with_relation(RelationId(0), |r| {
+                assert_eq!(r.seek_by_domain(from_val(1)).unwrap().len(), 1);
+                assert_eq!(
+                    r.seek_range_by_domain(Bound::Unbounded, Bound::Unbounded, false)
+                        .unwrap_err(),
+                    RelationError::UnorderedIndex
+                );
+            });
+            db.shutdown();
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn reopen_with_old_key_format() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let declared = [relation_info("first"), relation_info("second")];
+        {
+            let db = RelBox::new(1 << 24, Some(tmpdir.path().into()), &declared, 0);
+            let tx = db.clone().start_tx();
+            tx.relation(RelationId(1))
+                .insert_tuple(from_val(1), from_val(1))
+                .unwrap();
+            tx.commit().unwrap();
+            db.shutdown();
+        }
+
+        // Catalogs from before radix-tree keys were `codec` encoded record no key format (the
+        // relation count used to be 64 bits wide), and their values would be read as the wrong
+        // keys.
+        let catalog_page = tmpdir.path().join("pages").join("catalog.page");
+        let mut catalog = std::fs::read(&catalog_page).unwrap();
+        catalog[4..8].copy_from_slice(&[0; 4]);
+        std::fs::write(&catalog_page, catalog).unwrap();
+        for migrations in [vec![], vec![Migration::Reindex(RelationId(1))]] {
+            let result = RelBox::open(
+                1 << 24,
+                Some(tmpdir.path().into()),
+                &declared,
+                0,
+                &migrations,
+            );
+            assert_eq!(
+                result.unwrap_err(),
+                SchemaError::KeyFormatChanged {
+                    relation_id: RelationId(1),
+                    stored: 0,
+                    current: 1,
+                }
+            );
+        }
+    }
 }
@@ -30,6 +30,7 @@ pub fn test_db(dir: PathBuf) -> Arc<RelBox> {
             unique_domain: true,
             index_type: IndexType::AdaptiveRadixTree,
             codomain_index_type: None,
+            ..Default::default()
         })
         .collect::<Vec<_>>();
 